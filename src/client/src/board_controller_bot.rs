@@ -9,6 +9,7 @@ use quader_engine::piece_mgr::BoardErrorReason;
 use quader_engine::replays::MoveResult;
 use quader_engine::wall_kick_data::WallKickData;
 use quader_skynet::{BotBoard, BotSettings};
+use quader_skynet::bot_engine::BotEngineKind;
 use crate::assets::Assets;
 use crate::board_renderer::BoardRenderer;
 
@@ -18,10 +19,21 @@ pub struct BoardControllerBot {
 }
 
 impl BoardControllerBot {
-    pub fn new(x: f32, y: f32, game_settings: GameSettings, seed: u64, wkd: Arc<WallKickData>, target_pps: f32) -> Self {
+    /// Falls back to the native bot if the engine of `bot_settings` fails to start.
+    pub fn new(x: f32, y: f32, game_settings: GameSettings, seed: u64, wkd: Arc<WallKickData>, bot_settings: BotSettings) -> Self {
+        let board_renderer = BoardRenderer::new(x, y, game_settings.board.height);
+
+        let bot_board = BotBoard::new(game_settings.clone(), Arc::clone(&wkd), seed, bot_settings.clone())
+            .unwrap_or_else(|err| {
+                println!("Failed to start the bot, using the native one instead: {}", err);
+
+                let bot_settings = BotSettings { engine: BotEngineKind::default(), ..bot_settings };
+                BotBoard::new(game_settings, wkd, seed, bot_settings).expect("The native bot always starts")
+            });
+
         Self {
-            bot_board: Box::new(BotBoard::new(game_settings, wkd, seed, BotSettings { target_pps })),
-            board_renderer: BoardRenderer::new(x, y, game_settings.board.height)
+            bot_board: Box::new(bot_board),
+            board_renderer
         }
     }
 
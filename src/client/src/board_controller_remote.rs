@@ -3,49 +3,223 @@
  * See the LICENSE file in the repository root for full licence text.
  */
 
+use std::collections::HashMap;
+use std::io::ErrorKind;
 use std::net::TcpStream;
+use std::sync::Arc;
+use macroquad::prelude::*;
 
-use std::sync::mpsc;
-use std::thread;
+use quader_engine::board::BoardSimple;
+use quader_engine::board_predictor::BoardPredictor;
+use quader_engine::game_settings::{GameSettings, HandlingSettings};
+use quader_engine::input_handler::InputHandler;
+use quader_engine::piece_mgr::BoardErrorReason;
+use quader_engine::protocol::{ConnId, LobbyEvent, WsAction, WsBoardCommand, WsServerMessage};
+use quader_engine::replays::MoveResult;
+use quader_engine::time_mgr::TimeMgr;
+use quader_engine::wall_kick_data::WallKickData;
 use tungstenite::{connect, stream::MaybeTlsStream, Message, WebSocket};
 use url::Url;
+use crate::assets::Assets;
+use crate::board_renderer::BoardRenderer;
+use crate::input::poll_input;
 
+/// Board of the local player in an online match.
+///
+/// The inputs are applied to a predicted board immediately and sent to the server
+/// with sequence numbers. The boards of every player are mirrored from the deltas sent by the server,
+/// and the predicted board is reconciled with the mirror of the local player's board.
 pub struct BoardControllerRemote {
     socket: WebSocket<MaybeTlsStream<TcpStream>>,
+    is_closed: bool,
+    game_settings: GameSettings,
+    wkd: Arc<WallKickData>,
+    /// Connection id of the local player, known after joining a lobby.
+    conn: Option<ConnId>,
+    predictor: Option<BoardPredictor>,
+    /// Authoritative state of the boards of the match.
+    mirrors: HashMap<ConnId, BoardSimple>,
+    /// Last input acknowledged by the server.
+    last_ack: u64,
+    input_handler: InputHandler,
+    board_renderer: BoardRenderer
 }
 
 impl BoardControllerRemote {
-    pub fn new(connection_uri: &str) -> Self {
+    pub fn new(x: f32, y: f32, connection_uri: &str, game_settings: GameSettings, wkd: Arc<WallKickData>) -> Self {
         let (mut socket, response) =
             connect(Url::parse(connection_uri).unwrap()).expect("Can't connect");
 
         println!("Connected to the server");
         println!("Response HTTP code: {}", response.status());
-        println!("Response contains the following headers:");
 
-        for (ref header, _value) in response.headers() {
-            println!("* {}", header);
+        // the messages are polled every frame
+        if let MaybeTlsStream::Plain(stream) = socket.get_mut() {
+            stream.set_nonblocking(true).expect("Can't make the socket non-blocking");
+        }
+
+        Self {
+            socket,
+            is_closed: false,
+            wkd,
+            conn: None,
+            predictor: None,
+            mirrors: HashMap::new(),
+            last_ack: 0,
+            input_handler: InputHandler::new(HandlingSettings::default(), &game_settings.board),
+            board_renderer: BoardRenderer::new(x, y, game_settings.board.height),
+            game_settings
+        }
+    }
+
+    pub fn send_action(&mut self, action: &WsAction) {
+        let text = serde_json::to_string(action).unwrap();
+        self.socket.send(Message::Text(text)).ok();
+    }
+
+    /// Returns the next message received from the server or `None` if there is none yet.
+    pub fn read_message(&mut self) -> Option<WsServerMessage> {
+        if self.is_closed {
+            return None;
         }
 
-        socket.send(Message::Text("Hello WS!".into())).unwrap();
         loop {
-            let msg = socket.read().expect("Error reading message");
-            println!("Received: {}", msg);
+            match self.socket.read() {
+                Ok(Message::Text(content)) => match WsServerMessage::from_json(&content) {
+                    Ok(msg) => return Some(msg),
+                    Err(err) => println!("Invalid message from the server: {}", err),
+                },
+                Ok(Message::Close(_close_frame)) => {
+                    self.is_closed = true;
+                    return None;
+                }
+                Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_) | Message::Binary(_)) => {}
+                Err(tungstenite::Error::Io(err)) if err.kind() == ErrorKind::WouldBlock => return None,
+                Err(err) => {
+                    println!("Connection error: {}", err);
+                    self.is_closed = true;
+                    return None;
+                }
+            }
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.is_closed
+    }
+
+    pub fn render(&self, assets: &Assets) {
+        if let Some(predictor) = &self.predictor {
+            self.board_renderer.render(assets, predictor.get_board());
+        }
+    }
+
+    pub fn update(&mut self, time_mgr: &TimeMgr) -> Option<Result<MoveResult, BoardErrorReason>> {
+        self.process_messages();
+
+        self.predictor.as_ref()?;
+
+        let mut result = None;
+
+        let time_ms = time_mgr.elapsed_sec as f64 * 1000.0;
+        let actions = poll_input(&mut self.input_handler, time_ms);
+        for cmd in WsBoardCommand::from_actions(&actions) {
+            if let Some(res) = self.input(cmd) {
+                result = Some(res);
+            }
+        }
+
+        if let Some(res) = self.predictor.as_mut()?.update(time_mgr) {
+            // the piece was locked by gravity
+            if res.is_ok() {
+                self.input_handler.piece_spawned(time_ms);
+            }
+            result = Some(res);
+        }
+
+        result
+    }
 
+    /// Applies `cmd` to the predicted board and sends it to the server.
+    fn input(&mut self, cmd: WsBoardCommand) -> Option<Result<MoveResult, BoardErrorReason>> {
+        let (seq, res) = self.predictor.as_mut()?.input(cmd);
+        self.send_action(&WsAction::BoardInput { seq, cmd });
+
+        res
+    }
+
+    fn process_messages(&mut self) {
+        let mut is_own_board_changed = false;
+
+        while let Some(msg) = self.read_message() {
             match msg {
-                Message::Text(content) => {
-                    //serde_json::from_str(&content);
+                WsServerMessage::Lobby(LobbyEvent::Joined { conn, .. }) => {
+                    self.conn = Some(conn);
+                }
+                WsServerMessage::Lobby(LobbyEvent::MatchStarted { seed, players }) => {
+                    self.start(seed, &players);
                 }
-                Message::Close(_close_frame) => {
-                    break;
+                WsServerMessage::BoardSnapshot { conn, seq, snapshot } => {
+                    if let Some(mirror) = self.mirrors.get_mut(&conn) {
+                        mirror.apply_snapshot(seq, &snapshot);
+                        is_own_board_changed |= self.conn == Some(conn);
+                    }
                 }
-                Message::Ping(_) | Message::Pong(_) | Message::Frame(_) | Message::Binary(_) => {}
+                WsServerMessage::BoardDelta { conn, delta } => {
+                    let Some(mirror) = self.mirrors.get_mut(&conn) else {
+                        continue;
+                    };
+
+                    if mirror.apply_delta(&delta).is_err() {
+                        self.send_action(&WsAction::RequestResync(conn));
+                    } else {
+                        is_own_board_changed |= self.conn == Some(conn);
+                    }
+                }
+                WsServerMessage::InputAck { conn, input, seq } if self.conn == Some(conn) => {
+                    self.last_ack = input;
+
+                    match self.mirrors.get(&conn) {
+                        // the delta of the input is missing, the resync is already requested
+                        Some(mirror) if mirror.seq < seq => {}
+                        Some(_) => {
+                            self.reconcile();
+                            is_own_board_changed = false;
+                        }
+                        None => {}
+                    }
+                }
+                WsServerMessage::Lobby(LobbyEvent::MatchEnded { winner }) => {
+                    println!("Match ended, winner: {:?}", winner);
+                }
+                _ => {}
             }
+        }
 
-            break;
+        // changes made by the server itself, for example, by gravity or incoming garbage
+        if is_own_board_changed {
+            self.reconcile();
         }
+    }
+
+    fn start(&mut self, seed: u64, players: &[ConnId]) {
+        self.predictor = Some(BoardPredictor::new(self.game_settings.clone(), Arc::clone(&self.wkd), seed));
+        self.mirrors = players
+            .iter()
+            .map(|conn| (*conn, BoardSimple::new(self.game_settings.clone(), seed)))
+            .collect();
+        self.last_ack = 0;
+        self.input_handler.reset();
+    }
 
-        Self { socket }
+    fn reconcile(&mut self) {
+        let (Some(conn), Some(predictor)) = (self.conn, self.predictor.as_mut()) else {
+            return;
+        };
+
+        if let Some(mirror) = self.mirrors.get(&conn) {
+            predictor.reconcile(self.last_ack, &mirror.snapshot());
+        }
     }
 
     pub fn close(&mut self) {
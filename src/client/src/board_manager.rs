@@ -10,6 +10,7 @@ use quader_engine::game_settings::GameSettings;
 use quader_engine::rng_manager::RngManager;
 use quader_engine::time_mgr::TimeMgr;
 use quader_engine::wall_kick_data::WallKickData;
+use quader_skynet::{BotPreset, BotSettings};
 use crate::assets::Assets;
 use crate::board_controller::BoardController;
 use crate::board_controller_bot::BoardControllerBot;
@@ -27,14 +28,14 @@ impl BoardManager {
 
         let game_settings = GameSettings::default();
         let seed = RngManager::from_entropy().gen();
-        let wkd = Arc::new(WallKickData::new(game_settings.wall_kick_data_mode));
+        let wkd = Arc::new(WallKickData::new(&game_settings.wall_kick_data_mode));
 
         let time_mgr = TimeMgr::new();
 
         let player_board = BoardController::new(
             300., 
             128., 
-            game_settings, 
+            game_settings.clone(), 
             seed, 
             Arc::clone(&wkd)
         );
@@ -42,10 +43,10 @@ impl BoardManager {
         let bot_board = BoardControllerBot::new(
             1200.,
             128.,
-            game_settings,
+            game_settings.clone(),
             seed,
             Arc::clone(&wkd),
-            1.25
+            BotSettings { target_pps: 1.25, ..BotPreset::Medium.settings() }
         );
 
         Self {
@@ -69,22 +69,24 @@ impl BoardRenderer {
                 self.render_piece_ghost(assets, pos.0, pos.1 - self.render_offset, piece.get_type(), 150);
             });
 
-        // render hold piece
+        // render hold piece, dimmed if it can't be swapped now
         if let Some(hold_piece) = board.get_hold_piece() {
             let points = get_points_for_piece(hold_piece, RotationState::Initial);
+            let alpha = if board.get_piece_mgr().is_hold_available() { 255 } else { 100 };
 
             points
                 .iter()
                 //.map(|p| adjust_point_clone(p, Point::new(p.x, p.y)))
                 .for_each(|p| {
                     let pos = self.point_to_coords(&p);
-                    self.render_cell_type(assets, pos.0 - 110., pos.1 + 86., &piece_type_to_cell_type(hold_piece), 255);
+                    self.render_cell_type(assets, pos.0 - 110., pos.1 + 86., &piece_type_to_cell_type(hold_piece), alpha);
                 });
         }
 
-        // render queue
-        let queue = &board.get_piece_mgr().piece_queue.queue;
-        for (y, piece_type) in queue.iter().enumerate() {
+        // render queue, squeezing the pieces if there are more than fit the panel
+        let piece_queue = &board.get_piece_mgr().piece_queue;
+        let spacing = 96. * (5. / piece_queue.get_preview_size() as f32).min(1.);
+        for (y, piece_type) in piece_queue.get_preview().enumerate() {
             let points = get_points_for_piece(*piece_type, RotationState::Initial);
 
             points
@@ -99,7 +101,7 @@ impl BoardRenderer {
                             OffsetType::Cell => pos.0 + 420.,
                             OffsetType::BetweenCells => pos.0 + 420. + 16.
                         },
-                        88. + pos.1 + 96. * y as f32
+                        88. + pos.1 + spacing * y as f32
                     );
 
                     self.render_cell_type(assets, pos.0, pos.1, &piece_type_to_cell_type(*piece_type), 255)
@@ -0,0 +1,39 @@
+/*
+ * Copyright (c) Grigory Alfyorov. Licensed under the MIT License.
+ * See the LICENSE file in the repository root for full licence text.
+ */
+
+use macroquad::prelude::*;
+
+use quader_engine::input_handler::{InputHandler, InputKey};
+use quader_engine::replays::MoveAction;
+
+const KEY_BINDINGS: [(KeyCode, InputKey); 8] = [
+    (KeyCode::Left, InputKey::Left),
+    (KeyCode::Right, InputKey::Right),
+    (KeyCode::Down, InputKey::SoftDrop),
+    (KeyCode::Space, InputKey::HardDrop),
+    (KeyCode::X, InputKey::RotateCW),
+    (KeyCode::Z, InputKey::RotateCCW),
+    (KeyCode::F, InputKey::Rotate180),
+    (KeyCode::C, InputKey::Hold)
+];
+
+/// Passes the keys pressed and released since the last frame to the `handler`.
+/// Returns the actions up to `time_ms`.
+pub fn poll_input(handler: &mut InputHandler, time_ms: f64) -> Vec<MoveAction> {
+    let mut actions = Vec::new();
+
+    for (key_code, key) in KEY_BINDINGS {
+        if is_key_pressed(key_code) {
+            actions.extend(handler.key_down(key, time_ms));
+        }
+        if is_key_released(key_code) {
+            actions.extend(handler.key_up(key, time_ms));
+        }
+    }
+
+    actions.extend(handler.update(time_ms));
+
+    actions
+}
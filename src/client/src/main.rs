@@ -16,6 +16,7 @@ mod board_manager;
 mod board_renderer;
 mod entity;
 mod game_root;
+mod input;
 mod scene;
 mod state_machine;
 
@@ -5,18 +5,23 @@
 
 use std::sync::{Arc};
 use crate::board_command::BoardMoveDir;
-use crate::cell_holder::{CellHolder};
+use crate::board_sync::SyncError;
+use crate::cell_holder::{diff_rows, CellHolder};
+use crate::game_mode::{GameMode, GameOutcome, GameResult};
 use crate::game_settings::{GameSettings};
 use crate::garbage_mgr::GarbageMgr;
 use crate::gravity_mgr::{GravityMgr, GravityUpdateResult};
 use crate::piece::{Piece, PieceType, RotationDirection, RotationState};
 use crate::piece_mgr::{PieceMgr, BoardErrorReason};
-use crate::replays::{BoardStats, MoveAction, MoveResult, ReplayMgr};
+use crate::points_mgr::PointsMgr;
+use crate::practice_mgr::PracticeMgr;
+use crate::protocol::{BoardDelta, BoardSnapshot, ConnId};
+use crate::replays::{BoardStats, GarbageEventKind, MoveAction, MoveResult, ReplayMgr};
 use crate::scoring::{ScoringMgr};
 use crate::time_mgr::TimeMgr;
 use crate::wall_kick_data::{WallKickData};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Board {
     pub game_settings: GameSettings,
 
@@ -26,6 +31,10 @@ pub struct Board {
 
     wkd: Arc<WallKickData>,
     pub(crate) scoring_mgr: ScoringMgr,
+    /// Single-player points scoring, `None` if it is disabled by `GameSettings::points`.
+    pub points_mgr: Option<PointsMgr>,
+    /// Garbage of the cheese race and survival modes, `None` in the other modes.
+    pub practice_mgr: Option<PracticeMgr>,
     pub board_stats: BoardStats,
     pub is_dead: bool,
     pub garbage_mgr: GarbageMgr,
@@ -40,20 +49,29 @@ impl Board {
 
         let gravity_mgr = GravityMgr::new(&game_settings.gravity);
         let piece_mgr = Box::new(PieceMgr::new(&game_settings, seed));
+        let garbage_mgr = GarbageMgr::new(&game_settings.attack, seed);
+        let points_mgr = game_settings.points.as_ref().map(PointsMgr::new);
+        let practice_mgr = PracticeMgr::new(game_settings.game_mode, seed);
 
-        Self {
+        let mut board = Self {
             game_settings,
             gravity_mgr,
             piece_mgr,
             is_enabled: true,
             wkd: Arc::clone(&wkd),
             scoring_mgr: ScoringMgr::new(),
+            points_mgr,
+            practice_mgr,
             board_stats: BoardStats::default(),
             is_dead: false,
-            garbage_mgr: GarbageMgr::new(&game_settings.attack),
+            garbage_mgr,
             replay_mgr: ReplayMgr::default(),
             cur_sec: 0.0
-        }
+        };
+        board.reset_points();
+        board.refill_cheese();
+
+        board
     }
 
     /// Updates `GravityMgr` by sending delta time `dt` and updating its current variables:
@@ -67,16 +85,26 @@ impl Board {
             return Some(Err(BoardErrorReason::BoardDead));
         }
 
+        if self.is_finished() {
+            return Some(Err(BoardErrorReason::GameFinished));
+        }
+
         self.cur_sec = time_mgr.elapsed_sec;
 
         self.board_stats.update(time_mgr);
         self.garbage_mgr.update(time_mgr);
+        self.update_practice_garbage();
+
+        // the time of the timed modes has run out
+        if self.check_game_goal().is_some() {
+            return Some(Err(BoardErrorReason::GameFinished));
+        }
 
         let res = self.gravity_mgr.update(&self.piece_mgr, time_mgr);
         match res {
             GravityUpdateResult::None => None,
             GravityUpdateResult::SoftDrop(dt) => {
-                self.soft_drop(dt);
+                self.gravity_drop(dt);
                 None
             }
             GravityUpdateResult::HardDrop => {
@@ -169,6 +197,7 @@ impl Board {
             MoveAction::SoftDrop => { self.soft_drop(1); },
             MoveAction::HardDrop => { return Some(self.hard_drop()); }
             MoveAction::HoldPiece => { self.try_hold_piece(); }
+            MoveAction::GravityDrop => { self.gravity_drop(1); }
         }
 
         None
@@ -185,21 +214,44 @@ impl Board {
         if self.is_dead {
             return Err(BoardErrorReason::BoardDead);
         }
+        if self.is_finished() {
+            return Err(BoardErrorReason::GameFinished);
+        }
 
         let piece_mgr = &mut self.piece_mgr;
+        let drop_distance = piece_mgr.nearest_y - piece_mgr.cur_piece.get_y();
+        let garbage_rows = piece_mgr.cell_holder.get_garbage_row_count();
         // apply the piece onto board
-        let hard_drop_info = piece_mgr.hard_drop()?;
+        let hard_drop_info = piece_mgr.hard_drop().inspect_err(|err| {
+            // the player has topped out
+            if matches!(err, BoardErrorReason::CannotApplyPiece | BoardErrorReason::CannotSpawnPiece) {
+                self.is_dead = true;
+                self.finish_game(GameOutcome::ToppedOut);
+            }
+        })?;
         // update combo and b2b
         self.scoring_mgr.hard_drop(&hard_drop_info);
+        // update the single-player score and level
+        let points = self.points_mgr
+            .as_mut()
+            .map(|points_mgr| points_mgr.hard_drop(
+                &hard_drop_info,
+                drop_distance,
+                self.scoring_mgr.b2b,
+                self.scoring_mgr.combo
+            ))
+            .unwrap_or(0);
         // update board stats (apm, pps, etc.)
         self.board_stats.hard_drop(&hard_drop_info, &self.scoring_mgr);
+        self.board_stats.garbage_cleared += garbage_rows - self.piece_mgr.cell_holder.get_garbage_row_count();
+        self.update_points_stats();
         // add the move to the replay manager
         self.replay_mgr.push_move(self.cur_sec, MoveAction::HardDrop);
 
         let move_queue = self.replay_mgr.end_move();
 
         // initializing the move result
-        let move_result = MoveResult::new(
+        let mut move_result = MoveResult::new(
             &self.scoring_mgr,
             hard_drop_info,
             &self.game_settings.attack,
@@ -208,6 +260,8 @@ impl Board {
             move_queue,
             self.cur_sec
         );
+        move_result.points = points;
+        move_result.game_result = self.check_game_goal();
 
         // if the attack is negative, the board received damage; pushing garbage then
         move_result.attack.in_damage_queue
@@ -216,6 +270,7 @@ impl Board {
                 self.garbage_mgr.push_garbage_at(dmg.amount as u32, dmg.hole_x, &mut self.piece_mgr.cell_holder);
                 self.piece_mgr.update_nearest_y();
             });
+        self.refill_cheese();
 
         Ok(move_result)
     }
@@ -224,13 +279,30 @@ impl Board {
     /// Returns an `u32` which indicates how many times the piece was successfully
     /// soft dropped.
     pub fn soft_drop(&mut self, delta: u32) -> u32 {
+        let amount_moved = self.drop_piece(delta, MoveAction::SoftDrop);
+
+        if let Some(points_mgr) = &mut self.points_mgr {
+            points_mgr.soft_drop(amount_moved);
+            self.update_points_stats();
+        }
+
+        amount_moved
+    }
+
+    /// Moves current piece down by amount `delta` by the gravity. Unlike `soft_drop()`,
+    /// no points are awarded for that.
+    fn gravity_drop(&mut self, delta: u32) -> u32 {
+        self.drop_piece(delta, MoveAction::GravityDrop)
+    }
+
+    fn drop_piece(&mut self, delta: u32, action: MoveAction) -> u32 {
         let dt = std::cmp::min(delta, self.game_settings.board.full_height() as u32);
         let mut amount_moved = 0;
 
         for _ in 0..dt {
             if self.piece_mgr.soft_drop() {
                 self.gravity_mgr.reset_lock();
-                self.replay_mgr.push_move(self.cur_sec, MoveAction::SoftDrop);
+                self.replay_mgr.push_move(self.cur_sec, action);
                 amount_moved += 1;
             }
         }
@@ -238,19 +310,138 @@ impl Board {
         amount_moved
     }
 
+    /// Copies the score and level of `PointsMgr` to the stats and adjusts the gravity to the level.
+    fn update_points_stats(&mut self) {
+        let Some(points_mgr) = &self.points_mgr else {
+            return;
+        };
+
+        let is_level_changed = self.board_stats.level != points_mgr.level;
+        self.board_stats.score = points_mgr.score;
+        self.board_stats.level = points_mgr.level;
+
+        let level_gravity = self.game_settings.points.is_some_and(|points| points.level_gravity);
+        if is_level_changed && level_gravity {
+            self.gravity_mgr.set_level(points_mgr.level);
+        }
+    }
+
+    /// Returns `true` if the game is finished either by completing the goal of the `GameMode` or by topping out.
+    pub fn is_finished(&self) -> bool {
+        self.board_stats.game_result.is_some()
+    }
+
+    /// Finishes the game if the goal of the `GameMode` is completed. Returns the result of the game if so.
+    fn check_game_goal(&mut self) -> Option<GameResult> {
+        let game_mode = self.game_settings.game_mode;
+        if !game_mode.is_completed(&self.board_stats, self.game_settings.points.as_ref()) {
+            return None;
+        }
+
+        self.finish_game(GameOutcome::Completed)
+    }
+
+    fn finish_game(&mut self, outcome: GameOutcome) -> Option<GameResult> {
+        let game_mode = self.game_settings.game_mode;
+        // versus games are finished by the match, not by the board
+        if game_mode == GameMode::Versus {
+            return None;
+        }
+
+        let result = GameResult::new(game_mode, outcome, &self.board_stats);
+        self.board_stats.game_result = Some(result);
+
+        Some(result)
+    }
+
+    /// Pushes the cheese race rows onto the board until it has enough of them again.
+    fn refill_cheese(&mut self) {
+        let Some(practice_mgr) = &mut self.practice_mgr else {
+            return;
+        };
+
+        let garbage_rows = self.piece_mgr.cell_holder.get_garbage_row_count();
+        for hole_x in practice_mgr.refill_cheese(garbage_rows, self.game_settings.board.width) {
+            self.garbage_mgr.push_garbage_at(1, hole_x, &mut self.piece_mgr.cell_holder);
+        }
+        self.piece_mgr.update_nearest_y();
+    }
+
+    /// Queues the survival garbage arrived since the last update.
+    fn update_practice_garbage(&mut self) {
+        let Some(practice_mgr) = &mut self.practice_mgr else {
+            return;
+        };
+
+        let holes = practice_mgr.update(self.cur_sec, self.game_settings.board.width);
+        // rows with the same hole are queued together
+        for (hole_x, rows) in group_holes(&holes) {
+            self.garbage_mgr.attack_now(rows as i32, hole_x);
+        }
+    }
+
+    fn reset_points(&mut self) {
+        if let Some(points_mgr) = &mut self.points_mgr {
+            points_mgr.reset();
+        }
+        self.update_points_stats();
+    }
+
     /// Sends garbage onto current board with specified `amount` of garbage rows and `messiness`.
     /// The higher the messiness, the more random the holes are.
     /// Messiness = 0 means that the hole will be at the same x coordinate within
     /// pending garbage rows.
     pub fn push_garbage(&mut self, amount: u32, messiness: u32) {
-        self.garbage_mgr.push_garbage(amount, messiness, &mut self.piece_mgr.cell_holder);
+        let holes = self.garbage_mgr.push_garbage(amount, messiness, &mut self.piece_mgr.cell_holder);
+        self.piece_mgr.update_nearest_y();
+
+        for (hole_x, rows) in group_holes(&holes) {
+            self.replay_mgr.push_garbage(self.cur_sec, GarbageEventKind::Immediate, rows as i32, hole_x);
+        }
+    }
+
+    /// Sends `amount` rows of garbage with a hole at `hole_x` onto current board immediately.
+    pub fn push_garbage_at(&mut self, amount: u32, hole_x: u32) {
+        self.garbage_mgr.push_garbage_at(amount, hole_x, &mut self.piece_mgr.cell_holder);
+        self.piece_mgr.update_nearest_y();
+        self.replay_mgr.push_garbage(self.cur_sec, GarbageEventKind::Immediate, amount as i32, hole_x);
     }
 
     /// Pushes damage onto board. The difference between this method and `push_garbage()`
     /// is that `push_garbage()` adds garbage immediately, whereas this method
     /// adds damage into the damage queue.
     pub fn attack(&mut self, damage: i32) {
-        self.garbage_mgr.attack(self.game_settings.board.width, damage);
+        for dmg in self.garbage_mgr.attack(self.game_settings.board.width, damage) {
+            self.replay_mgr.push_garbage(self.cur_sec, GarbageEventKind::Attack, dmg.amount, dmg.hole_x);
+        }
+    }
+
+    /// Same as `attack()`, but the garbage is marked as sent by the player `sender`,
+    /// who is credited with the KO if the garbage tops out the board.
+    pub fn attack_from(&mut self, damage: i32, sender: ConnId) {
+        for dmg in self.garbage_mgr.attack_from(self.game_settings.board.width, damage, Some(sender)) {
+            self.replay_mgr.push_garbage(self.cur_sec, GarbageEventKind::Attack, dmg.amount, dmg.hole_x);
+        }
+    }
+
+    /// Credits the board with knocking out an opponent who had `victim_badges` badges.
+    /// The player collects a badge for the KO along with all of the badges of the opponent.
+    pub fn credit_ko(&mut self, victim_badges: u32) {
+        self.scoring_mgr.badges += victim_badges + 1;
+        self.board_stats.kos += 1;
+        self.board_stats.badges = self.scoring_mgr.badges;
+    }
+
+    pub fn get_badges(&self) -> u32 {
+        self.scoring_mgr.badges
+    }
+
+    /// Same as `attack()`, but with the garbage hole at the specified x coordinate.
+    pub fn attack_at(&mut self, damage: i32, hole_x: u32) {
+        if damage > 0 {
+            self.garbage_mgr.attack_at(damage, hole_x);
+            self.replay_mgr.push_garbage(self.cur_sec, GarbageEventKind::Attack, damage, hole_x);
+        }
     }
 
     pub fn get_cell_holder(&self) -> &CellHolder {
@@ -261,23 +452,57 @@ impl Board {
         &self.piece_mgr
     }
 
+    pub fn get_scoring_mgr(&self) -> &ScoringMgr {
+        &self.scoring_mgr
+    }
+
+    /// Returns the seed the piece queue was created with.
+    pub fn get_seed(&self) -> u64 {
+        self.piece_mgr.piece_queue.get_seed()
+    }
+
     /// Returns nearest Y coordinate which the piece fits at.
     /// May be useful for rendering ghost piece.
     pub fn find_nearest_y(&self) -> u32 {
         self.get_piece_mgr().find_nearest_y()
     }
 
+    /// Overrides the visible state of the board with the authoritative `snapshot`.
+    /// The piece queue is caught up with the snapshot, so the board must have
+    /// the same seed and must not be ahead of it.
+    /// Returns the number of pieces the queue was behind by
+    /// or `None` if the piece queue could not be synchronised.
+    pub fn apply_snapshot(&mut self, snapshot: &BoardSnapshot) -> Option<usize> {
+        let spawned = self.piece_mgr.sync_queue(&snapshot.queue);
+        if spawned.is_none() {
+            // at least show the right pieces
+            self.piece_mgr.piece_queue.queue = snapshot.queue.iter().copied().collect();
+        }
+
+        let rows = diff_rows(self.piece_mgr.cell_holder.get_layout(), &snapshot.cells);
+        self.piece_mgr.cell_holder.apply_delta(&rows);
+        self.piece_mgr.set_piece_state(&snapshot.piece, snapshot.hold);
+        self.garbage_mgr.queue = snapshot.garbage_queue.iter().copied().collect();
+        self.is_dead = snapshot.is_dead;
+
+        spawned
+    }
+
     /// Completely resets the state of the board.
     pub fn reset(&mut self, new_seed: Option<u64>) {
         self.gravity_mgr.reset();
-        self.scoring_mgr.combo = 0;
-        self.scoring_mgr.b2b = 0;
+        self.scoring_mgr.reset();
         self.board_stats.reset();
+        self.reset_points();
         self.piece_mgr.reset(new_seed);
+        if let Some(practice_mgr) = &mut self.practice_mgr {
+            practice_mgr.reset(new_seed);
+        }
+        self.refill_cheese();
         self.is_dead = false;
         //self.time_mgr.reset();
         self.replay_mgr.reset();
-        self.garbage_mgr.reset();
+        self.garbage_mgr.reset(new_seed);
 
         self.enable();
     }
@@ -307,6 +532,20 @@ impl Board {
     }
 }
 
+/// Groups the consecutive garbage rows with the same hole into `(hole_x, rows)` pairs.
+fn group_holes(holes: &[u32]) -> Vec<(u32, u32)> {
+    let mut groups: Vec<(u32, u32)> = vec![];
+
+    for hole_x in holes {
+        match groups.last_mut() {
+            Some((last_x, rows)) if last_x == hole_x => *rows += 1,
+            _ => groups.push((*hole_x, 1))
+        }
+    }
+
+    groups
+}
+
 /// Simplified or lightweight version of `Board` which contains only the essentials.
 /// It can only execute a `MoveAction`, doesn't track any scores, but it can handle incoming garbage.
 /// Piece can be moved and rotated freely without doing any checks.
@@ -314,7 +553,10 @@ impl Board {
 pub struct BoardSimple {
     pub piece_mgr: Box<PieceMgr>,
     pub is_enabled: bool,
+    pub is_dead: bool,
     pub garbage_mgr: GarbageMgr,
+    /// Sequence number of the last state received from `apply_snapshot()` or `apply_delta()`.
+    pub seq: u64
 }
 
 impl BoardSimple {
@@ -322,7 +564,9 @@ impl BoardSimple {
         Self {
             piece_mgr: Box::new(PieceMgr::new(&game_settings, seed)),
             is_enabled: true,
-            garbage_mgr: GarbageMgr::new(&game_settings.attack)
+            is_dead: false,
+            garbage_mgr: GarbageMgr::new(&game_settings.attack, seed),
+            seq: 0
         }
     }
 
@@ -338,7 +582,7 @@ impl BoardSimple {
             MoveAction::RotateCW => { self.piece_mgr.rotate_force(RotationDirection::Clockwise); }
             MoveAction::RotateCCW => { self.piece_mgr.rotate_force(RotationDirection::CounterClockwise); }
             MoveAction::RotateDeg180 => { self.piece_mgr.rotate_force(RotationDirection::Deg180); }
-            MoveAction::SoftDrop => { self.piece_mgr.soft_drop_force(); }
+            MoveAction::SoftDrop | MoveAction::GravityDrop => { self.piece_mgr.soft_drop_force(); }
             MoveAction::HardDrop => {
                 self.piece_mgr.hard_drop().ok();
             }
@@ -352,4 +596,52 @@ impl BoardSimple {
     pub fn send_garbage(&mut self, amount: u32, hole_x: u32) {
         self.garbage_mgr.push_garbage_at(amount, hole_x, &mut self.piece_mgr.cell_holder);
     }
+
+    pub fn snapshot(&self) -> BoardSnapshot {
+        BoardSnapshot::from_parts(&self.piece_mgr, &self.garbage_mgr, self.is_dead)
+    }
+
+    /// Replaces the whole state of the board with the `snapshot` of sequence number `seq`.
+    pub fn apply_snapshot(&mut self, seq: u64, snapshot: &BoardSnapshot) {
+        let rows = diff_rows(self.piece_mgr.cell_holder.get_layout(), &snapshot.cells);
+        self.piece_mgr.cell_holder.apply_delta(&rows);
+        self.piece_mgr.set_piece_state(&snapshot.piece, snapshot.hold);
+        self.piece_mgr.piece_queue.queue = snapshot.queue.iter().copied().collect();
+        self.garbage_mgr.queue = snapshot.garbage_queue.iter().copied().collect();
+        self.is_dead = snapshot.is_dead;
+        self.seq = seq;
+    }
+
+    /// Applies the changes of the next sequence number.
+    /// Deltas older than the current state are ignored.
+    /// Returns `Err(SyncError)` if some of the deltas were missed or the delta doesn't fit the board,
+    /// in which case the board must be resynchronised with a snapshot.
+    pub fn apply_delta(&mut self, delta: &BoardDelta) -> Result<(), SyncError> {
+        if delta.seq <= self.seq {
+            return Ok(());
+        }
+        if delta.seq != self.seq + 1 {
+            return Err(SyncError::Gap { expected: self.seq + 1, received: delta.seq });
+        }
+        let cell_holder = &self.piece_mgr.cell_holder;
+        delta.check_rows(cell_holder.width, cell_holder.height)?;
+
+        self.piece_mgr.cell_holder.apply_delta(&delta.rows);
+        match delta.piece {
+            Some(piece) => self.piece_mgr.set_piece_state(&piece.piece, piece.hold),
+            None => self.piece_mgr.update_nearest_y()
+        }
+        if let Some(queue) = &delta.queue {
+            self.piece_mgr.piece_queue.queue = queue.iter().copied().collect();
+        }
+        if let Some(garbage_queue) = &delta.garbage_queue {
+            self.garbage_mgr.queue = garbage_queue.iter().copied().collect();
+        }
+        if let Some(is_dead) = delta.is_dead {
+            self.is_dead = is_dead;
+        }
+        self.seq = delta.seq;
+
+        Ok(())
+    }
 }
\ No newline at end of file
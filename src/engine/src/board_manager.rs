@@ -126,12 +126,12 @@ impl BoardManager {
     pub fn new(game_settings: GameSettings) -> Self {
         let mut rng = rand::thread_rng();
         let seed: u64 = rng.gen();
-        let wkd = Arc::new(WallKickData::new(game_settings.wall_kick_data_mode));
+        let wkd = Arc::new(WallKickData::new(&game_settings.wall_kick_data_mode));
         let wkd2 = Arc::clone(&wkd);
 
         Self {
             boards: Boards::default(),
-            game_settings,
+            game_settings: game_settings.clone(),
             rng_manager: RngManager::new(seed),
             time_mgr: TimeMgr::new(),
             wkd,
@@ -142,7 +142,7 @@ impl BoardManager {
     pub fn add_board(&mut self) -> (String, Receiver<BoardMessage>, Rc<RefCell<Board>>) {
         let uuid = Uuid::new_v4();
 
-        let board = RefCell::new(Board::new(self.game_settings, Arc::clone(&self.wkd), self.rng_manager.get_seed()));
+        let board = RefCell::new(Board::new(self.game_settings.clone(), Arc::clone(&self.wkd), self.rng_manager.get_seed()));
         let rw_board = Rc::new(board);
 
         let (sender, receiver) = mpsc::channel();
@@ -0,0 +1,277 @@
+/*
+ * Copyright (c) Grigory Alfyorov. Licensed under the MIT License.
+ * See the LICENSE file in the repository root for full licence text.
+ */
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use crate::board::Board;
+use crate::game_settings::GameSettings;
+use crate::piece_mgr::BoardErrorReason;
+use crate::protocol::{BoardSnapshot, WsBoardCommand};
+use crate::replays::MoveResult;
+use crate::time_mgr::TimeMgr;
+use crate::wall_kick_data::WallKickData;
+
+#[derive(Debug, Clone)]
+enum PendingInput {
+    /// Input sent to the server, which has not been acknowledged yet.
+    Command { seq: u64, cmd: WsBoardCommand },
+    /// The piece was locked by the local gravity. The server locks the piece on its own,
+    /// so it is not sent, but it is replayed until the server does that.
+    Lock
+}
+
+impl PendingInput {
+    fn apply(&self, board: &mut Board) -> Option<Result<MoveResult, BoardErrorReason>> {
+        match self {
+            PendingInput::Command { cmd, .. } => cmd.apply(board),
+            PendingInput::Lock => Some(board.hard_drop())
+        }
+    }
+}
+
+/// Board of the local player of an online match.
+///
+/// Inputs are applied to the predicted board immediately, so the game feels as responsive
+/// as the offline one, and are numbered to be sent to the server. When an authoritative
+/// state arrives, the acknowledged inputs are applied to the confirmed board, the confirmed board
+/// takes the state of the server and the inputs which are not acknowledged yet are replayed on top of it.
+/// If the result differs from the predicted board, the predicted board is rolled back to it.
+///
+/// Both boards must be created with the seed of the server's board, so they generate the same pieces.
+#[derive(Debug)]
+pub struct BoardPredictor {
+    board: Board,
+    confirmed: Board,
+    pending: VecDeque<PendingInput>,
+    next_seq: u64,
+    last_ack: u64
+}
+
+impl BoardPredictor {
+    pub fn new(game_settings: GameSettings, wkd: Arc<WallKickData>, seed: u64) -> Self {
+        let board = Board::new(game_settings, wkd, seed);
+
+        Self {
+            confirmed: board.clone(),
+            board,
+            pending: VecDeque::new(),
+            next_seq: 1,
+            last_ack: 0
+        }
+    }
+
+    /// Returns the predicted board.
+    pub fn get_board(&self) -> &Board {
+        &self.board
+    }
+
+    /// Returns the board state confirmed by the server.
+    pub fn get_confirmed_board(&self) -> &Board {
+        &self.confirmed
+    }
+
+    /// Sequence number of the last input acknowledged by the server.
+    pub fn get_last_ack(&self) -> u64 {
+        self.last_ack
+    }
+
+    /// Number of inputs not acknowledged by the server yet.
+    pub fn pending_count(&self) -> usize {
+        self.pending
+            .iter()
+            .filter(|input| matches!(input, PendingInput::Command { .. }))
+            .count()
+    }
+
+    /// Applies `cmd` to the predicted board. Returns the sequence number the command must
+    /// be sent to the server with and the result of the move, if any.
+    pub fn input(&mut self, cmd: WsBoardCommand) -> (u64, Option<Result<MoveResult, BoardErrorReason>>) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        let input = PendingInput::Command { seq, cmd };
+        let res = input.apply(&mut self.board);
+        self.pending.push_back(input);
+
+        (seq, res)
+    }
+
+    /// Advances the gravity of the predicted board.
+    pub fn update(&mut self, time_mgr: &TimeMgr) -> Option<Result<MoveResult, BoardErrorReason>> {
+        let res = self.board.update(time_mgr);
+
+        if let Some(Ok(_)) = res {
+            self.pending.push_back(PendingInput::Lock);
+        }
+
+        res
+    }
+
+    /// Takes the authoritative state `snapshot` of the board which includes the inputs up to `ack`.
+    /// Returns `true` if the predicted board was rolled back.
+    pub fn reconcile(&mut self, ack: u64, snapshot: &BoardSnapshot) -> bool {
+        if ack < self.last_ack {
+            return false;
+        }
+
+        let mut locks_skipped = 0;
+        while let Some(input) = self.pending.front() {
+            match *input {
+                PendingInput::Command { seq, .. } if seq <= ack => {
+                    input.apply(&mut self.confirmed);
+                }
+                // the piece was either locked by the server as well, or the input after it
+                // was applied to the same piece on the server
+                PendingInput::Lock if self.has_acked_command(ack) => {
+                    locks_skipped += 1;
+                }
+                _ => break
+            }
+
+            self.pending.pop_front();
+        }
+        self.last_ack = ack;
+
+        // every piece the server has spawned on its own was locked by its gravity
+        let server_locks = self.confirmed.apply_snapshot(snapshot).unwrap_or(0);
+        let mut locks_left = server_locks.saturating_sub(locks_skipped);
+        self.pending.retain(|input| match input {
+            PendingInput::Lock if locks_left > 0 => {
+                locks_left -= 1;
+                false
+            }
+            _ => true
+        });
+
+        let mut predicted = self.confirmed.clone();
+        // the timers of the local gravity are kept, otherwise the piece would stutter
+        predicted.gravity_mgr = self.board.gravity_mgr.clone();
+        for input in self.pending.iter() {
+            input.apply(&mut predicted);
+        }
+
+        if BoardSnapshot::new(&predicted) == BoardSnapshot::new(&self.board) {
+            return false;
+        }
+
+        self.board = predicted;
+
+        true
+    }
+
+    /// Starts a new game with the seed `new_seed` forgetting all of the inputs.
+    pub fn reset(&mut self, new_seed: Option<u64>) {
+        self.board.reset(new_seed);
+        self.confirmed.reset(new_seed);
+        self.pending.clear();
+        self.next_seq = 1;
+        self.last_ack = 0;
+    }
+
+    fn has_acked_command(&self, ack: u64) -> bool {
+        self.pending
+            .iter()
+            .any(|input| matches!(input, PendingInput::Command { seq, .. } if *seq <= ack))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board_command::BoardMoveDir;
+    use crate::piece::RotationDirection;
+
+    const SEED: u64 = 1337;
+
+    fn create_boards() -> (BoardPredictor, Board) {
+        let game_settings = GameSettings::default();
+        let wkd = Arc::new(WallKickData::new(&game_settings.wall_kick_data_mode));
+
+        (
+            BoardPredictor::new(game_settings.clone(), Arc::clone(&wkd), SEED),
+            Board::new(game_settings, wkd, SEED)
+        )
+    }
+
+    fn commands() -> Vec<WsBoardCommand> {
+        vec![
+            WsBoardCommand::Move(BoardMoveDir::Left, 3),
+            WsBoardCommand::HardDrop,
+            WsBoardCommand::Rotate(RotationDirection::Clockwise),
+            WsBoardCommand::Move(BoardMoveDir::Right, 4),
+            WsBoardCommand::HardDrop,
+            WsBoardCommand::HoldPiece,
+            WsBoardCommand::SoftDrop(3),
+            WsBoardCommand::HardDrop,
+            WsBoardCommand::Move(BoardMoveDir::Left, 1),
+            WsBoardCommand::HardDrop
+        ]
+    }
+
+    #[test]
+    fn matching_server_does_not_roll_back() {
+        let (mut predictor, mut server) = create_boards();
+
+        let sent: Vec<(u64, WsBoardCommand)> = commands()
+            .into_iter()
+            .map(|cmd| (predictor.input(cmd).0, cmd))
+            .collect();
+        assert_eq!(predictor.pending_count(), sent.len());
+
+        // the server is a few inputs behind
+        for (seq, cmd) in sent {
+            cmd.apply(&mut server);
+            assert!(!predictor.reconcile(seq, &BoardSnapshot::new(&server)));
+        }
+
+        assert_eq!(predictor.pending_count(), 0);
+        assert_eq!(BoardSnapshot::new(predictor.get_board()), BoardSnapshot::new(&server));
+    }
+
+    #[test]
+    fn diverged_state_is_rolled_back() {
+        let (mut predictor, mut server) = create_boards();
+        let commands = commands();
+
+        let (seq, _) = predictor.input(commands[0]);
+        commands[0].apply(&mut server);
+        // the garbage received by the server is not known to the client
+        server.push_garbage_at(3, 2);
+
+        for cmd in &commands[1..4] {
+            predictor.input(*cmd);
+        }
+
+        assert!(predictor.reconcile(seq, &BoardSnapshot::new(&server)));
+        assert_eq!(predictor.pending_count(), 3);
+
+        for (i, cmd) in commands[1..4].iter().enumerate() {
+            cmd.apply(&mut server);
+            predictor.reconcile(seq + 1 + i as u64, &BoardSnapshot::new(&server));
+        }
+
+        assert_eq!(BoardSnapshot::new(predictor.get_board()), BoardSnapshot::new(&server));
+        assert_eq!(predictor.get_board().get_cell_holder().get_layout(), server.get_cell_holder().get_layout());
+    }
+
+    #[test]
+    fn server_lock_catches_up_piece_queue() {
+        let (mut predictor, mut server) = create_boards();
+
+        // the piece is locked by the gravity of the server
+        server.hard_drop().unwrap();
+        assert!(predictor.reconcile(0, &BoardSnapshot::new(&server)));
+
+        let (seq, _) = predictor.input(WsBoardCommand::HardDrop);
+        WsBoardCommand::HardDrop.apply(&mut server);
+        assert!(!predictor.reconcile(seq, &BoardSnapshot::new(&server)));
+
+        assert_eq!(BoardSnapshot::new(predictor.get_board()), BoardSnapshot::new(&server));
+        assert_eq!(
+            BoardSnapshot::new(predictor.get_confirmed_board()),
+            BoardSnapshot::new(&server)
+        );
+    }
+}
@@ -0,0 +1,216 @@
+/*
+ * Copyright (c) Grigory Alfyorov. Licensed under the MIT License.
+ * See the LICENSE file in the repository root for full licence text.
+ */
+
+use std::fmt::{Display, Formatter};
+use crate::board::Board;
+use crate::protocol::{BoardDelta, BoardSnapshot};
+
+/// Returned by `BoardSimple::apply_delta()` when the delta can't be applied.
+/// The board must be resynchronised with a snapshot then.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SyncError {
+    /// One or more deltas were missed.
+    Gap {
+        expected: u64,
+        received: u64
+    },
+    /// The delta has a row which doesn't fit the board, so it is malformed or made for another board.
+    InvalidRow {
+        y: usize
+    }
+}
+
+impl Display for SyncError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SyncError::Gap { expected, received } => write!(f, "Expected board delta #{}, received #{}", expected, received),
+            SyncError::InvalidRow { y } => write!(f, "Board delta row {} doesn't fit the board", y)
+        }
+    }
+}
+
+impl std::error::Error for SyncError {}
+
+/// Tracks the state of a `Board` last sent to the remote clients and produces
+/// the deltas between that state and the current one.
+///
+/// The initial state has sequence number `0`, every non-empty delta increments it by one.
+#[derive(Debug, Clone)]
+pub struct BoardSyncSource {
+    seq: u64,
+    last: BoardSnapshot
+}
+
+impl BoardSyncSource {
+    pub fn new(board: &Board) -> Self {
+        Self {
+            seq: 0,
+            last: BoardSnapshot::new(board)
+        }
+    }
+
+    /// Returns the changes of the `board` since the last call.
+    /// Returns `None` if nothing has changed.
+    pub fn delta(&mut self, board: &Board) -> Option<BoardDelta> {
+        let snapshot = BoardSnapshot::new(board);
+        let delta = self.last.diff(&snapshot, self.seq + 1);
+
+        if delta.is_empty() {
+            return None;
+        }
+
+        self.seq = delta.seq;
+        self.last = snapshot;
+
+        Some(delta)
+    }
+
+    pub fn get_seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Returns the state at the current sequence number. Used for resynchronising the clients.
+    pub fn get_snapshot(&self) -> &BoardSnapshot {
+        &self.last
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use super::*;
+    use crate::board::BoardSimple;
+    use crate::game_settings::GameSettings;
+    use crate::piece::RotationDirection;
+    use crate::wall_kick_data::WallKickData;
+
+    const SEED: u64 = 1337;
+
+    fn create_board() -> Board {
+        let game_settings = GameSettings::default();
+        let wkd = Arc::new(WallKickData::new(&game_settings.wall_kick_data_mode));
+
+        Board::new(game_settings, wkd, SEED)
+    }
+
+    /// Plays a piece, changing the board a little bit differently every time.
+    fn play_piece(board: &mut Board, i: u32) {
+        match i % 3 {
+            0 => { board.move_left(i % 4 + 1); }
+            1 => { board.rotate(RotationDirection::Clockwise); board.move_right(i % 3); }
+            _ => { board.try_hold_piece(); }
+        }
+        if i % 4 == 2 {
+            board.attack(1);
+        }
+
+        board.hard_drop().unwrap();
+    }
+
+    #[test]
+    fn unchanged_board_produces_no_delta() {
+        let board = create_board();
+        let mut source = BoardSyncSource::new(&board);
+
+        assert!(source.delta(&board).is_none());
+        assert_eq!(source.get_seq(), 0);
+    }
+
+    #[test]
+    fn deltas_keep_mirror_in_sync() {
+        let mut board = create_board();
+        let mut source = BoardSyncSource::new(&board);
+        let mut mirror = BoardSimple::new(GameSettings::default(), 0);
+        mirror.apply_snapshot(source.get_seq(), source.get_snapshot());
+
+        for i in 0..10 {
+            board.move_right(1);
+            // every delta goes through the wire
+            if let Some(delta) = source.delta(&board) {
+                let json = serde_json::to_string(&delta).unwrap();
+                mirror.apply_delta(&serde_json::from_str(&json).unwrap()).unwrap();
+            }
+
+            play_piece(&mut board, i);
+            let delta = source.delta(&board).unwrap();
+            assert!(delta.rows.len() < board.get_cell_holder().get_layout().len());
+            mirror.apply_delta(&delta).unwrap();
+
+            assert_eq!(mirror.seq, source.get_seq());
+            assert_eq!(&mirror.snapshot(), source.get_snapshot());
+            assert_eq!(mirror.snapshot(), BoardSnapshot::new(&board));
+        }
+    }
+
+    #[test]
+    fn gap_is_detected_and_resynced() {
+        let mut board = create_board();
+        let mut source = BoardSyncSource::new(&board);
+        let mut mirror = BoardSimple::new(GameSettings::default(), 0);
+        mirror.apply_snapshot(source.get_seq(), source.get_snapshot());
+
+        play_piece(&mut board, 0);
+        let first = source.delta(&board).unwrap();
+        play_piece(&mut board, 1);
+        let second = source.delta(&board).unwrap();
+
+        // the first delta is lost
+        assert_eq!(mirror.apply_delta(&second), Err(SyncError::Gap { expected: 1, received: 2 }));
+        assert_eq!(mirror.seq, 0);
+
+        mirror.apply_snapshot(source.get_seq(), source.get_snapshot());
+        assert_eq!(mirror.snapshot(), BoardSnapshot::new(&board));
+
+        // late deltas are ignored
+        assert_eq!(mirror.apply_delta(&first), Ok(()));
+        assert_eq!(mirror.seq, 2);
+        assert_eq!(mirror.snapshot(), BoardSnapshot::new(&board));
+    }
+
+    #[test]
+    fn mirror_plays_on_with_preview_only() {
+        let mut board = create_board();
+        let source = BoardSyncSource::new(&board);
+        // a board of another seed can't catch up, so it only has the preview
+        let game_settings = GameSettings::default();
+        let wkd = Arc::new(WallKickData::new(&game_settings.wall_kick_data_mode));
+        let mut mirror = Board::new(game_settings, wkd, SEED + 1);
+        mirror.apply_snapshot(source.get_snapshot());
+
+        for i in 0..10 {
+            board.hard_drop().unwrap();
+            mirror.hard_drop().unwrap();
+
+            // the pieces of the preview are the same, the ones generated after them are not
+            if i < board.game_settings.preview_size {
+                assert_eq!(mirror.get_piece_mgr().get_piece().get_type(), board.get_piece_mgr().get_piece().get_type());
+            }
+            assert_eq!(mirror.get_piece_mgr().piece_queue.get_preview().count(), board.game_settings.preview_size);
+        }
+    }
+
+    #[test]
+    fn malformed_delta_is_rejected() {
+        let mut board = create_board();
+        let mut source = BoardSyncSource::new(&board);
+        let mut mirror = BoardSimple::new(GameSettings::default(), 0);
+        mirror.apply_snapshot(source.get_seq(), source.get_snapshot());
+        let initial = source.get_snapshot().clone();
+
+        play_piece(&mut board, 0);
+        let mut delta = source.delta(&board).unwrap();
+        let height = board.get_cell_holder().get_layout().len();
+        delta.rows.last_mut().unwrap().y = height;
+
+        // nothing is applied, so the board stays in the previous state
+        assert_eq!(mirror.apply_delta(&delta), Err(SyncError::InvalidRow { y: height }));
+        assert_eq!(mirror.seq, 0);
+        assert_eq!(mirror.snapshot(), initial);
+
+        let mut snapshot = initial.clone();
+        assert_eq!(snapshot.apply_delta(&delta), Err(SyncError::InvalidRow { y: height }));
+        assert_eq!(snapshot, initial);
+    }
+}
@@ -34,6 +34,24 @@ pub struct Row {
     pub width: usize
 }
 
+/// A row which has changed between two versions of a layout.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RowDelta {
+    pub y: usize,
+    pub row: Row
+}
+
+/// Returns the rows of `new` which differ from the rows of `old`.
+/// Both of the layouts must be of the same height.
+pub fn diff_rows(old: &[Row], new: &[Row]) -> Vec<RowDelta> {
+    old.iter()
+        .zip(new.iter())
+        .enumerate()
+        .filter(|(_, (old, new))| old != new)
+        .map(|(y, (_, new))| RowDelta { y, row: new.clone() })
+        .collect()
+}
+
 impl Default for Row {
     fn default() -> Self {
         Self {
@@ -74,6 +92,10 @@ impl Row {
         self.cells.iter().filter(|c| increases_cells(**c)).count()
     }
 
+    pub fn has_garbage(&self) -> bool {
+        self.cells.contains(&CellType::Garbage)
+    }
+
     pub fn iter(&self) -> Iter<'_, CellType> {
         self.cells.iter()
     }
@@ -191,6 +213,20 @@ impl CellHolder {
         }
     }
 
+    /// Fills the bottom rows with `layout`, given from the top row down,
+    /// where `#` is a garbage cell. Used to set up the boards in the tests.
+    pub fn fill_bottom(&mut self, layout: &[&str]) {
+        let top = self.height - layout.len();
+
+        for (y, row) in layout.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                if c == '#' {
+                    self.set_cell_at(x, top + y, CellType::Garbage);
+                }
+            }
+        }
+    }
+
     pub fn is_out_of_bounds(&self, x: i32, y: i32) -> bool {
         x < 0 || x >= self.width as i32 || y >= self.height as i32 || y < 0
     }
@@ -283,6 +319,14 @@ impl CellHolder {
         }
     }
 
+    pub fn get_width(&self) -> usize {
+        self.width
+    }
+
+    pub fn get_height(&self) -> usize {
+        self.height
+    }
+
     pub fn get_layout(&self) -> &[Row] {
         &self.layout
     }
@@ -291,6 +335,20 @@ impl CellHolder {
         self.occupied_cells
     }
 
+    /// Returns the height of the stack, that is the number of rows from the bottom
+    /// up to and including the highest non-empty row.
+    pub fn get_stack_height(&self) -> u32 {
+        self.layout
+            .iter()
+            .position(|row| !row.is_empty())
+            .map_or(0, |y| (self.height - y) as u32)
+    }
+
+    /// Returns the number of rows containing at least one garbage cell.
+    pub fn get_garbage_row_count(&self) -> u32 {
+        self.layout.iter().filter(|row| row.has_garbage()).count() as u32
+    }
+
     pub fn calc_nearest_y(&self, cur_x: u32, cur_y: u32, points: &[Point]) -> u32 {
         let mut y = cur_y;
         
@@ -313,6 +371,22 @@ impl CellHolder {
 
         self.occupied_cells = 0;
     }
+
+    /// Returns the rows of `other` which differ from the rows of the current cell holder.
+    pub fn diff(&self, other: &CellHolder) -> Vec<RowDelta> {
+        diff_rows(&self.layout, &other.layout)
+    }
+
+    /// Replaces the rows with the ones in `deltas`, keeping the occupied cell count up to date.
+    pub fn apply_delta(&mut self, deltas: &[RowDelta]) {
+        for delta in deltas {
+            let old_cells = self.layout[delta.y].get_occupied_cell_count();
+            let new_cells = delta.row.get_occupied_cell_count();
+
+            self.layout[delta.y] = delta.row.clone();
+            self.occupied_cells = (self.occupied_cells + new_cells).saturating_sub(old_cells);
+        }
+    }
 }
 
 impl BoolArray for CellHolder {
@@ -482,4 +556,24 @@ mod tests {
         assert_eq!(1, cleared_rows[0]);
         assert_eq!(39, cleared_rows[1]);
     }
+
+    #[test]
+    fn diff_and_apply_delta() {
+        let mut old = create_empty_holder();
+        old.set_row(70, str_to_row("XXXX XXXXX"));
+        old.set_cell_at(0, 79, CellType::Garbage);
+
+        let mut new = old.clone();
+        assert!(old.diff(&new).is_empty());
+
+        new.set_cell_at(3, 79, CellType::T);
+        new.push_garbage(2);
+
+        let deltas = old.diff(&new);
+        assert_eq!(vec![69, 70, 78, 79], deltas.iter().map(|d| d.y).collect::<Vec<_>>());
+
+        old.apply_delta(&deltas);
+        assert_eq!(old.get_layout(), new.get_layout());
+        assert!(old.diff(&new).is_empty());
+    }
 }
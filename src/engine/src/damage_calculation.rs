@@ -3,10 +3,20 @@
  * See the LICENSE file in the repository root for full licence text.
  */
 
-use crate::game_settings::{AttackSettings, BoardSettings};
+use crate::game_settings::{AttackSettings, AttackTier, B2bMode, BoardSettings, ComboMode, TSpinSettings};
+use crate::piece::{Piece, PieceType, RotationState};
 use crate::primitives::Point;
 use crate::replays::MoveResult;
-use crate::scoring::{damage_mods, has_flag, thresholds, TSpinStatus};
+use crate::scoring::{damage_mods, has_flag, TSpinStatus};
+
+/// Returns the attack of the highest tier reached by `count`.
+pub fn get_tier_attack(tiers: &[AttackTier], count: u32) -> u32 {
+    tiers
+        .iter()
+        .filter(|tier| count >= tier.min)
+        .max_by_key(|tier| tier.min)
+        .map_or(0, |tier| tier.attack)
+}
 
 
 pub fn calculate_damage(attack_settings: &AttackSettings, mv: &MoveResult) -> u32 {
@@ -18,21 +28,8 @@ pub fn calculate_damage(attack_settings: &AttackSettings, mv: &MoveResult) -> u3
 
     let mods = mv.mod_bits;
 
-    // COMBOS
-    if has_flag(mods, damage_mods::COMBO_1) { attack += attack_settings.combos[0]; }
-    if has_flag(mods, damage_mods::COMBO_2) { attack += attack_settings.combos[1]; }
-    if has_flag(mods, damage_mods::COMBO_3) { attack += attack_settings.combos[2]; }
-    if has_flag(mods, damage_mods::COMBO_4) { attack += attack_settings.combos[3]; }
-    if has_flag(mods, damage_mods::COMBO_5) { attack += attack_settings.combos[4]; }
-
-    if has_flag(mods, damage_mods::ALL_CLEAR) { attack += attack_settings.all_clear; }
-
     // B2Bs
-    if has_flag(mods, damage_mods::B2B_1) { attack += attack_settings.b2bs[0]; }
-    if has_flag(mods, damage_mods::B2B_2) { attack += attack_settings.b2bs[1]; }
-    if has_flag(mods, damage_mods::B2B_3) { attack += attack_settings.b2bs[2]; }
-    if has_flag(mods, damage_mods::B2B_4) { attack += attack_settings.b2bs[3]; }
-    if has_flag(mods, damage_mods::B2B_5) { attack += attack_settings.b2bs[4]; }
+    if has_flag(mods, damage_mods::B2B) { attack += get_tier_attack(&attack_settings.b2bs, mv.b2b); }
 
     // T-SPINS
     if has_flag(mods, damage_mods::T_SPIN_FULL) {
@@ -51,6 +48,15 @@ pub fn calculate_damage(attack_settings: &AttackSettings, mv: &MoveResult) -> u3
         if has_flag(mods, damage_mods::SINGLE) {
             attack += attack_settings.t_spin_single_mini;
         }
+    } else if let Some(spin_attack) = get_spin_attack(attack_settings, mods) {
+        // ALL-SPINS
+        let lines = [damage_mods::SINGLE, damage_mods::DOUBLE, damage_mods::TRIPLE, damage_mods::QUAD]
+            .iter()
+            .position(|flag| has_flag(mods, *flag));
+
+        if let Some(lines) = lines {
+            attack += spin_attack[lines.min(spin_attack.len() - 1)];
+        }
     } else {
         // REGULAR ATTACKS
         if has_flag(mods, damage_mods::SINGLE) {
@@ -67,7 +73,47 @@ pub fn calculate_damage(attack_settings: &AttackSettings, mv: &MoveResult) -> u3
         }
     }
 
-    attack
+    // COMBOS
+    match attack_settings.combo_mode {
+        ComboMode::Table => {
+            if has_flag(mods, damage_mods::COMBO) { attack += get_tier_attack(&attack_settings.combos, mv.combo); }
+        }
+        ComboMode::Multiplier { factor } => {
+            // the first line clear does not count
+            let combo = mv.combo.saturating_sub(1) as f32;
+
+            attack = if attack == 0 {
+                (1.0 + 1.25 * combo).ln().floor() as u32
+            } else {
+                (attack as f32 * (1.0 + factor * combo)).floor() as u32
+            };
+        }
+    }
+
+    // B2B SURGE
+    if let B2bMode::Surge { threshold } = attack_settings.b2b_mode {
+        // the first move of the chain is not a B2B one
+        let chain = mv.broken_b2b.saturating_sub(1);
+        if chain >= threshold {
+            attack += chain;
+        }
+    }
+
+    if has_flag(mods, damage_mods::ALL_CLEAR) { attack += attack_settings.all_clear; }
+
+    // BADGES
+    attack * (100 + get_tier_attack(&attack_settings.badge_bonus, mv.badges)) / 100
+}
+
+/// Returns the attack table of the all-spin in `mods`, if any.
+fn get_spin_attack(attack_settings: &AttackSettings, mods: u32) -> Option<&[u32]> {
+    if has_flag(mods, damage_mods::S_SPIN) { return Some(&attack_settings.s_spin); }
+    if has_flag(mods, damage_mods::Z_SPIN) { return Some(&attack_settings.z_spin); }
+    if has_flag(mods, damage_mods::L_SPIN) { return Some(&attack_settings.l_spin); }
+    if has_flag(mods, damage_mods::J_SPIN) { return Some(&attack_settings.j_spin); }
+    if has_flag(mods, damage_mods::I_SPIN) { return Some(&attack_settings.i_spin); }
+
+    None
 }
 
 pub fn create_board_move_bits(
@@ -92,16 +138,8 @@ pub fn create_board_move_bits(
         res |= damage_mods::QUAD;
     }
 
-    if mv.combo > thresholds::COMBO_1_THRESHOLD {
-        res |= damage_mods::COMBO_1;
-    } else if mv.combo >= thresholds::COMBO_2_THRESHOLD {
-        res |= damage_mods::COMBO_2;
-    } else if mv.combo >= thresholds::COMBO_3_THRESHOLD {
-        res |= damage_mods::COMBO_3;
-    } else if mv.combo >= thresholds::COMBO_4_THRESHOLD {
-        res |= damage_mods::COMBO_4;
-    } else if mv.combo >= thresholds::COMBO_5_THRESHOLD {
-        res |= damage_mods::COMBO_5;
+    if mv.combo >= 2 {
+        res |= damage_mods::COMBO;
     }
 
     match t_spin_status {
@@ -114,24 +152,45 @@ pub fn create_board_move_bits(
         }
     }
 
-    if mv.b2b > thresholds::B2B_1_THRESHOLD {
-        res |= damage_mods::B2B_1;
-    } else if mv.b2b >= thresholds::B2B_2_THRESHOLD {
-        res |= damage_mods::B2B_2;
-    } else if mv.b2b >= thresholds::B2B_3_THRESHOLD {
-        res |= damage_mods::B2B_3;
-    } else if mv.b2b >= thresholds::B2B_4_THRESHOLD {
-        res |= damage_mods::B2B_4;
-    } else if mv.b2b >= thresholds::B2B_5_THRESHOLD {
-        res |= damage_mods::B2B_5;
+    match mv.hard_drop_info.spin {
+        Some(PieceType::S) => res |= damage_mods::S_SPIN,
+        Some(PieceType::Z) => res |= damage_mods::Z_SPIN,
+        Some(PieceType::L) => res |= damage_mods::L_SPIN,
+        Some(PieceType::J) => res |= damage_mods::J_SPIN,
+        Some(PieceType::I) => res |= damage_mods::I_SPIN,
+        _ => {}
+    }
+
+    if mv.b2b >= 2 {
+        res |= damage_mods::B2B;
     }
 
     res
 }
 
-pub fn check_t_overhang<F>(board_settings: &BoardSettings, piece_x: i32, piece_y: i32, not_empty_func: F) -> TSpinStatus
+/// Checks if the T piece is put into a T-Spin position using the 3-corner rule.
+///
+/// At least 3 of the 4 corners around the center of the piece must be occupied.
+/// It is a Full T-Spin if both of the front corners, which the piece points to, are occupied,
+/// or if the last rotation used the kick test `TSpinSettings::upgrade_kick`. Otherwise it is a Mini.
+///
+/// The piece must have got to its position by a rotation, which is checked by the caller.
+pub fn check_t_spin<F>(
+    t_spin_settings: &TSpinSettings,
+    board_settings: &BoardSettings,
+    piece: &Piece,
+    kick_index: usize,
+    not_empty_func: F
+) -> TSpinStatus
     where F: Fn(Point) -> bool {
 
+    if !t_spin_settings.enabled {
+        return TSpinStatus::None;
+    }
+
+    let piece_x = piece.get_x() as i32;
+    let piece_y = piece.get_y() as i32;
+
     let point_arr = [
         // TOP LEFT
         Point::new(piece_x - 1, piece_y - 1),
@@ -143,24 +202,202 @@ pub fn check_t_overhang<F>(board_settings: &BoardSettings, piece_x: i32, piece_y
         Point::new(piece_x + 1, piece_y + 1)
     ];
 
-    let mut oob_overhangs = 0;
-    let mut non_oob_overhangs = 0;
+    let front_corners = match piece.current_rotation {
+        RotationState::Initial => [0, 1],
+        RotationState::Clockwise => [1, 3],
+        RotationState::Deg180 => [2, 3],
+        RotationState::CounterClockwise => [0, 2]
+    };
 
-    for p in point_arr {
+    let occupied = point_arr.map(|p| {
         if crate::utils::is_oob(p.x, p.y, board_settings.width as i32, board_settings.full_height() as i32) {
-            oob_overhangs += 1;
-        } else if not_empty_func(p) {
-            non_oob_overhangs += 1;
+            t_spin_settings.walls_are_corners
+        } else {
+            not_empty_func(p)
         }
-    }
+    });
 
-    if oob_overhangs > 0 && non_oob_overhangs > 0 {
-        return TSpinStatus::Mini;
+    if occupied.iter().filter(|c| **c).count() < 3 {
+        return TSpinStatus::None;
     }
 
-    if oob_overhangs == 0 && non_oob_overhangs >= 3 {
+    if front_corners.iter().all(|i| occupied[*i]) || t_spin_settings.upgrade_kick == Some(kick_index) {
         return TSpinStatus::Full;
     }
 
-    TSpinStatus::None
+    if t_spin_settings.minis {
+        TSpinStatus::Mini
+    } else {
+        TSpinStatus::None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replays::{HardDropInfo, LastMoveType};
+    use crate::scoring::ScoringMgr;
+
+    fn spin_move(piece_type: PieceType, lines_cleared: u32) -> MoveResult {
+        MoveResult {
+            hard_drop_info: HardDropInfo {
+                lines_cleared,
+                last_move_type: LastMoveType::Rotation,
+                spin: Some(piece_type),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn all_spins_use_own_attack() {
+        let attack_settings = AttackSettings {
+            s_spin: [1, 2, 3],
+            z_spin: [4, 5, 6],
+            l_spin: [7, 8, 9],
+            j_spin: [10, 11, 12],
+            i_spin: [13, 14, 15, 16],
+            ..Default::default()
+        };
+
+        let cases = [
+            (PieceType::S, 2, damage_mods::S_SPIN, 2),
+            (PieceType::Z, 1, damage_mods::Z_SPIN, 4),
+            (PieceType::L, 3, damage_mods::L_SPIN, 9),
+            (PieceType::J, 2, damage_mods::J_SPIN, 11),
+            (PieceType::I, 4, damage_mods::I_SPIN, 16),
+        ];
+
+        for (piece_type, lines, flag, attack) in cases {
+            let mut mv = spin_move(piece_type, lines);
+            mv.mod_bits = create_board_move_bits(10, &mv, TSpinStatus::None);
+
+            assert!(has_flag(mv.mod_bits, flag), "{:?}", piece_type);
+            assert_eq!(calculate_damage(&attack_settings, &mv), attack, "{:?}", piece_type);
+        }
+
+        // a spin without cleared lines does not attack
+        let mut mv = spin_move(PieceType::S, 0);
+        mv.mod_bits = create_board_move_bits(10, &mv, TSpinStatus::None);
+        assert_eq!(calculate_damage(&attack_settings, &mv), 0);
+    }
+
+    fn clear_move(lines_cleared: u32, combo: u32, b2b: u32) -> MoveResult {
+        let mut mv = MoveResult {
+            combo,
+            b2b,
+            hard_drop_info: HardDropInfo {
+                lines_cleared,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        mv.mod_bits = create_board_move_bits(10, &mv, TSpinStatus::None);
+
+        mv
+    }
+
+    #[test]
+    fn every_default_tier_is_reached() {
+        let attack_settings = AttackSettings::default();
+
+        let mut prev = 0;
+        for tier in &attack_settings.combos {
+            // singles do not attack on their own
+            assert_eq!(calculate_damage(&attack_settings, &clear_move(1, tier.min - 1, 0)), prev);
+            assert_eq!(calculate_damage(&attack_settings, &clear_move(1, tier.min, 0)), tier.attack);
+            prev = tier.attack;
+        }
+        // the highest tier used to be unreachable
+        assert_eq!(calculate_damage(&attack_settings, &clear_move(1, 20, 0)), 5);
+
+        let mut prev = 0;
+        for tier in &attack_settings.b2bs {
+            let quad = attack_settings.lines_4;
+            assert_eq!(calculate_damage(&attack_settings, &clear_move(4, 1, tier.min - 1)), quad + prev);
+            assert_eq!(calculate_damage(&attack_settings, &clear_move(4, 1, tier.min)), quad + tier.attack);
+            prev = tier.attack;
+        }
+        assert_eq!(calculate_damage(&attack_settings, &clear_move(4, 1, 100)), attack_settings.lines_4 + 5);
+    }
+
+    #[test]
+    fn guideline_and_ppt_combo_tables() {
+        let guideline = AttackSettings::guideline();
+        let ppt = AttackSettings::ppt();
+
+        let guideline_attack: Vec<u32> = (1..=12)
+            .map(|combo| calculate_damage(&guideline, &clear_move(1, combo, 0)))
+            .collect();
+        assert_eq!(guideline_attack, [0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5, 5]);
+
+        let ppt_attack: Vec<u32> = (1..=12)
+            .map(|combo| calculate_damage(&ppt, &clear_move(1, combo, 0)))
+            .collect();
+        assert_eq!(ppt_attack, [0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5]);
+
+        // B2B is a flat bonus
+        assert_eq!(calculate_damage(&guideline, &clear_move(4, 1, 2)), 5);
+        assert_eq!(calculate_damage(&ppt, &clear_move(4, 1, 50)), 5);
+    }
+
+    #[test]
+    fn tetr_io_b2b_levels() {
+        let attack_settings = AttackSettings::tetr_io();
+        let expected = [(1, 0), (2, 1), (3, 1), (4, 2), (8, 2), (9, 3), (24, 3), (25, 4), (67, 4), (68, 5), (186, 6), (505, 7), (1371, 8)];
+
+        for (b2b, bonus) in expected {
+            assert_eq!(calculate_damage(&attack_settings, &clear_move(4, 1, b2b)), 4 + bonus, "B2B {}", b2b);
+        }
+    }
+
+    #[test]
+    fn tetr_io_combo_multiplier() {
+        let attack_settings = AttackSettings::tetr_io();
+
+        assert_eq!(calculate_damage(&attack_settings, &clear_move(2, 5, 0)), 2);
+        assert_eq!(calculate_damage(&attack_settings, &clear_move(4, 3, 0)), 6);
+        assert_eq!(calculate_damage(&attack_settings, &clear_move(1, 1, 0)), 0);
+        assert_eq!(calculate_damage(&attack_settings, &clear_move(1, 3, 0)), 1);
+        assert_eq!(calculate_damage(&attack_settings, &clear_move(1, 9, 0)), 2);
+    }
+
+    #[test]
+    fn b2b_surge_is_released_when_broken() {
+        let quad = HardDropInfo { lines_cleared: 4, ..Default::default() };
+        let no_clear = HardDropInfo::default();
+        let single = HardDropInfo { lines_cleared: 1, ..Default::default() };
+
+        let mut scoring_mgr = ScoringMgr::new();
+        for _ in 0..6 {
+            scoring_mgr.hard_drop(&quad);
+            scoring_mgr.hard_drop(&no_clear);
+            assert_eq!(scoring_mgr.broken_b2b, 0);
+        }
+        scoring_mgr.hard_drop(&single);
+        assert_eq!(scoring_mgr.b2b, 0);
+        assert_eq!(scoring_mgr.broken_b2b, 6);
+
+        let mut mv = clear_move(1, scoring_mgr.combo, scoring_mgr.b2b);
+        mv.broken_b2b = scoring_mgr.broken_b2b;
+
+        let surge = |threshold| AttackSettings { b2b_mode: B2bMode::Surge { threshold }, ..Default::default() };
+        // 5 B2B moves in the chain
+        assert_eq!(calculate_damage(&surge(4), &mv), 5);
+        assert_eq!(calculate_damage(&surge(5), &mv), 5);
+        assert_eq!(calculate_damage(&surge(6), &mv), 0);
+        assert_eq!(calculate_damage(&AttackSettings::default(), &mv), 0);
+    }
+
+    #[test]
+    fn badges_multiply_attack() {
+        let attack_settings = AttackSettings::default();
+        let quad_with_badges = |badges| MoveResult { badges, ..clear_move(4, 1, 0) };
+
+        let expected = [(0, 4), (1, 4), (2, 5), (6, 6), (14, 7), (30, 8), (100, 8)];
+        for (badges, attack) in expected {
+            assert_eq!(calculate_damage(&attack_settings, &quad_with_badges(badges)), attack, "{} badges", badges);
+        }
+    }
 }
@@ -0,0 +1,232 @@
+/*
+ * Copyright (c) Grigory Alfyorov. Licensed under the MIT License.
+ * See the LICENSE file in the repository root for full licence text.
+ */
+
+use serde::{Deserialize, Serialize};
+use crate::game_settings::PointsSettings;
+use crate::replays::BoardStats;
+
+/// Goal of a game. The game is finished once the goal is completed or the player tops out.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum GameMode {
+    /// No goal, the game lasts until the player tops out.
+    #[default]
+    Versus,
+    /// Clear `lines` lines as fast as possible.
+    Sprint { lines: u32 },
+    /// Score as many points as possible in `duration_sec` seconds. The level rises with the lines cleared.
+    Blitz { duration_sec: f32 },
+    /// Score as many points as possible in `duration_sec` seconds at a constant level.
+    Ultra { duration_sec: f32 },
+    /// Clear the lines of every level up to `max_level`.
+    Marathon { max_level: u32 },
+    /// Clear `lines` rows of messy garbage. Up to `CHEESE_VISIBLE_ROWS` of them are on the board at once.
+    CheeseRace { lines: u32 },
+    /// Survive as long as possible while `rows` rows of garbage arrive every `interval_sec` seconds.
+    /// `messiness` is the chance from 0 to 1 the hole changes between two rows.
+    Survival { interval_sec: f32, rows: u32, messiness: f32 }
+}
+
+impl GameMode {
+    /// 40 lines sprint.
+    pub fn sprint() -> Self {
+        Self::Sprint { lines: 40 }
+    }
+
+    /// 2 minutes blitz.
+    pub fn blitz() -> Self {
+        Self::Blitz { duration_sec: 120.0 }
+    }
+
+    /// 3 minutes ultra.
+    pub fn ultra() -> Self {
+        Self::Ultra { duration_sec: 180.0 }
+    }
+
+    /// 15 levels marathon.
+    pub fn marathon() -> Self {
+        Self::Marathon { max_level: 15 }
+    }
+
+    /// 100 lines cheese race.
+    pub fn cheese_race() -> Self {
+        Self::CheeseRace { lines: 100 }
+    }
+
+    /// Survival with clean garbage arriving slowly.
+    pub fn survival() -> Self {
+        Self::Survival { interval_sec: 3.0, rows: 2, messiness: 0.2 }
+    }
+
+    /// Survival with a row of messy garbage arriving every second, the player has to keep digging.
+    pub fn dig() -> Self {
+        Self::Survival { interval_sec: 1.0, rows: 1, messiness: 1.0 }
+    }
+
+    /// Returns the points scoring used by the mode, `None` if the mode doesn't score any points.
+    pub fn points_settings(&self) -> Option<PointsSettings> {
+        match *self {
+            GameMode::Versus
+            | GameMode::Sprint { .. }
+            | GameMode::CheeseRace { .. }
+            | GameMode::Survival { .. } => None,
+            GameMode::Blitz { .. } => Some(PointsSettings {
+                max_level: 30,
+                ..Default::default()
+            }),
+            GameMode::Ultra { .. } => Some(PointsSettings {
+                lines_per_level: 0,
+                level_gravity: false,
+                ..Default::default()
+            }),
+            GameMode::Marathon { max_level } => Some(PointsSettings {
+                max_level,
+                ..Default::default()
+            })
+        }
+    }
+
+    /// Returns `true` if the goal of the mode is completed. `points` are the points settings of the game.
+    pub fn is_completed(&self, stats: &BoardStats, points: Option<&PointsSettings>) -> bool {
+        match *self {
+            GameMode::Versus | GameMode::Survival { .. } => false,
+            GameMode::Sprint { lines } => stats.lines >= lines,
+            GameMode::CheeseRace { lines } => stats.garbage_cleared >= lines,
+            GameMode::Blitz { duration_sec } | GameMode::Ultra { duration_sec } => stats.elapsed_seconds >= duration_sec,
+            GameMode::Marathon { max_level } => {
+                let points = points.copied().unwrap_or_default();
+                let levels = (max_level + 1).saturating_sub(points.start_level);
+
+                points.lines_per_level > 0 && stats.lines >= levels * points.lines_per_level
+            }
+        }
+    }
+
+    /// Returns `true` if the mode is played against the clock rather than for a goal.
+    pub fn is_timed(&self) -> bool {
+        matches!(self, GameMode::Blitz { .. } | GameMode::Ultra { .. })
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GameOutcome {
+    /// The goal of the mode is completed.
+    Completed,
+    /// The player has topped out before completing the goal.
+    ToppedOut
+}
+
+/// Final result of a finished game.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GameResult {
+    pub game_mode: GameMode,
+    pub outcome: GameOutcome,
+    /// Time the game has lasted in seconds.
+    pub time_sec: f32,
+    pub lines: u32,
+    pub garbage_cleared: u32,
+    pub score: u64,
+    pub level: u32,
+    pub total_pieces: u32
+}
+
+impl GameResult {
+    pub fn new(game_mode: GameMode, outcome: GameOutcome, stats: &BoardStats) -> Self {
+        let time_sec = match game_mode {
+            GameMode::Blitz { duration_sec } | GameMode::Ultra { duration_sec } => stats.elapsed_seconds.min(duration_sec),
+            _ => stats.elapsed_seconds
+        };
+
+        Self {
+            game_mode,
+            outcome,
+            time_sec,
+            lines: stats.lines,
+            garbage_cleared: stats.garbage_cleared,
+            score: stats.score,
+            level: stats.level,
+            total_pieces: stats.total_pieces
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use super::*;
+    use crate::board::Board;
+    use crate::game_settings::GameSettings;
+    use crate::piece_mgr::BoardErrorReason;
+    use crate::time_mgr::TimeMgr;
+    use crate::wall_kick_data::WallKickData;
+
+    fn create_board(game_mode: GameMode) -> Board {
+        let game_settings = GameSettings::for_game_mode(game_mode);
+        let wkd = Arc::new(WallKickData::new(&game_settings.wall_kick_data_mode));
+
+        Board::new(game_settings, wkd, 1337)
+    }
+
+    #[test]
+    fn goals_are_completed() {
+        let stats = BoardStats {
+            lines: 40,
+            elapsed_seconds: 100.0,
+            ..Default::default()
+        };
+
+        assert!(GameMode::sprint().is_completed(&stats, None));
+        assert!(!GameMode::Sprint { lines: 41 }.is_completed(&stats, None));
+        assert!(!GameMode::blitz().is_completed(&stats, None));
+        assert!(GameMode::Ultra { duration_sec: 100.0 }.is_completed(&stats, None));
+        assert!(!GameMode::Versus.is_completed(&stats, None));
+
+        // levels 3 and 4 are cleared with 20 lines each
+        let points = PointsSettings {
+            start_level: 3,
+            lines_per_level: 20,
+            ..Default::default()
+        };
+        assert!(GameMode::Marathon { max_level: 4 }.is_completed(&stats, Some(&points)));
+        assert!(!GameMode::Marathon { max_level: 5 }.is_completed(&stats, Some(&points)));
+    }
+
+    #[test]
+    fn timed_mode_finishes_board() {
+        let mut board = create_board(GameMode::Blitz { duration_sec: 10.0 });
+        assert_eq!(board.board_stats.level, 1);
+
+        let mut time_mgr = TimeMgr::new();
+        time_mgr.update(5.0);
+        board.update(&time_mgr);
+        assert!(!board.is_finished());
+
+        time_mgr.update(6.0);
+        assert!(matches!(board.update(&time_mgr), Some(Err(BoardErrorReason::GameFinished))));
+        assert!(matches!(board.hard_drop(), Err(BoardErrorReason::GameFinished)));
+
+        let result = board.board_stats.game_result.unwrap();
+        assert_eq!(result.outcome, GameOutcome::Completed);
+        assert_eq!(result.time_sec, 10.0);
+
+        board.reset(None);
+        assert!(!board.is_finished());
+    }
+
+    #[test]
+    fn top_out_finishes_board() {
+        let mut board = create_board(GameMode::sprint());
+
+        let mut pieces = 0;
+        while board.hard_drop().is_ok() {
+            pieces += 1;
+            assert!(pieces < 100);
+        }
+
+        assert!(board.is_dead);
+        let result = board.board_stats.game_result.unwrap();
+        assert_eq!(result.outcome, GameOutcome::ToppedOut);
+        assert_eq!(result.total_pieces, pieces);
+    }
+}
@@ -4,6 +4,8 @@
  */
 
 use serde::{Deserialize, Serialize};
+use crate::piece_generators::PieceGeneratorType;
+use crate::game_mode::GameMode;
 use crate::wall_kick_data::{WallKickDataMode};
 
 //pub const BOARD_WIDTH: usize = 10;
@@ -31,7 +33,102 @@ impl Default for GravitySettings {
     }
 }
 
+/// Attack bonus for `min` or more combo or back-to-back moves in a row.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttackTier {
+    pub min: u32,
+    pub attack: u32
+}
+
+/// Creates the tiers from `(min, attack)` pairs.
+pub fn attack_tiers(tiers: &[(u32, u32)]) -> Vec<AttackTier> {
+    tiers
+        .iter()
+        .map(|(min, attack)| AttackTier { min: *min, attack: *attack })
+        .collect()
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub enum ComboMode {
+    /// The attack of the highest reached tier of `AttackSettings::combos` is added.
+    #[default]
+    Table,
+    /// The attack is multiplied by `1 + factor * combo`, like in TETR.IO.
+    /// Clears which send nothing on their own send `ln(1 + 1.25 * combo)` instead.
+    Multiplier { factor: f32 }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum B2bMode {
+    /// The attack of the highest reached tier of `AttackSettings::b2bs` is added to every B2B move.
+    #[default]
+    Chain,
+    /// Same as `Chain`, but the chain is also charged: when a chain of at least `threshold`
+    /// B2B moves is broken, the number of the B2B moves in it is sent at once.
+    Surge { threshold: u32 }
+}
+
+/// How the holes are placed within the rows of a single attack.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GarbageHoleMode {
+    /// All of the rows of an attack share the same hole.
+    #[default]
+    Clean,
+    /// The hole may move between the rows of an attack with the chance `GarbageSettings::row_messiness`.
+    Cheese
+}
+
+/// How the outgoing attack interacts with the incoming garbage.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GarbagePolicy {
+    /// The attack cancels the incoming garbage and only the rest of it is sent to the opponents.
+    #[default]
+    Cancelling,
+    /// The attack is sent in full without cancelling anything. Clearing lines only holds
+    /// the incoming garbage back until a piece is placed without clearing any.
+    Blocking
+}
+
+/// Rules of the garbage holes.
 #[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GarbageSettings {
+    pub hole_mode: GarbageHoleMode,
+    /// Chance from 0 to 1 the hole moves between two attacks.
+    pub attack_messiness: f32,
+    /// Chance from 0 to 1 the hole moves between two rows of an attack in the `Cheese` mode.
+    pub row_messiness: f32
+}
+
+impl Default for GarbageSettings {
+    fn default() -> Self {
+        Self {
+            hole_mode: GarbageHoleMode::Clean,
+            attack_messiness: 1.0,
+            row_messiness: 0.0
+        }
+    }
+}
+
+impl GarbageSettings {
+    /// The hole rarely moves, so the garbage can be downstacked in one column.
+    pub fn clean() -> Self {
+        Self {
+            attack_messiness: 0.2,
+            ..Default::default()
+        }
+    }
+
+    /// Every attack has a new hole, which also moves between the rows half of the time.
+    pub fn messy() -> Self {
+        Self {
+            hole_mode: GarbageHoleMode::Cheese,
+            attack_messiness: 1.0,
+            row_messiness: 0.5
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AttackSettings {
     pub lines_0: u32,
     pub lines_1: u32,
@@ -43,9 +140,49 @@ pub struct AttackSettings {
     pub t_spin_triple: u32,
     pub t_spin_single_mini: u32,
     pub all_clear: u32,
-    pub b2bs: [u32; 5],
-    pub combos: [u32; 5],
-    pub garbage_delay_ms: u32
+    /// Bonus for the B2B moves, sorted by `min`. The first difficult move of a chain counts as 1.
+    pub b2bs: Vec<AttackTier>,
+    /// Bonus for the combos, sorted by `min`. The first line clear of a combo counts as 1.
+    pub combos: Vec<AttackTier>,
+    #[serde(default)]
+    pub combo_mode: ComboMode,
+    #[serde(default)]
+    pub b2b_mode: B2bMode,
+    pub garbage_delay_ms: u32,
+    /// Attack of the spins of the pieces other than T by the number of the lines cleared,
+    /// used if `GameSettings::spin_rules` is `AllSpins`.
+    #[serde(default = "default_spin_attack")]
+    pub s_spin: [u32; 3],
+    #[serde(default = "default_spin_attack")]
+    pub z_spin: [u32; 3],
+    #[serde(default = "default_spin_attack")]
+    pub l_spin: [u32; 3],
+    #[serde(default = "default_spin_attack")]
+    pub j_spin: [u32; 3],
+    #[serde(default = "default_i_spin_attack")]
+    pub i_spin: [u32; 4],
+    #[serde(default)]
+    pub garbage: GarbageSettings,
+    #[serde(default)]
+    pub garbage_policy: GarbagePolicy,
+    /// Maximum number of the garbage rows inserted per piece placed. The rest stays in the queue.
+    #[serde(default)]
+    pub garbage_cap: Option<u32>,
+    /// Bonus of the outgoing attack in percent by the number of the badges collected for the KOs.
+    #[serde(default = "default_badge_bonus")]
+    pub badge_bonus: Vec<AttackTier>
+}
+
+fn default_spin_attack() -> [u32; 3] {
+    [2, 4, 6]
+}
+
+fn default_i_spin_attack() -> [u32; 4] {
+    [2, 4, 6, 8]
+}
+
+fn default_badge_bonus() -> Vec<AttackTier> {
+    attack_tiers(&[(2, 25), (6, 50), (14, 75), (30, 100)])
 }
 
 impl Default for AttackSettings {
@@ -61,9 +198,53 @@ impl Default for AttackSettings {
             t_spin_triple: 6,
             t_spin_single_mini: 1,
             all_clear: 10,
-            b2bs: [1,2,3,4,5],
-            combos: [1,2,3,4,5],
+            b2bs: attack_tiers(&[(2, 1), (5, 2), (10, 3), (30, 4), (60, 5)]),
+            combos: attack_tiers(&[(2, 1), (6, 2), (10, 3), (15, 4), (18, 5)]),
+            combo_mode: ComboMode::Table,
+            b2b_mode: B2bMode::Chain,
             garbage_delay_ms: 1000,
+            s_spin: default_spin_attack(),
+            z_spin: default_spin_attack(),
+            l_spin: default_spin_attack(),
+            j_spin: default_spin_attack(),
+            i_spin: default_i_spin_attack(),
+            garbage: GarbageSettings::default(),
+            garbage_policy: GarbagePolicy::default(),
+            garbage_cap: None,
+            badge_bonus: default_badge_bonus()
+        }
+    }
+}
+
+impl AttackSettings {
+    /// Attack of the modern guideline games: +1 for B2B and the combo table 0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5.
+    pub fn guideline() -> Self {
+        Self {
+            t_spin_single_mini: 0,
+            b2bs: attack_tiers(&[(2, 1)]),
+            combos: attack_tiers(&[(2, 1), (4, 2), (6, 3), (8, 4), (11, 5)]),
+            ..Default::default()
+        }
+    }
+
+    /// Attack of TETR.IO: combo multiplier and B2B levels.
+    pub fn tetr_io() -> Self {
+        Self {
+            t_spin_single_mini: 0,
+            b2bs: attack_tiers(&[(2, 1), (4, 2), (9, 3), (25, 4), (68, 5), (186, 6), (505, 7), (1371, 8)]),
+            combos: Vec::new(),
+            combo_mode: ComboMode::Multiplier { factor: 0.25 },
+            ..Default::default()
+        }
+    }
+
+    /// Attack of Puyo Puyo Tetris: +1 for B2B and the combo table 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5.
+    pub fn ppt() -> Self {
+        Self {
+            t_spin_single_mini: 0,
+            b2bs: attack_tiers(&[(2, 1)]),
+            combos: attack_tiers(&[(3, 1), (5, 2), (7, 3), (9, 4), (12, 5)]),
+            ..Default::default()
         }
     }
 }
@@ -89,12 +270,149 @@ impl BoardSettings {
     }
 }
 
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum HoldMode {
+    /// The hold is not available.
+    Disabled,
+    /// The piece can be held once until the next piece is placed.
+    #[default]
+    Once,
+    /// The pieces can be swapped with the hold piece any number of times.
+    Unlimited
+}
+
+/// Single-player points scoring and levels of the guideline games.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PointsSettings {
+    pub start_level: u32,
+    /// Number of the lines to clear to advance to the next level. 0 keeps the level constant.
+    pub lines_per_level: u32,
+    pub max_level: u32,
+    /// The gravity follows the guideline curve of the current level instead of rising with time.
+    pub level_gravity: bool
+}
+
+impl Default for PointsSettings {
+    fn default() -> Self {
+        Self {
+            start_level: 1,
+            lines_per_level: 10,
+            max_level: 15,
+            level_gravity: true
+        }
+    }
+}
+
+/// Rules of the T-Spin detection. By default they follow the guideline:
+/// at least 3 of the 4 corners around the center of the T piece must be occupied,
+/// and if one of the two corners the T piece points to is empty, it is a Mini.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TSpinSettings {
+    pub enabled: bool,
+    /// If disabled, the T-Spins which would be a Mini are not counted at all.
+    pub minis: bool,
+    /// Walls and the floor count as occupied corners.
+    pub walls_are_corners: bool,
+    /// Index of the wall kick test which upgrades a Mini to a Full T-Spin,
+    /// like the "TST kick" of SRS. `None` disables the upgrade.
+    pub upgrade_kick: Option<usize>
+}
+
+impl Default for TSpinSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            minis: true,
+            walls_are_corners: true,
+            upgrade_kick: Some(4)
+        }
+    }
+}
+
+/// Pieces which can score spins.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum SpinRules {
+    /// Only the T-Spins are detected.
+    #[default]
+    TSpinsOnly,
+    /// The S, Z, L, J and I pieces score spins if they can't move left, right or up
+    /// after the last rotation. The T piece still follows `TSpinSettings`.
+    AllSpins
+}
+
+/// Direction the piece moves in when both the left and the right keys are held.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum DirectionPriority {
+    /// The key pressed last wins.
+    #[default]
+    Last,
+    Left,
+    Right,
+    /// The piece doesn't move while both keys are held.
+    Neutral
+}
+
+/// Handling of the inputs of a player. Unlike `GameSettings`, every player has their own.
+/// All of the delays are in milliseconds.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HandlingSettings {
+    /// Delayed Auto Shift, time the movement key must be held for before the piece starts repeating the move.
+    pub das: f32,
+    /// Auto Repeat Rate, time between the repeated moves. 0 moves the piece to the wall instantly.
+    pub arr: f32,
+    /// DAS Cut Delay, time the auto repeat is paused for after a piece is placed or held,
+    /// so the charged DAS doesn't throw the next piece to the wall right away.
+    pub dcd: f32,
+    /// Soft Drop Factor, rows per second the soft drop moves the piece by,
+    /// which is `sdf` times faster than the gravity of level 1. 0 drops the piece to the floor instantly.
+    pub sdf: u32,
+    /// Rotations pause the auto repeat for `dcd` as well.
+    pub das_cut_on_rotation: bool,
+    pub priority: DirectionPriority
+}
+
+impl Default for HandlingSettings {
+    fn default() -> Self {
+        Self {
+            das: 128.0,
+            arr: 0.0,
+            dcd: 0.0,
+            sdf: 0,
+            das_cut_on_rotation: false,
+            priority: DirectionPriority::default()
+        }
+    }
+}
+
+/// Maximum number of the next pieces which can be shown to the player.
+pub const MAX_PREVIEW_SIZE: usize = 7;
+
+fn default_preview_size() -> usize {
+    5
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct GameSettings {
     pub gravity: GravitySettings,
     pub board: BoardSettings,
     pub attack: AttackSettings,
-    pub wall_kick_data_mode: WallKickDataMode
+    pub wall_kick_data_mode: WallKickDataMode,
+    #[serde(default)]
+    pub piece_generator: PieceGeneratorType,
+    /// Number of the next pieces shown to the player, from 0 to `MAX_PREVIEW_SIZE`.
+    #[serde(default = "default_preview_size")]
+    pub preview_size: usize,
+    #[serde(default)]
+    pub hold_mode: HoldMode,
+    #[serde(default)]
+    pub t_spin: TSpinSettings,
+    #[serde(default)]
+    pub spin_rules: SpinRules,
+    /// Enables the single-player points scoring. `None` for the versus games.
+    #[serde(default)]
+    pub points: Option<PointsSettings>,
+    #[serde(default)]
+    pub game_mode: GameMode
 }
 impl Default for GameSettings {
     fn default() -> Self {
@@ -102,7 +420,25 @@ impl Default for GameSettings {
             gravity: GravitySettings::default(),
             board: BoardSettings::default(),
             attack: AttackSettings::default(),
-            wall_kick_data_mode: WallKickDataMode::Standard
+            wall_kick_data_mode: WallKickDataMode::Standard,
+            piece_generator: PieceGeneratorType::default(),
+            preview_size: default_preview_size(),
+            hold_mode: HoldMode::default(),
+            t_spin: TSpinSettings::default(),
+            spin_rules: SpinRules::default(),
+            points: None,
+            game_mode: GameMode::default()
+        }
+    }
+}
+
+impl GameSettings {
+    /// Default settings of `game_mode` with the points scoring the mode is played with.
+    pub fn for_game_mode(game_mode: GameMode) -> Self {
+        Self {
+            points: game_mode.points_settings(),
+            game_mode,
+            ..Default::default()
         }
     }
 }
\ No newline at end of file
@@ -4,18 +4,23 @@
  */
 
 use crate::cell_holder::CellHolder;
-use crate::game_settings::AttackSettings;
+use crate::game_settings::{AttackSettings, GarbageHoleMode, GarbagePolicy};
+use crate::protocol::ConnId;
+use crate::rng_manager::GARBAGE_RNG_STREAM;
 use crate::time_mgr::TimeMgr;
 use rand::{Rng, SeedableRng};
 use rand_chacha::ChaCha8Rng;
 use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
 
-#[derive(Default, Debug, Copy, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub struct IncomingDamage {
     pub amount: i32,
     pub delay: u32,
     pub hole_x: u32,
+    /// Player who has sent the garbage, `None` if it doesn't come from an opponent.
+    #[serde(default)]
+    pub sender: Option<ConnId>,
 }
 
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
@@ -30,57 +35,147 @@ impl IncomingDamage {
             amount,
             delay: delay_ms,
             hole_x: 0,
+            sender: None,
         }
     }
 }
 
-#[derive(Debug)]
+/// Returns the hole of the next garbage row: it stays at `last_hole_x` or moves to another
+/// column with the chance `messiness` from 0 to 1. The first hole is placed randomly.
+pub(crate) fn next_hole_x(rng: &mut ChaCha8Rng, last_hole_x: Option<u32>, width: usize, messiness: f32) -> u32 {
+    let width = width as u32;
+
+    match last_hole_x {
+        Some(last) if width < 2 || rng.gen::<f32>() >= messiness => last,
+        Some(last) => {
+            // any column except the previous one
+            let x = rng.gen_range(0..width - 1);
+            if x >= last { x + 1 } else { x }
+        }
+        None => rng.gen_range(0..width)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct GarbageMgr {
     pub queue: VecDeque<IncomingDamage>,
     // used for generating garbage holes,
     rng: ChaCha8Rng,
+    seed: u64,
     last_garbage_x: Option<u32>,
+    /// Sender of the last garbage landed on the board. Credited with the KO if the player tops out.
+    last_sender: Option<ConnId>,
+    /// Part of the last frame shorter than a millisecond, which hasn't been subtracted from the delays yet.
+    dt_remainder_ms: f32,
     attack_settings: AttackSettings,
 }
 
 impl GarbageMgr {
-    pub fn new(attack_settings: &AttackSettings) -> Self {
+    /// Creates the manager generating the garbage holes from `seed`, which is the seed of the game.
+    pub fn new(attack_settings: &AttackSettings, seed: u64) -> Self {
         Self {
             queue: VecDeque::default(),
-            rng: SeedableRng::from_entropy(),
+            rng: create_rng(seed),
+            seed,
             last_garbage_x: None,
-            attack_settings: *attack_settings,
+            last_sender: None,
+            dt_remainder_ms: 0.0,
+            attack_settings: attack_settings.clone(),
+        }
+    }
+
+    /// Returns the number of the garbage rows in the queue.
+    pub fn get_queued_amount(&self) -> u32 {
+        self.queue.iter().map(|dmg| dmg.amount.max(0) as u32).sum()
+    }
+
+    /// Returns the player who has sent the last garbage landed on the board.
+    pub fn get_last_sender(&self) -> Option<ConnId> {
+        self.last_sender
+    }
+
+    /// Queues `damage` with the holes placed by `AttackSettings::garbage`.
+    /// Returns the queued damage, one entry per hole.
+    pub fn attack(&mut self, width: usize, damage: i32) -> Vec<IncomingDamage> {
+        self.attack_from(width, damage, None)
+    }
+
+    /// Same as `attack()`, but the garbage is marked as sent by `sender`.
+    pub fn attack_from(&mut self, width: usize, damage: i32, sender: Option<ConnId>) -> Vec<IncomingDamage> {
+        if damage <= 0 {
+            return vec![];
+        }
+
+        let garbage = self.attack_settings.garbage;
+        let mut hole_x = next_hole_x(&mut self.rng, self.last_garbage_x, width, garbage.attack_messiness);
+        let mut res = vec![IncomingDamage::new(0, self.attack_settings.garbage_delay_ms)];
+
+        for row in 0..damage {
+            if row > 0 && garbage.hole_mode == GarbageHoleMode::Cheese {
+                let next = next_hole_x(&mut self.rng, Some(hole_x), width, garbage.row_messiness);
+                if next != hole_x {
+                    hole_x = next;
+                    res.push(IncomingDamage::new(0, self.attack_settings.garbage_delay_ms));
+                }
+            }
+
+            let last = res.last_mut().unwrap();
+            last.amount += 1;
+            last.hole_x = hole_x;
         }
+
+        for dmg in res.iter_mut() {
+            dmg.sender = sender;
+        }
+
+        self.last_garbage_x = Some(hole_x);
+        self.queue.extend(res.iter().copied());
+
+        res
     }
 
-    pub fn attack(&mut self, width: usize, damage: i32) {
+    /// Queues `damage` with the hole at the specified x coordinate without the garbage delay,
+    /// so it lands on the board with the next piece placed.
+    pub fn attack_now(&mut self, damage: i32, hole_x: u32) {
+        if damage > 0 {
+            self.queue.push_back(IncomingDamage {
+                amount: damage,
+                delay: 0,
+                hole_x,
+                sender: None,
+            });
+        }
+    }
+
+    /// Queues `damage` with the hole at the specified x coordinate.
+    pub fn attack_at(&mut self, damage: i32, hole_x: u32) {
         if damage > 0 {
-            let hole_x = self.rng.gen_range(0..width) as u32;
             self.queue.push_back(IncomingDamage {
                 amount: damage,
                 delay: self.attack_settings.garbage_delay_ms,
                 hole_x,
+                sender: None,
             });
         }
     }
 
     /// Sends garbage onto current board with specified `amount` of garbage rows and `messiness`.
-    /// The higher the messiness, the more random the holes are.
+    /// Messiness is the chance in percent the hole moves between two rows.
     /// Messiness = 0 means that the hole will be at the same x coordinate within
-    /// pending garbage rows.
-    pub fn push_garbage(&mut self, amount: u32, _messiness: u32, cell_holder: &mut CellHolder) {
-        let width = cell_holder.width as u32;
-
-        let garbage_hole_x: u32 = if let Some(gx) = self.last_garbage_x {
-            // TODO: Use messiness
-            gx
-        } else {
-            self.rng.gen_range(0..width)
-        };
+    /// pending garbage rows. Returns the hole x coordinate of every row.
+    pub fn push_garbage(&mut self, amount: u32, messiness: u32, cell_holder: &mut CellHolder) -> Vec<u32> {
+        let messiness = messiness.min(100) as f32 / 100.0;
+        let mut holes = Vec::with_capacity(amount as usize);
 
         for _ in 0..amount {
-            cell_holder.push_garbage(garbage_hole_x);
+            let hole_x = next_hole_x(&mut self.rng, self.last_garbage_x, cell_holder.width, messiness);
+            cell_holder.push_garbage(hole_x);
+
+            self.last_garbage_x = Some(hole_x);
+            holes.push(hole_x);
         }
+
+        holes
     }
 
     pub fn push_garbage_at(&mut self, amount: u32, hole_x: u32, cell_holder: &mut CellHolder) {
@@ -89,114 +184,133 @@ impl GarbageMgr {
         }
     }
 
+    /// Resolves the incoming garbage when a piece is placed. If the piece has cleared lines,
+    /// `outgoing_damage` cancels the queued garbage according to `AttackSettings::garbage_policy`
+    /// and the garbage left is held back. Otherwise the garbage which delay has elapsed lands
+    /// on the board, up to `AttackSettings::garbage_cap` rows, and `out_damage` is the negated
+    /// number of the rows received.
     pub fn hard_drop(&mut self, lines_cleared: u32, outgoing_damage: i32) -> GarbageHardDropResult {
-        let mut result = GarbageHardDropResult {
-            in_damage_queue: vec![],
-            out_damage: outgoing_damage,
-        };
+        if lines_cleared > 0 {
+            let out_damage = match self.attack_settings.garbage_policy {
+                GarbagePolicy::Cancelling => self.cancel(outgoing_damage),
+                GarbagePolicy::Blocking => outgoing_damage
+            };
 
-        // if the queue is empty then deal damage to the enemies
-        if self.queue.is_empty() {
-            return result;
+            return GarbageHardDropResult {
+                in_damage_queue: vec![],
+                out_damage,
+            };
         }
 
-        // accumulate total incoming damage
-        let mut incoming_dmg = VecDeque::new();
-        loop {
-            let recv_dmg = self.queue.front();
-            if let Some(dmg) = recv_dmg {
-                if dmg.delay > 0 {
-                    break;
-                }
-
-                let recv_dmg = self.queue.pop_front().unwrap();
-                incoming_dmg.push_back(recv_dmg);
-            } else {
-                break;
-            }
+        let in_damage_queue = self.take_ready();
+        if let Some(sender) = in_damage_queue.iter().rev().find_map(|dmg| dmg.sender) {
+            self.last_sender = Some(sender);
         }
+        let received: i32 = in_damage_queue.iter().map(|dmg| dmg.amount).sum();
 
-        // if player hasn't cleared any lines, then push garbage onto his board
-        if lines_cleared == 0 {
-            return GarbageHardDropResult {
-                in_damage_queue: incoming_dmg.into_iter().collect(),
-                out_damage: 0,
-            };
+        GarbageHardDropResult {
+            in_damage_queue,
+            out_damage: -received,
         }
+    }
 
-        while !incoming_dmg.is_empty() {
-            let mut recv_dmg = incoming_dmg.pop_front().unwrap();
-            if recv_dmg.amount <= result.out_damage {
-                result.out_damage -= recv_dmg.amount;
-            } else {
-                recv_dmg.amount -= result.out_damage;
-                incoming_dmg.push_front(recv_dmg);
+    /// Advances the delays of the queued garbage by the last frame.
+    pub fn update(&mut self, time_mgr: &TimeMgr) {
+        // the fractions of a millisecond are carried over to the next frame
+        let dt_ms = time_mgr.last_dt.max(0.0) * 1000.0 + self.dt_remainder_ms;
+        let elapsed_ms = dt_ms.floor();
+        self.dt_remainder_ms = dt_ms - elapsed_ms;
 
-                result.out_damage = 0;
+        for dmg in self.queue.iter_mut() {
+            dmg.delay = dmg.delay.saturating_sub(elapsed_ms as u32);
+        }
+    }
 
-                for dmg in incoming_dmg {
-                    self.queue.push_front(dmg);
-                }
+    /// Cancels the queued garbage from the oldest one with `damage`. Returns the damage left.
+    fn cancel(&mut self, mut damage: i32) -> i32 {
+        while damage > 0 {
+            let Some(dmg) = self.queue.front_mut() else {
+                break;
+            };
 
-                return result;
+            if dmg.amount > damage {
+                dmg.amount -= damage;
+                return 0;
             }
+
+            damage -= dmg.amount;
+            self.queue.pop_front();
         }
 
-        // see if queue still contains damage entries with delay > 0
-        while !self.queue.is_empty() {
-            let dmg = self.queue.pop_front().unwrap();
-            result.out_damage -= dmg.amount;
-            if result.out_damage == 0 {
+        damage
+    }
+
+    /// Takes the garbage which delay has elapsed out of the queue, up to the garbage cap.
+    fn take_ready(&mut self) -> Vec<IncomingDamage> {
+        let mut cap = self.attack_settings.garbage_cap.map_or(i32::MAX, |cap| cap as i32);
+        let mut res = vec![];
+
+        while let Some(dmg) = self.queue.front_mut() {
+            if dmg.delay > 0 || cap == 0 {
                 break;
             }
-            if result.out_damage < 0 {
-                self.queue.push_front(IncomingDamage {
-                    amount: -result.out_damage,
-                    delay: dmg.delay,
-                    hole_x: dmg.hole_x,
-                });
-                result.out_damage = 0;
+
+            // the rest of the rows stay in the queue
+            if dmg.amount > cap {
+                dmg.amount -= cap;
+                res.push(IncomingDamage { amount: cap, ..*dmg });
                 break;
             }
+
+            cap -= dmg.amount;
+            res.extend(self.queue.pop_front());
         }
 
-        result
+        res
     }
 
-    pub fn update(&mut self, time_mgr: &TimeMgr) {
-        for dmg in self.queue.iter_mut() {
-            dmg.delay = dmg.delay.saturating_sub(time_mgr.elapsed_sec as u32);
+    pub fn reset(&mut self, new_seed: Option<u64>) {
+        if let Some(seed) = new_seed {
+            self.seed = seed;
         }
-    }
 
-    pub fn reset(&mut self) {
+        self.rng = create_rng(self.seed);
         self.queue.clear();
         self.last_garbage_x = None;
+        self.last_sender = None;
+        self.dt_remainder_ms = 0.0;
     }
 }
 
+fn create_rng(seed: u64) -> ChaCha8Rng {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    rng.set_stream(GARBAGE_RNG_STREAM);
+
+    rng
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-
-    const ATTACK_SETTINGS: AttackSettings = AttackSettings {
-        garbage_delay_ms: 500,
-        lines_0: 0,
-        lines_1: 0,
-        lines_2: 1,
-        lines_3: 2,
-        lines_4: 5,
-        t_spin_single_mini: 0,
-        t_spin_single: 2,
-        t_spin_double: 4,
-        t_spin_triple: 6,
-        all_clear: 8,
-        combos: [1, 2, 3, 4, 5],
-        b2bs: [1, 2, 3, 4, 5],
-    };
+    use crate::game_settings::{BoardSettings, GarbageSettings};
 
     fn create_garbage_mgr() -> GarbageMgr {
-        GarbageMgr::new(&ATTACK_SETTINGS)
+        let attack_settings = AttackSettings {
+            garbage_delay_ms: 500,
+            lines_0: 0,
+            lines_1: 0,
+            lines_2: 1,
+            lines_3: 2,
+            lines_4: 5,
+            t_spin_single_mini: 0,
+            t_spin_single: 2,
+            t_spin_double: 4,
+            t_spin_triple: 6,
+            all_clear: 8,
+            ..Default::default()
+        };
+
+        GarbageMgr::new(&attack_settings, 1337)
     }
 
     fn id(amount: i32, delay: u32) -> IncomingDamage {
@@ -204,6 +318,7 @@ mod tests {
             amount,
             delay,
             hole_x: 0,
+            sender: None,
         }
     }
 
@@ -347,7 +462,7 @@ mod tests {
         assert_eq!(g.queue[0].amount, 1);
 
         let mut tm = TimeMgr::new();
-        tm.update(100.0);
+        tm.update(0.1);
         g.update(&tm);
 
         let dmg = g.hard_drop(0, 0);
@@ -360,4 +475,159 @@ mod tests {
         assert_eq!(dmg.out_damage, 7);
         assert_eq!(g.queue.len(), 0);
     }
+
+    fn create_garbage_mgr_with(garbage: GarbageSettings, seed: u64) -> GarbageMgr {
+        let attack_settings = AttackSettings {
+            garbage,
+            ..Default::default()
+        };
+
+        GarbageMgr::new(&attack_settings, seed)
+    }
+
+    fn attack_holes(g: &mut GarbageMgr, damage: i32) -> Vec<u32> {
+        g.attack(10, damage)
+            .iter()
+            .flat_map(|dmg| std::iter::repeat(dmg.hole_x).take(dmg.amount as usize))
+            .collect()
+    }
+
+    #[test]
+    fn holes_are_seed_deterministic() {
+        let mut a = create_garbage_mgr_with(GarbageSettings::messy(), 42);
+        let mut b = create_garbage_mgr_with(GarbageSettings::messy(), 42);
+
+        let holes: Vec<Vec<u32>> = (1..8).map(|dmg| attack_holes(&mut a, dmg)).collect();
+        assert_eq!(holes, (1..8).map(|dmg| attack_holes(&mut b, dmg)).collect::<Vec<_>>());
+
+        a.reset(None);
+        assert_eq!(attack_holes(&mut a, 1), holes[0]);
+    }
+
+    #[test]
+    fn clean_attack_shares_one_hole() {
+        let garbage = GarbageSettings {
+            attack_messiness: 0.0,
+            ..Default::default()
+        };
+        let mut g = create_garbage_mgr_with(garbage, 42);
+
+        let first = g.attack(10, 4);
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].amount, 4);
+        // the hole never moves between the attacks
+        assert_eq!(attack_holes(&mut g, 3), vec![first[0].hole_x; 3]);
+        assert_eq!(g.queue.len(), 2);
+    }
+
+    #[test]
+    fn messy_attack_moves_hole() {
+        let garbage = GarbageSettings {
+            hole_mode: GarbageHoleMode::Cheese,
+            attack_messiness: 1.0,
+            row_messiness: 1.0
+        };
+        let mut g = create_garbage_mgr_with(garbage, 42);
+
+        let first = attack_holes(&mut g, 5);
+        assert!(first.windows(2).all(|w| w[0] != w[1]));
+        assert_eq!(g.queue.len(), 5);
+        assert_ne!(attack_holes(&mut g, 1)[0], first[4]);
+    }
+
+    #[test]
+    fn push_garbage_follows_messiness() {
+        let mut g = create_garbage_mgr();
+        let mut cell_holder = CellHolder::new(&BoardSettings::default());
+
+        let holes = g.push_garbage(3, 0, &mut cell_holder);
+        assert!(holes.iter().all(|x| *x == holes[0]));
+        // continues the hole of the previous garbage
+        assert_eq!(g.push_garbage(1, 0, &mut cell_holder), vec![holes[0]]);
+
+        let holes = g.push_garbage(4, 100, &mut cell_holder);
+        assert!(holes.windows(2).all(|w| w[0] != w[1]));
+        assert_eq!(cell_holder.get_garbage_row_count(), 8);
+    }
+
+    #[test]
+    fn delay_elapses_per_frame() {
+        let mut g = create_garbage_mgr();
+        g.attack_at(3, 0);
+
+        let mut tm = TimeMgr::new();
+        for _ in 0..29 {
+            tm.update(1.0 / 60.0);
+            g.update(&tm);
+        }
+        // 483 ms have passed
+        assert_eq!(g.queue[0].delay, 17);
+        assert_eq!(g.hard_drop(0, 0).out_damage, 0);
+
+        tm.update(1.0 / 60.0);
+        g.update(&tm);
+        assert_eq!(g.queue[0].delay, 0);
+        assert_eq!(g.hard_drop(0, 0).out_damage, -3);
+    }
+
+    #[test]
+    fn garbage_cap_keeps_rest_queued() {
+        let attack_settings = AttackSettings {
+            garbage_cap: Some(4),
+            ..Default::default()
+        };
+        let mut g = GarbageMgr::new(&attack_settings, 1337);
+        g.queue.push_back(id(3, 0));
+        g.queue.push_back(id(3, 0));
+        g.queue.push_back(id(2, 100));
+
+        let dmg = g.hard_drop(0, 0);
+        assert_eq!(dmg.out_damage, -4);
+        assert_eq!(dmg.in_damage_queue, vec![id(3, 0), id(1, 0)]);
+        assert_eq!(g.queue.len(), 2);
+        assert_eq!(g.queue[0], id(2, 0));
+
+        let dmg = g.hard_drop(0, 0);
+        assert_eq!(dmg.out_damage, -2);
+        assert_eq!(g.queue.len(), 1);
+    }
+
+    #[test]
+    fn blocking_policy_does_not_cancel() {
+        let attack_settings = AttackSettings {
+            garbage_policy: GarbagePolicy::Blocking,
+            ..Default::default()
+        };
+        let mut g = GarbageMgr::new(&attack_settings, 1337);
+        g.queue.push_back(id(3, 0));
+
+        // the line clear holds the garbage back, but the attack is sent in full
+        let dmg = g.hard_drop(2, 4);
+        assert_eq!(dmg.out_damage, 4);
+        assert_eq!(g.queue.len(), 1);
+        assert_eq!(g.queue[0].amount, 3);
+
+        let dmg = g.hard_drop(0, 0);
+        assert_eq!(dmg.out_damage, -3);
+        assert!(g.queue.is_empty());
+    }
+
+    #[test]
+    fn landed_garbage_keeps_sender() {
+        let mut g = create_garbage_mgr();
+        g.attack_from(10, 2, Some(3));
+        g.attack_at(1, 0);
+        assert_eq!(g.queue[0].sender, Some(3));
+        assert_eq!(g.get_last_sender(), None);
+
+        for dmg in g.queue.iter_mut() {
+            dmg.delay = 0;
+        }
+        // the garbage of no one doesn't take the KO from the opponent
+        g.hard_drop(0, 0);
+        assert_eq!(g.get_last_sender(), Some(3));
+
+        g.reset(None);
+        assert_eq!(g.get_last_sender(), None);
+    }
 }
@@ -7,6 +7,9 @@ use crate::game_settings::{GravitySettings};
 use crate::piece_mgr::PieceMgr;
 use crate::time_mgr::{TimeMgr};
 
+/// 20 rows per frame at 60 FPS.
+const MAX_GRAVITY: f32 = 20.0 * 60.0;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum GravityUpdateResult {
     None,
@@ -15,7 +18,7 @@ pub enum GravityUpdateResult {
     HardDrop
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct GravityMgr {
     pub(crate) cur_gravity: f32,
     pub(crate) cur_lock: f32,
@@ -25,6 +28,8 @@ pub struct GravityMgr {
     pub(crate) y_to_check: u32,
 
     gravity_settings: GravitySettings,
+    /// Level set by `set_level()`. The gravity doesn't rise with time while it is set.
+    level: Option<u32>,
     pub is_enabled: bool
 }
 
@@ -40,6 +45,7 @@ impl GravityMgr {
             y_needs_update: true,
             y_to_check: 0,
             gravity_settings: *gravity_settings,
+            level: None,
             is_enabled: true
         }
     }
@@ -60,6 +66,18 @@ impl GravityMgr {
 
         self.cur_gravity = self.gravity_settings.grav_base;
         self.cur_lock = self.gravity_settings.lock_delay;
+        self.level = None;
+    }
+
+    /// Sets the gravity of the guideline curve at `level`: a piece falls one row in
+    /// `(grav_base - (level - 1) * grav_incr) ^ (level - 1)` seconds, up to 20 rows per frame.
+    pub fn set_level(&mut self, level: u32) {
+        let level = level.max(1);
+        let base = (self.gravity_settings.grav_base - (level - 1) as f32 * self.gravity_settings.grav_incr).max(0.0);
+        let sec_per_row = base.powi(level as i32 - 1);
+
+        self.cur_gravity = (1.0 / sec_per_row).min(MAX_GRAVITY);
+        self.level = Some(level);
     }
 
     pub fn update(&mut self, piece_mgr: &PieceMgr, time_mgr: &TimeMgr) -> GravityUpdateResult {
@@ -98,7 +116,9 @@ impl GravityMgr {
             self.intermediate_y = 0.0;
         }
 
-        self.cur_gravity += self.gravity_settings.grav_incr * time_mgr.last_dt;
+        if self.level.is_none() {
+            self.cur_gravity += self.gravity_settings.grav_incr * time_mgr.last_dt;
+        }
 
         res
     }
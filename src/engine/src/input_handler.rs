@@ -0,0 +1,478 @@
+/*
+ * Copyright (c) Grigory Alfyorov. Licensed under the MIT License.
+ * See the LICENSE file in the repository root for full licence text.
+ */
+
+use serde::{Deserialize, Serialize};
+use crate::board_command::BoardMoveDir;
+use crate::game_settings::{BoardSettings, DirectionPriority, HandlingSettings};
+use crate::replays::MoveAction;
+
+/// Controls of the game.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InputKey {
+    Left,
+    Right,
+    SoftDrop,
+    HardDrop,
+    RotateCW,
+    RotateCCW,
+    Rotate180,
+    Hold
+}
+
+/// Converts the key presses and releases of a player into `MoveAction`s according to `HandlingSettings`.
+///
+/// The actions depend only on the timestamps of the events, not on how often `update()` is called,
+/// so the same inputs always produce the same moves on the client, in the replays and on the server.
+/// The events must be passed in the order they happened. All of the timestamps are in milliseconds.
+///
+/// The instant auto repeat (ARR 0) and the instant soft drop (SDF 0) take the piece to the wall
+/// or the floor once, then wait until the piece changes: it is placed, held or rotated.
+#[derive(Debug, Clone)]
+pub struct InputHandler {
+    settings: HandlingSettings,
+    /// Number of the moves which take any piece from one wall to another.
+    max_moves: u32,
+    max_drops: u32,
+    time: f64,
+    is_left_down: bool,
+    is_right_down: bool,
+    /// Direction pressed last, used by `DirectionPriority::Last`.
+    last_dir: Option<BoardMoveDir>,
+    /// Direction the piece is moving in, `None` if no movement key is held.
+    active_dir: Option<BoardMoveDir>,
+    /// Time of the next auto repeat move of `active_dir`. Infinite once the instant auto repeat is done.
+    repeat_at: f64,
+    /// Time of the next soft drop, `None` if the soft drop key is not held.
+    /// Infinite once the instant soft drop is done.
+    soft_drop_at: Option<f64>
+}
+
+impl InputHandler {
+    pub fn new(settings: HandlingSettings, board_settings: &BoardSettings) -> Self {
+        Self {
+            settings,
+            max_moves: board_settings.width.saturating_sub(1) as u32,
+            max_drops: board_settings.full_height() as u32,
+            time: 0.0,
+            is_left_down: false,
+            is_right_down: false,
+            last_dir: None,
+            active_dir: None,
+            repeat_at: 0.0,
+            soft_drop_at: None
+        }
+    }
+
+    pub fn get_settings(&self) -> &HandlingSettings {
+        &self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: HandlingSettings) {
+        self.settings = settings;
+        // the finished instant repeats may become timed ones
+        self.rearm(self.time);
+    }
+
+    /// Handles the press of `key` at `time`. Returns the actions up to and including the press.
+    pub fn key_down(&mut self, key: InputKey, time: f64) -> Vec<MoveAction> {
+        let mut actions = Vec::new();
+        self.advance(time, &mut actions);
+
+        match key {
+            InputKey::Left | InputKey::Right => {
+                let dir = if key == InputKey::Left { BoardMoveDir::Left } else { BoardMoveDir::Right };
+                if self.is_dir_down(dir) {
+                    return actions;
+                }
+
+                self.set_dir_down(dir, true);
+                self.last_dir = Some(dir);
+
+                let new_dir = self.resolve_dir();
+                if new_dir == Some(dir) && self.active_dir != new_dir {
+                    // the piece is moved once right away, then it waits for DAS
+                    actions.push(move_action(dir));
+                    self.repeat_at = self.time + self.settings.das as f64;
+                    self.rearm_soft_drop(self.time);
+                }
+                self.active_dir = new_dir;
+            }
+            InputKey::SoftDrop => {
+                if self.soft_drop_at.is_none() {
+                    self.soft_drop_at = Some(self.time);
+                    self.advance(time, &mut actions);
+                }
+            }
+            InputKey::HardDrop => {
+                actions.push(MoveAction::HardDrop);
+                self.rearm(self.time);
+                self.cut_das();
+            }
+            InputKey::Hold => {
+                actions.push(MoveAction::HoldPiece);
+                self.rearm(self.time);
+                self.cut_das();
+            }
+            InputKey::RotateCW | InputKey::RotateCCW | InputKey::Rotate180 => {
+                actions.push(match key {
+                    InputKey::RotateCW => MoveAction::RotateCW,
+                    InputKey::RotateCCW => MoveAction::RotateCCW,
+                    _ => MoveAction::RotateDeg180
+                });
+                self.rearm(self.time);
+
+                if self.settings.das_cut_on_rotation {
+                    self.cut_das();
+                }
+            }
+        }
+
+        actions
+    }
+
+    /// Handles the release of `key` at `time`. Returns the actions up to the release.
+    pub fn key_up(&mut self, key: InputKey, time: f64) -> Vec<MoveAction> {
+        let mut actions = Vec::new();
+        self.advance(time, &mut actions);
+
+        match key {
+            InputKey::Left | InputKey::Right => {
+                let dir = if key == InputKey::Left { BoardMoveDir::Left } else { BoardMoveDir::Right };
+                self.set_dir_down(dir, false);
+
+                if self.last_dir == Some(dir) {
+                    self.last_dir = [BoardMoveDir::Left, BoardMoveDir::Right]
+                        .into_iter()
+                        .find(|d| self.is_dir_down(*d));
+                }
+
+                let new_dir = self.resolve_dir();
+                if new_dir != self.active_dir {
+                    // the key which is still held takes over without the initial move
+                    self.repeat_at = self.time + self.settings.das as f64;
+                    self.active_dir = new_dir;
+                }
+            }
+            InputKey::SoftDrop => {
+                self.soft_drop_at = None;
+            }
+            _ => {}
+        }
+
+        actions
+    }
+
+    /// Returns the auto repeated actions up to `time`.
+    pub fn update(&mut self, time: f64) -> Vec<MoveAction> {
+        let mut actions = Vec::new();
+        self.advance(time, &mut actions);
+
+        actions
+    }
+
+    /// Tells the handler that a new piece has spawned without a key press, for example,
+    /// after the previous one was locked by gravity. The instant auto repeat and soft drop
+    /// of the held keys are applied to the new piece by the next `update()`.
+    pub fn piece_spawned(&mut self, time: f64) {
+        self.rearm(self.time.max(time));
+    }
+
+    /// Releases all of the keys.
+    pub fn reset(&mut self) {
+        self.time = 0.0;
+        self.is_left_down = false;
+        self.is_right_down = false;
+        self.last_dir = None;
+        self.active_dir = None;
+        self.repeat_at = 0.0;
+        self.soft_drop_at = None;
+    }
+
+    fn advance(&mut self, time: f64, actions: &mut Vec<MoveAction>) {
+        self.time = self.time.max(time);
+
+        let arr = self.settings.arr as f64;
+        let drop_interval = match self.settings.sdf {
+            0 => 0.0,
+            sdf => 1000.0 / sdf as f64
+        };
+
+        // the timed moves are more than enough to reach the wall or the floor, the rest is skipped
+        let mut moves = 0;
+        let mut drops = 0;
+        loop {
+            let move_at = self.active_dir
+                .map(|_| self.repeat_at)
+                .filter(|at| *at <= self.time && (arr == 0.0 || moves < self.max_moves));
+            let drop_at = self.soft_drop_at
+                .filter(|at| *at <= self.time && (drop_interval == 0.0 || drops < self.max_drops));
+
+            match (move_at, drop_at) {
+                (Some(move_at), drop_at) if drop_at.map_or(true, |drop_at| move_at <= drop_at) => {
+                    let action = move_action(self.active_dir.unwrap());
+                    if arr > 0.0 {
+                        actions.push(action);
+                        moves += 1;
+                        self.repeat_at += arr;
+                    } else {
+                        actions.extend(std::iter::repeat(action).take(self.max_moves as usize));
+                        self.repeat_at = f64::INFINITY;
+                    }
+
+                    // the piece may fall further after the move
+                    self.rearm_soft_drop(move_at);
+                }
+                (_, Some(drop_at)) => {
+                    if drop_interval > 0.0 {
+                        actions.push(MoveAction::SoftDrop);
+                        drops += 1;
+                        self.soft_drop_at = Some(drop_at + drop_interval);
+                        // the piece may move further after the drop. The instant drop doesn't do it,
+                        // otherwise the instant moves and drops would trigger each other forever
+                        self.rearm_auto_repeat(drop_at);
+                    } else {
+                        actions.extend(std::iter::repeat(MoveAction::SoftDrop).take(self.max_drops as usize));
+                        self.soft_drop_at = Some(f64::INFINITY);
+                    }
+                }
+                _ => break
+            }
+        }
+
+        if self.active_dir.is_some() && arr > 0.0 {
+            self.repeat_at = skip_until(self.repeat_at, arr, self.time);
+        }
+        if let (Some(drop_at), true) = (self.soft_drop_at, drop_interval > 0.0) {
+            self.soft_drop_at = Some(skip_until(drop_at, drop_interval, self.time));
+        }
+    }
+
+    /// Makes the finished instant auto repeat and soft drop happen again at `at`.
+    fn rearm(&mut self, at: f64) {
+        self.rearm_auto_repeat(at);
+        self.rearm_soft_drop(at);
+    }
+
+    fn rearm_auto_repeat(&mut self, at: f64) {
+        if self.active_dir.is_some() && self.repeat_at == f64::INFINITY {
+            self.repeat_at = at;
+        }
+    }
+
+    fn rearm_soft_drop(&mut self, at: f64) {
+        if self.soft_drop_at == Some(f64::INFINITY) {
+            self.soft_drop_at = Some(at);
+        }
+    }
+
+    /// Pauses the auto repeat for `dcd`.
+    fn cut_das(&mut self) {
+        if self.active_dir.is_some() && self.settings.dcd > 0.0 {
+            self.repeat_at = self.repeat_at.max(self.time + self.settings.dcd as f64);
+        }
+    }
+
+    fn resolve_dir(&self) -> Option<BoardMoveDir> {
+        match (self.is_left_down, self.is_right_down) {
+            (false, false) => None,
+            (true, false) => Some(BoardMoveDir::Left),
+            (false, true) => Some(BoardMoveDir::Right),
+            (true, true) => match self.settings.priority {
+                DirectionPriority::Last => self.last_dir,
+                DirectionPriority::Left => Some(BoardMoveDir::Left),
+                DirectionPriority::Right => Some(BoardMoveDir::Right),
+                DirectionPriority::Neutral => None
+            }
+        }
+    }
+
+    fn is_dir_down(&self, dir: BoardMoveDir) -> bool {
+        match dir {
+            BoardMoveDir::Left => self.is_left_down,
+            BoardMoveDir::Right => self.is_right_down
+        }
+    }
+
+    fn set_dir_down(&mut self, dir: BoardMoveDir, is_down: bool) {
+        match dir {
+            BoardMoveDir::Left => self.is_left_down = is_down,
+            BoardMoveDir::Right => self.is_right_down = is_down
+        }
+    }
+}
+
+fn move_action(dir: BoardMoveDir) -> MoveAction {
+    match dir {
+        BoardMoveDir::Left => MoveAction::MoveLeft,
+        BoardMoveDir::Right => MoveAction::MoveRight
+    }
+}
+
+/// Returns the first time after `time` in the series `at + n * interval`, or `at` if it is already after it.
+fn skip_until(at: f64, interval: f64, time: f64) -> f64 {
+    if at > time {
+        return at;
+    }
+
+    at + (((time - at) / interval).floor() + 1.0) * interval
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_handler(settings: HandlingSettings) -> InputHandler {
+        InputHandler::new(settings, &BoardSettings::default())
+    }
+
+    fn handling(das: f32, arr: f32) -> HandlingSettings {
+        HandlingSettings {
+            das,
+            arr,
+            ..Default::default()
+        }
+    }
+
+    fn count(actions: &[MoveAction], action: MoveAction) -> usize {
+        actions.iter().filter(|a| **a == action).count()
+    }
+
+    #[test]
+    fn das_then_arr() {
+        let mut handler = create_handler(handling(100.0, 20.0));
+
+        assert_eq!(handler.key_down(InputKey::Right, 0.0), vec![MoveAction::MoveRight]);
+        assert!(handler.update(99.0).is_empty());
+        assert_eq!(handler.update(100.0), vec![MoveAction::MoveRight]);
+        assert_eq!(handler.update(145.0).len(), 2);
+        assert_eq!(handler.key_up(InputKey::Right, 150.0).len(), 0);
+        assert!(handler.update(500.0).is_empty());
+    }
+
+    #[test]
+    fn instant_arr_and_soft_drop() {
+        let mut handler = create_handler(handling(100.0, 0.0));
+
+        handler.key_down(InputKey::Left, 0.0);
+        assert_eq!(count(&handler.update(100.0), MoveAction::MoveLeft), 9);
+
+        let actions = handler.key_down(InputKey::SoftDrop, 120.0);
+        assert_eq!(count(&actions, MoveAction::SoftDrop), 40);
+    }
+
+    #[test]
+    fn actions_do_not_depend_on_frame_rate() {
+        let timed = HandlingSettings {
+            das: 90.0,
+            arr: 15.0,
+            dcd: 30.0,
+            sdf: 40,
+            ..Default::default()
+        };
+        let events = [
+            (InputKey::Left, true, 0.0),
+            (InputKey::SoftDrop, true, 50.0),
+            (InputKey::HardDrop, true, 130.0),
+            (InputKey::Right, true, 160.0),
+            (InputKey::Left, false, 200.0),
+            (InputKey::SoftDrop, false, 210.0),
+            (InputKey::Right, false, 300.0)
+        ];
+
+        let play = |settings: HandlingSettings, frame: f64| {
+            let mut handler = create_handler(settings);
+            let mut actions = vec![];
+            let mut events = events.iter().peekable();
+            let mut time = 0.0;
+
+            while time <= 320.0 {
+                while let Some((key, is_down, _)) = events.next_if(|e| e.2 <= time) {
+                    actions.extend(if *is_down { handler.key_down(*key, time) } else { handler.key_up(*key, time) });
+                }
+                actions.extend(handler.update(time));
+                time += frame;
+            }
+
+            actions
+        };
+
+        // the events happen exactly on the frames of all of the rates
+        for settings in [
+            timed,
+            HandlingSettings { arr: 0.0, ..timed },
+            HandlingSettings { sdf: 0, ..timed },
+            HandlingSettings { arr: 0.0, sdf: 0, ..timed },
+            HandlingSettings::default()
+        ] {
+            let actions = play(settings, 10.0);
+            assert_eq!(actions, play(settings, 2.5), "{:?}", settings);
+            assert_eq!(actions, play(settings, 1.0), "{:?}", settings);
+        }
+    }
+
+    #[test]
+    fn instant_repeats_are_applied_once_per_piece() {
+        let mut handler = create_handler(HandlingSettings::default());
+
+        handler.key_down(InputKey::Left, 0.0);
+        handler.key_down(InputKey::SoftDrop, 0.0);
+        assert_eq!(count(&handler.update(200.0), MoveAction::MoveLeft), 9);
+        for time in 201..300 {
+            assert!(handler.update(time as f64).is_empty());
+        }
+
+        let actions = handler.key_down(InputKey::HardDrop, 300.0);
+        assert_eq!(actions, vec![MoveAction::HardDrop]);
+        let actions = handler.update(300.0);
+        assert_eq!(count(&actions, MoveAction::MoveLeft), 9);
+        assert_eq!(count(&actions, MoveAction::SoftDrop), 40);
+        assert!(handler.update(301.0).is_empty());
+
+        // the piece locked by gravity is moved as well
+        handler.piece_spawned(400.0);
+        assert_eq!(count(&handler.update(400.0), MoveAction::MoveLeft), 9);
+    }
+
+    #[test]
+    fn direction_priority() {
+        let mut handler = create_handler(handling(100.0, 50.0));
+        handler.key_down(InputKey::Left, 0.0);
+        assert_eq!(handler.key_down(InputKey::Right, 10.0), vec![MoveAction::MoveRight]);
+        // the left key takes over after DAS without the initial move
+        assert!(handler.key_up(InputKey::Right, 20.0).is_empty());
+        assert_eq!(handler.update(120.0), vec![MoveAction::MoveLeft]);
+
+        let mut handler = create_handler(HandlingSettings { priority: DirectionPriority::Left, ..handling(100.0, 50.0) });
+        handler.key_down(InputKey::Left, 0.0);
+        assert!(handler.key_down(InputKey::Right, 10.0).is_empty());
+        assert_eq!(handler.update(100.0), vec![MoveAction::MoveLeft]);
+
+        let mut handler = create_handler(HandlingSettings { priority: DirectionPriority::Neutral, ..handling(100.0, 50.0) });
+        handler.key_down(InputKey::Left, 0.0);
+        handler.key_down(InputKey::Right, 10.0);
+        assert!(handler.update(500.0).is_empty());
+    }
+
+    #[test]
+    fn das_cut_delays_auto_repeat() {
+        let settings = HandlingSettings {
+            dcd: 50.0,
+            das_cut_on_rotation: true,
+            ..handling(100.0, 0.0)
+        };
+        let mut handler = create_handler(settings);
+
+        handler.key_down(InputKey::Right, 0.0);
+        handler.update(100.0);
+        // the piece is kept at the wall until it is dropped
+        assert_eq!(handler.key_down(InputKey::HardDrop, 110.0).last(), Some(&MoveAction::HardDrop));
+        assert!(handler.update(159.0).is_empty());
+        assert_eq!(count(&handler.update(160.0), MoveAction::MoveRight), 9);
+
+        handler.key_down(InputKey::RotateCW, 170.0);
+        assert!(handler.update(200.0).is_empty());
+        assert!(!handler.update(220.0).is_empty());
+    }
+}
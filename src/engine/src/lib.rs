@@ -17,7 +17,18 @@ pub mod board_command;
 pub mod damage_calculation;
 pub mod game_settings;
 pub mod replays;
+pub mod replay_player;
+pub mod replay_file;
+pub mod protocol;
+pub mod board_sync;
+pub mod board_predictor;
 pub mod scoring;
+pub mod points_mgr;
+pub mod game_mode;
+pub mod practice_mgr;
+pub mod targeting;
+pub mod input_handler;
+pub mod move_gen;
 mod gravity_mgr;
 pub mod time_mgr;
 pub mod piece_mgr;
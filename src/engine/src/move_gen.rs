@@ -0,0 +1,385 @@
+/*
+ * Copyright (c) Grigory Alfyorov. Licensed under the MIT License.
+ * See the LICENSE file in the repository root for full licence text.
+ */
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use crate::cell_holder::CellType;
+use crate::piece::{Piece, PieceType, RotationDirection, RotationState};
+use crate::piece_mgr::PieceMgr;
+use crate::primitives::Point;
+use crate::replays::{LastMoveType, MoveAction};
+use crate::scoring::TSpinStatus;
+use crate::utils::adjust_positions_clone;
+use crate::wall_kick_data::WallKickData;
+
+/// Actions the search tries from every position, in the order of preference.
+const SEARCH_ACTIONS: [MoveAction; 6] = [
+    MoveAction::MoveLeft,
+    MoveAction::MoveRight,
+    MoveAction::RotateCW,
+    MoveAction::RotateCCW,
+    MoveAction::RotateDeg180,
+    MoveAction::SoftDrop
+];
+
+/// How far a soft drop moves the piece during the search.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DropMode {
+    /// One row, so the piece can be moved and rotated at every height.
+    Row,
+    /// All the way down, so the piece is only moved and rotated at the spawn height or when
+    /// resting on the stack. Misses the placements which need a move in the air, but is much faster.
+    Sonic
+}
+
+/// Position a piece can be locked at.
+#[derive(Debug, Clone)]
+pub struct Placement {
+    /// The piece at the position it locks at.
+    pub piece: Piece,
+    pub tspin_status: TSpinStatus,
+    /// Spin of the all-spin rules, see `SpinRules::AllSpins`.
+    pub spin: Option<PieceType>,
+    pub lines_cleared: u32,
+    /// Sequence of the actions which puts the piece there from its starting position,
+    /// using as few moves, rotations and drops as possible. Always ends with `MoveAction::HardDrop`.
+    pub actions: Vec<MoveAction>
+}
+
+/// Position of the piece during the search. A T piece which was rotated into the same position
+/// with a different kick may score a different T-Spin, so the kick is a part of the state.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+struct SearchKey {
+    x: u32,
+    y: u32,
+    rotation: RotationState,
+    /// Wall kick test of the last rotation, `None` if the last action was not a rotation.
+    last_kick: Option<usize>
+}
+
+/// Placements are told apart by the cells they occupy, as the pieces like O, I, S and Z
+/// occupy the same cells in different rotations.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct PlacementKey {
+    cells: Vec<(i32, i32)>,
+    tspin_status: TSpinStatus,
+    is_spin: bool
+}
+
+struct SearchNode {
+    piece: Piece,
+    last_kick: Option<usize>,
+    /// Index of the node the piece came from and the action which brought it here.
+    parent: Option<(usize, MoveAction)>
+}
+
+/// Lists every distinct position `piece` can be locked at on the board of `piece_mgr`
+/// using the moves, the rotations with the wall kicks of `wkd` and the soft drops of `drop_mode`.
+///
+/// Placing the piece the same way with and without a spin gives two different placements.
+/// The placements which would top out the board are left out.
+pub fn find_placements(piece_mgr: &PieceMgr, wkd: &WallKickData, piece: &Piece, drop_mode: DropMode) -> Vec<Placement> {
+    if piece_mgr.cell_holder.intersects_any(&get_cells(piece)) {
+        return vec![];
+    }
+
+    let mut nodes = vec![SearchNode { piece: *piece, last_kick: None, parent: None }];
+    let mut visited = HashSet::from([search_key(&nodes[0])]);
+    let mut queue = VecDeque::from([0]);
+
+    let mut placements = vec![];
+    let mut placement_keys = HashMap::new();
+
+    // the nodes are visited in the order of the number of actions, so the first path
+    // found to every placement is the shortest one, counting every drop as one action
+    while let Some(index) = queue.pop_front() {
+        let node = &nodes[index];
+
+        if let Some(placement) = lock(piece_mgr, node) {
+            let mut cells: Vec<(i32, i32)> = get_cells(&placement.piece).iter().map(|p| (p.x, p.y)).collect();
+            cells.sort_unstable();
+
+            let key = PlacementKey {
+                cells,
+                tspin_status: placement.tspin_status,
+                is_spin: placement.spin.is_some()
+            };
+
+            placement_keys.entry(key).or_insert_with(|| {
+                placements.push(Placement {
+                    actions: collect_actions(&nodes, index),
+                    ..placement
+                });
+            });
+        }
+
+        for action in SEARCH_ACTIONS {
+            let Some(next) = apply_action(piece_mgr, wkd, &nodes[index], action, drop_mode) else {
+                continue;
+            };
+
+            if visited.insert(search_key(&next)) {
+                nodes.push(SearchNode { parent: Some((index, action)), ..next });
+                queue.push_back(nodes.len() - 1);
+            }
+        }
+    }
+
+    placements
+}
+
+fn search_key(node: &SearchNode) -> SearchKey {
+    SearchKey {
+        x: node.piece.get_x(),
+        y: node.piece.get_y(),
+        rotation: node.piece.current_rotation,
+        last_kick: node.last_kick
+    }
+}
+
+fn apply_action(
+    piece_mgr: &PieceMgr,
+    wkd: &WallKickData,
+    node: &SearchNode,
+    action: MoveAction,
+    drop_mode: DropMode
+) -> Option<SearchNode> {
+    let mut piece = node.piece;
+
+    let last_kick = match action {
+        MoveAction::MoveLeft | MoveAction::MoveRight | MoveAction::SoftDrop => {
+            let (dx, dy) = match action {
+                MoveAction::MoveLeft => (-1, 0),
+                MoveAction::MoveRight => (1, 0),
+                _ => (0, 1)
+            };
+            if !piece_mgr.test_movement(&piece, dx, dy) {
+                return None;
+            }
+
+            match action {
+                MoveAction::MoveLeft => piece.move_left(),
+                MoveAction::MoveRight => piece.move_right(),
+                _ => match drop_mode {
+                    DropMode::Row => piece.move_down(),
+                    DropMode::Sonic => piece.set_y(piece_mgr.find_nearest_y_of(&piece))
+                }
+            }
+
+            None
+        }
+        _ => {
+            let rotation = match action {
+                MoveAction::RotateCW => RotationDirection::Clockwise,
+                MoveAction::RotateCCW => RotationDirection::CounterClockwise,
+                _ => RotationDirection::Deg180
+            };
+            let (kick, rotated) = piece_mgr.try_rotate(wkd, &piece, rotation)?;
+            piece = rotated;
+
+            Some(kick)
+        }
+    };
+
+    Some(SearchNode { piece, last_kick, parent: None })
+}
+
+/// Returns the placement of hard dropping the piece of `node` without the actions,
+/// or `None` if the piece would top out the board.
+fn lock(piece_mgr: &PieceMgr, node: &SearchNode) -> Option<Placement> {
+    let last_move_type = match node.last_kick {
+        Some(_) => LastMoveType::Rotation,
+        None => LastMoveType::Movement
+    };
+    let (tspin_status, spin) = piece_mgr.check_spin(&node.piece, last_move_type, node.last_kick.unwrap_or(0));
+
+    let mut piece = node.piece;
+    piece.set_y(piece_mgr.find_nearest_y_of(&piece));
+
+    let cells = get_cells(&piece);
+    let mut rows: Vec<usize> = cells.iter().map(|p| p.y as usize).collect();
+    rows.sort_unstable();
+    rows.dedup();
+
+    let lines_cleared = rows
+        .iter()
+        .filter(|y| {
+            piece_mgr.cell_holder.get_row(**y).iter().enumerate().all(|(x, cell)| {
+                cells.contains(&Point::new(x as i32, **y as i32))
+                    || (*cell != CellType::None && *cell != CellType::Solid)
+            })
+        })
+        .count() as u32;
+
+    // same as in `PieceMgr::hard_drop()`
+    if piece.get_y() <= piece_mgr.board_settings.height as u32 && lines_cleared == 0 {
+        return None;
+    }
+
+    Some(Placement {
+        piece,
+        tspin_status,
+        spin,
+        lines_cleared,
+        actions: vec![]
+    })
+}
+
+fn get_cells(piece: &Piece) -> Vec<Point> {
+    adjust_positions_clone(
+        piece.get_current_pos(),
+        Point::new(piece.get_x() as i32, piece.get_y() as i32)
+    )
+}
+
+fn collect_actions(nodes: &[SearchNode], index: usize) -> Vec<MoveAction> {
+    let mut actions = vec![MoveAction::HardDrop];
+
+    let mut cur = index;
+    while let Some((parent, action)) = nodes[cur].parent {
+        // a drop is played as a soft drop per every row
+        let count = match action {
+            MoveAction::SoftDrop => nodes[cur].piece.get_y() - nodes[parent].piece.get_y(),
+            _ => 1
+        };
+        actions.extend(std::iter::repeat(action).take(count as usize));
+        cur = parent;
+    }
+    actions.reverse();
+
+    actions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_settings::{GameSettings, SpinRules};
+
+    fn create_piece_mgr(layout: &[&str], piece_type: PieceType) -> PieceMgr {
+        create_piece_mgr_with(GameSettings::default(), layout, piece_type)
+    }
+
+    fn create_piece_mgr_with(game_settings: GameSettings, layout: &[&str], piece_type: PieceType) -> PieceMgr {
+        let mut piece_mgr = PieceMgr::new(&game_settings, 1337);
+
+        piece_mgr.cell_holder.fill_bottom(layout);
+        piece_mgr.cur_piece = piece_mgr.spawn_piece(piece_type);
+
+        piece_mgr
+    }
+
+    /// Plays the actions of `placement` on `piece_mgr` and checks the piece locks where expected.
+    fn play(piece_mgr: &PieceMgr, wkd: &WallKickData, placement: &Placement) {
+        let mut piece_mgr = piece_mgr.clone();
+
+        for action in &placement.actions {
+            match action {
+                MoveAction::MoveLeft => assert!(piece_mgr.move_left()),
+                MoveAction::MoveRight => assert!(piece_mgr.move_right()),
+                MoveAction::RotateCW => assert!(piece_mgr.rotate(wkd, RotationDirection::Clockwise)),
+                MoveAction::RotateCCW => assert!(piece_mgr.rotate(wkd, RotationDirection::CounterClockwise)),
+                MoveAction::RotateDeg180 => assert!(piece_mgr.rotate(wkd, RotationDirection::Deg180)),
+                MoveAction::SoftDrop => assert!(piece_mgr.soft_drop()),
+                MoveAction::HardDrop => {
+                    assert_eq!(piece_mgr.get_piece().get_x(), placement.piece.get_x());
+                    assert_eq!(piece_mgr.find_nearest_y(), placement.piece.get_y());
+                    assert_eq!(piece_mgr.get_piece().current_rotation, placement.piece.current_rotation);
+
+                    let res = piece_mgr.hard_drop().unwrap();
+                    assert_eq!(res.tspin_status, placement.tspin_status);
+                    assert_eq!(res.lines_cleared, placement.lines_cleared);
+                }
+                _ => unreachable!()
+            }
+        }
+    }
+
+    #[test]
+    fn empty_board_placements() {
+        let wkd = WallKickData::new(&GameSettings::default().wall_kick_data_mode);
+
+        // 9 positions of the O piece, 7 + 10 of the I piece, 8 + 9 of the S piece and 8 + 9 + 8 + 9 of the T piece
+        for (piece_type, count) in [(PieceType::O, 9), (PieceType::I, 17), (PieceType::S, 17), (PieceType::T, 34)] {
+            let piece_mgr = create_piece_mgr(&[], piece_type);
+            let placements = piece_mgr.find_placements(&wkd);
+
+            assert_eq!(placements.len(), count, "{:?}", piece_type);
+            for placement in &placements {
+                assert!(!placement.actions.contains(&MoveAction::SoftDrop), "{:?}", placement);
+                play(&piece_mgr, &wkd, placement);
+            }
+        }
+    }
+
+    #[test]
+    fn t_spin_double_is_found() {
+        let layout = [
+            "..........",
+            "#.........",
+            "...#######",
+            "#.########"
+        ];
+        let wkd = WallKickData::new(&GameSettings::default().wall_kick_data_mode);
+        let piece_mgr = create_piece_mgr(&layout, PieceType::T);
+
+        let placements = piece_mgr.find_placements(&wkd);
+        let tsd = placements
+            .iter()
+            .find(|p| p.tspin_status == TSpinStatus::Full)
+            .unwrap();
+
+        assert_eq!(tsd.lines_cleared, 2);
+        assert!(tsd.actions.contains(&MoveAction::SoftDrop));
+        play(&piece_mgr, &wkd, tsd);
+    }
+
+    #[test]
+    fn pieces_dropped_into_slot_are_not_spins() {
+        let layout = [
+            "#..#######",
+            "#..#######",
+            "##.#######"
+        ];
+        let wkd = WallKickData::new(&GameSettings::default().wall_kick_data_mode);
+        let game_settings = GameSettings { spin_rules: SpinRules::AllSpins, ..Default::default() };
+        let piece_mgr = create_piece_mgr_with(game_settings, &layout, PieceType::S);
+
+        let placements = piece_mgr.find_placements(&wkd);
+        let slot = placements
+            .iter()
+            .find(|p| p.lines_cleared == 2)
+            .unwrap();
+
+        assert_eq!(slot.spin, None);
+        assert!(placements.iter().all(|p| p.spin.is_none()), "{:?}", placements);
+        play(&piece_mgr, &wkd, slot);
+    }
+
+    #[test]
+    fn piece_is_slid_under_overhang_in_the_air() {
+        // the O piece can only get under the roof by stopping above the well
+        let layout = [
+            "########..",
+            "..........",
+            "..........",
+            "########..",
+            "########..",
+            "########..",
+            "########.."
+        ];
+        let wkd = WallKickData::new(&GameSettings::default().wall_kick_data_mode);
+        let piece_mgr = create_piece_mgr(&layout, PieceType::O);
+        let roof_y = (piece_mgr.cell_holder.get_height() - layout.len()) as i32;
+        let is_under_roof = |p: &Placement| get_cells(&p.piece)
+            .iter()
+            .all(|cell| cell.y > roof_y && cell.x < 8);
+
+        let placements = piece_mgr.find_placements(&wkd);
+        let tuck = placements.iter().find(|p| is_under_roof(p)).unwrap();
+        assert!(tuck.actions.contains(&MoveAction::SoftDrop));
+        play(&piece_mgr, &wkd, tuck);
+
+        assert!(!piece_mgr.find_placements_fast(&wkd).iter().any(is_under_roof));
+    }
+}
@@ -33,7 +33,7 @@ impl Display for PieceType {
     }
 }
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub enum RotationState {
     Initial = 0, Clockwise = 1, Deg180 = 2, CounterClockwise = 3
 }
@@ -58,7 +58,10 @@ pub enum RotationMove {
     InitToLeft,
 
     InitToDeg180,
-    Deg180ToInit
+    Deg180ToInit,
+
+    RightToLeft,
+    LeftToRight
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
@@ -290,7 +293,7 @@ impl Piece {
             RotationState::Clockwise => match rotation {
                 RotationDirection::Clockwise => (RotationMove::RightToDeg180, get_points_for_piece(self.piece_type, RotationState::Deg180)),
                 RotationDirection::CounterClockwise => (RotationMove::RightToInit, get_points_for_piece(self.piece_type, RotationState::Initial)),
-                RotationDirection::Deg180 => (RotationMove::Deg180ToLeft, get_points_for_piece(self.piece_type, RotationState::CounterClockwise)),
+                RotationDirection::Deg180 => (RotationMove::RightToLeft, get_points_for_piece(self.piece_type, RotationState::CounterClockwise)),
             }
             RotationState::Deg180 => match rotation {
                 RotationDirection::Clockwise => (RotationMove::Deg180ToLeft, get_points_for_piece(self.piece_type, RotationState::CounterClockwise)),
@@ -300,7 +303,7 @@ impl Piece {
             RotationState::CounterClockwise => match rotation {
                 RotationDirection::Clockwise => (RotationMove::LeftToInit, get_points_for_piece(self.piece_type, RotationState::Initial)),
                 RotationDirection::CounterClockwise => (RotationMove::LeftToDeg180, get_points_for_piece(self.piece_type, RotationState::Deg180)),
-                RotationDirection::Deg180 => (RotationMove::InitToRight, get_points_for_piece(self.piece_type, RotationState::Clockwise)),
+                RotationDirection::Deg180 => (RotationMove::LeftToRight, get_points_for_piece(self.piece_type, RotationState::Clockwise)),
             }
         }
     }
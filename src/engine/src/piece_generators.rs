@@ -7,6 +7,7 @@ use std::collections::VecDeque;
 use std::fmt::{Debug, Formatter};
 use rand::prelude::*;
 use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
 use crate::piece::{PieceType};
 
 pub const AVAILABLE_PIECES: [PieceType; 7] = [
@@ -21,36 +22,81 @@ pub const AVAILABLE_PIECES: [PieceType; 7] = [
 
 pub const BAG_SIZE: usize = AVAILABLE_PIECES.len();
 
-pub trait PieceGenerator {
-    /// Returns queue size.
-    fn get_queue_size(&self) -> usize { 5 }
-    /// Creates and returns initial piece queue.
-    fn init(&mut self) -> VecDeque<PieceType>;
+pub trait PieceGenerator: Send + Sync {
+    /// Creates and returns initial piece queue of `queue_size` pieces.
+    fn init(&mut self, queue_size: usize) -> VecDeque<PieceType> {
+        (0..queue_size)
+            .map(|_i| self.next())
+            .collect()
+    }
     /// Generates a new piece.
     fn next(&mut self) -> PieceType;
+    /// Clones the generator along with the state of its RNG.
+    fn clone_box(&self) -> Box<dyn PieceGenerator>;
 }
 
 impl Debug for dyn PieceGenerator {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "PieceGenerator{{queue_size: {}}}", self.get_queue_size())
+        write!(f, "PieceGenerator")
     }
 }
 
+impl Clone for Box<dyn PieceGenerator> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// Randomizer used for generating the pieces. Selected by `GameSettings::piece_generator`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub enum PieceGeneratorType {
+    /// Every piece is random.
+    FullRandom,
+    /// All of the 7 pieces in random order.
+    #[default]
+    Bag7,
+    /// Two of each of the 7 pieces in random order.
+    Bag14,
+    /// All of the 7 pieces and one random extra piece in random order.
+    Bag7Plus1,
+    /// TGM-style randomizer. A piece which is in the history of the last `history_size` pieces
+    /// is rerolled up to `rerolls` times. The first piece is never S, Z or O.
+    History {
+        history_size: usize,
+        rerolls: u32
+    },
+    /// Pieces of `sequence` in the given order, repeated when the sequence ends. Used for puzzles.
+    Fixed(Vec<PieceType>)
+}
+
+impl PieceGeneratorType {
+    /// TGM1 randomizer: history of 4 pieces and 4 rerolls.
+    pub fn tgm() -> Self {
+        PieceGeneratorType::History { history_size: 4, rerolls: 4 }
+    }
 
-/*pub enum PieceGeneratorType {
-    FullRandom, Bag7
+    /// TGM2 randomizer: history of 4 pieces and 6 rerolls.
+    pub fn tgm2() -> Self {
+        PieceGeneratorType::History { history_size: 4, rerolls: 6 }
+    }
 }
 
 pub struct PieceGeneratorFactory;
 impl PieceGeneratorFactory {
-    pub fn create(rng: Rc<RngManager>, generator_type: PieceGeneratorType) -> Box<dyn PieceGenerator> {
+    pub fn create(generator_type: &PieceGeneratorType, seed: u64) -> Box<dyn PieceGenerator> {
         match generator_type {
-            PieceGeneratorType::FullRandom => Box::new(PieceGeneratorFullRandom::new(rng)),
-            PieceGeneratorType::Bag7 => Box::new(PieceGeneratorBag7::new(rng))
+            PieceGeneratorType::FullRandom => Box::new(PieceGeneratorFullRandom::new(seed)),
+            PieceGeneratorType::Bag7 => Box::new(PieceGeneratorBag7::new(seed)),
+            PieceGeneratorType::Bag14 => Box::new(PieceGeneratorBag::new(seed, 2, 0)),
+            PieceGeneratorType::Bag7Plus1 => Box::new(PieceGeneratorBag::new(seed, 1, 1)),
+            PieceGeneratorType::History { history_size, rerolls } =>
+                Box::new(PieceGeneratorHistory::new(seed, *history_size, *rerolls)),
+            PieceGeneratorType::Fixed(sequence) => Box::new(PieceGeneratorFixed::new(sequence.clone()))
         }
     }
-}*/
+}
 
+#[derive(Debug, Clone)]
 pub struct PieceGeneratorFullRandom {
     rng: ChaCha8Rng,
 }
@@ -69,18 +115,16 @@ impl PieceGeneratorFullRandom {
 }
 
 impl PieceGenerator for PieceGeneratorFullRandom {
-    fn init(&mut self) -> VecDeque<PieceType> {
-        (0..self.get_queue_size())
-            .map(|_i| self.rng())
-            .collect()
-    }
-
     fn next(&mut self) -> PieceType {
         self.rng()
     }
+
+    fn clone_box(&self) -> Box<dyn PieceGenerator> {
+        Box::new(self.clone())
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PieceGeneratorBag7 {
     rng: ChaCha8Rng,
     queue: VecDeque<PieceType>
@@ -108,36 +152,264 @@ impl PieceGeneratorBag7 {
 }
 
 impl PieceGenerator for PieceGeneratorBag7 {
-    fn init(&mut self) -> VecDeque<PieceType> {
-        let bag = self.generate_bag();
-        let bag2 = self.generate_bag();
+    fn next(&mut self) -> PieceType {
+        if self.queue.is_empty() {
+            let bag = self.generate_bag();
+            self.enqueue_range(&bag);
+        }
+
+        self.queue.pop_front().expect("The queue must not be empty")
+    }
+
+    fn clone_box(&self) -> Box<dyn PieceGenerator> {
+        Box::new(self.clone())
+    }
+}
+
+/// Bag of `copies` of each of the 7 pieces and `extra` random pieces, shuffled.
+#[derive(Debug, Clone)]
+pub struct PieceGeneratorBag {
+    rng: ChaCha8Rng,
+    copies: usize,
+    extra: usize,
+    bag: Vec<PieceType>
+}
 
-        self.queue = (self.get_queue_size()..BAG_SIZE)
-            .map(|i|bag[i])
-            .collect::<VecDeque<PieceType>>();
+impl PieceGeneratorBag {
+    pub fn new(seed: u64, copies: usize, extra: usize) -> Self {
+        Self {
+            rng: SeedableRng::seed_from_u64(seed),
+            copies: copies.max(1),
+            extra,
+            bag: Vec::new()
+        }
+    }
+
+    fn fill_bag(&mut self) {
+        for _ in 0..self.copies {
+            self.bag.extend_from_slice(&AVAILABLE_PIECES);
+        }
+        for _ in 0..self.extra {
+            let piece = *AVAILABLE_PIECES.choose(&mut self.rng).unwrap();
+            self.bag.push(piece);
+        }
 
-        self.enqueue_range(&bag2);
+        self.bag.shuffle(&mut self.rng);
+    }
+}
+
+impl PieceGenerator for PieceGeneratorBag {
+    fn next(&mut self) -> PieceType {
+        if self.bag.is_empty() {
+            self.fill_bag();
+        }
+
+        // the bag is taken from the end, it's shuffled anyway
+        self.bag.pop().expect("The bag must not be empty")
+    }
 
-        let res = bag.into_iter()
-            .take(self.get_queue_size())
+    fn clone_box(&self) -> Box<dyn PieceGenerator> {
+        Box::new(self.clone())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PieceGeneratorHistory {
+    rng: ChaCha8Rng,
+    history: VecDeque<PieceType>,
+    history_size: usize,
+    rerolls: u32,
+    is_first: bool
+}
+
+impl PieceGeneratorHistory {
+    pub fn new(seed: u64, history_size: usize, rerolls: u32) -> Self {
+        // the history starts filled with S and Z, so they are less likely to be the first ones
+        let history = [PieceType::Z, PieceType::S]
+            .into_iter()
+            .cycle()
+            .take(history_size)
             .collect();
 
-        res
+        Self {
+            rng: SeedableRng::seed_from_u64(seed),
+            history,
+            history_size,
+            rerolls,
+            is_first: true
+        }
     }
 
+    fn roll(&mut self) -> PieceType {
+        if self.is_first {
+            self.is_first = false;
+            // the first piece is never a piece which would leave an overhang
+            return *[PieceType::I, PieceType::J, PieceType::L, PieceType::T]
+                .choose(&mut self.rng)
+                .unwrap();
+        }
+
+        let mut piece = *AVAILABLE_PIECES.choose(&mut self.rng).unwrap();
+        for _ in 0..self.rerolls {
+            if !self.history.contains(&piece) {
+                break;
+            }
+
+            piece = *AVAILABLE_PIECES.choose(&mut self.rng).unwrap();
+        }
+
+        piece
+    }
+}
+
+impl PieceGenerator for PieceGeneratorHistory {
     fn next(&mut self) -> PieceType {
-        let p = self.queue.pop_front().expect("The queue must not be empty");
+        let piece = self.roll();
 
-        if self.queue.len() <= self.get_queue_size() {
-            let bag = self.generate_bag();
-            self.enqueue_range(&bag);
+        if self.history_size > 0 {
+            if self.history.len() >= self.history_size {
+                self.history.pop_front();
+            }
+            self.history.push_back(piece);
         }
 
-        p
+        piece
+    }
+
+    fn clone_box(&self) -> Box<dyn PieceGenerator> {
+        Box::new(self.clone())
+    }
+}
+
+/// Repeats the given sequence of pieces. If the sequence is empty, the 7 pieces are used.
+#[derive(Debug, Clone)]
+pub struct PieceGeneratorFixed {
+    sequence: Vec<PieceType>,
+    index: usize
+}
+
+impl PieceGeneratorFixed {
+    pub fn new(sequence: Vec<PieceType>) -> Self {
+        let sequence = if sequence.is_empty() {
+            AVAILABLE_PIECES.to_vec()
+        } else {
+            sequence
+        };
+
+        Self {
+            sequence,
+            index: 0
+        }
+    }
+}
+
+impl PieceGenerator for PieceGeneratorFixed {
+    fn next(&mut self) -> PieceType {
+        let piece = self.sequence[self.index];
+        self.index = (self.index + 1) % self.sequence.len();
+
+        piece
+    }
+
+    fn clone_box(&self) -> Box<dyn PieceGenerator> {
+        Box::new(self.clone())
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    const SEED: u64 = 1337;
+
+    fn generate(generator_type: &PieceGeneratorType, count: usize) -> Vec<PieceType> {
+        let mut generator = PieceGeneratorFactory::create(generator_type, SEED);
+        let mut pieces: Vec<PieceType> = generator.init(5).into_iter().collect();
+
+        while pieces.len() < count {
+            pieces.push(generator.next());
+        }
 
+        pieces
+    }
+
+    fn count(pieces: &[PieceType], piece: PieceType) -> usize {
+        pieces.iter().filter(|&&p| p == piece).count()
+    }
+
+    #[test]
+    fn generators_are_deterministic() {
+        let types = [
+            PieceGeneratorType::FullRandom,
+            PieceGeneratorType::Bag7,
+            PieceGeneratorType::Bag14,
+            PieceGeneratorType::Bag7Plus1,
+            PieceGeneratorType::tgm2(),
+            PieceGeneratorType::Fixed(vec![PieceType::T, PieceType::I])
+        ];
+
+        for generator_type in types.iter() {
+            assert_eq!(generate(generator_type, 100), generate(generator_type, 100), "{:?}", generator_type);
+        }
+    }
+
+    #[test]
+    fn bags_contain_every_piece() {
+        let pieces = generate(&PieceGeneratorType::Bag7, 70);
+        for bag in pieces.chunks(7) {
+            for piece in AVAILABLE_PIECES {
+                assert_eq!(count(bag, piece), 1);
+            }
+        }
+
+        let pieces = generate(&PieceGeneratorType::Bag14, 70);
+        for bag in pieces.chunks(14) {
+            for piece in AVAILABLE_PIECES {
+                assert_eq!(count(bag, piece), 2);
+            }
+        }
+
+        let pieces = generate(&PieceGeneratorType::Bag7Plus1, 80);
+        for bag in pieces.chunks(8) {
+            for piece in AVAILABLE_PIECES {
+                assert!((1..=2).contains(&count(bag, piece)));
+            }
+        }
+    }
+
+    #[test]
+    fn history_avoids_repeats() {
+        let pieces = generate(&PieceGeneratorType::tgm2(), 1000);
+
+        assert!(![PieceType::S, PieceType::Z, PieceType::O].contains(&pieces[0]));
+
+        // with 6 rerolls a piece repeats right after itself rarely
+        let repeats = pieces.windows(2).filter(|w| w[0] == w[1]).count();
+        assert!(repeats < 20, "{} repeats", repeats);
+
+        // without rerolls it's just random
+        let pieces = generate(&PieceGeneratorType::History { history_size: 4, rerolls: 0 }, 1000);
+        let repeats = pieces.windows(2).filter(|w| w[0] == w[1]).count();
+        assert!(repeats > 80, "{} repeats", repeats);
+    }
+
+    #[test]
+    fn fixed_sequence_repeats() {
+        let sequence = vec![PieceType::T, PieceType::I, PieceType::O];
+        let pieces = generate(&PieceGeneratorType::Fixed(sequence.clone()), 9);
+
+        assert_eq!(pieces, [sequence.clone(), sequence.clone(), sequence].concat());
+    }
+
+    #[test]
+    fn cloned_generator_continues_the_same_sequence() {
+        let mut generator = PieceGeneratorFactory::create(&PieceGeneratorType::Bag7Plus1, SEED);
+        generator.init(5);
+        generator.next();
+
+        let mut cloned = generator.clone();
+        for _ in 0..20 {
+            assert_eq!(generator.next(), cloned.next());
+        }
+    }
 }
\ No newline at end of file
@@ -3,23 +3,28 @@
  * See the LICENSE file in the repository root for full licence text.
  */
 
+use serde::{Deserialize, Serialize};
 use crate::cell_holder::{CellHolder, CellType};
-use crate::damage_calculation::check_t_overhang;
-use crate::game_settings::{BoardSettings, GameSettings};
+use crate::damage_calculation::check_t_spin;
+use crate::move_gen::{find_placements, DropMode, Placement};
+use crate::game_settings::{BoardSettings, GameSettings, HoldMode, SpinRules, TSpinSettings};
 use crate::piece::{OffsetType, Piece, PieceType, RotationDirection, WallKickCheckParams};
 use crate::piece_queue::PieceQueue;
 use crate::primitives::Point;
+use crate::protocol::PieceSnapshot;
 use crate::replays::{HardDropInfo, LastMoveType};
 use crate::scoring::TSpinStatus;
 use crate::utils::{adjust_positions_clone, piece_type_to_cell_type};
 use crate::wall_kick_data::WallKickData;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum BoardErrorReason {
     CannotApplyPiece,
     BoardDead,
     BoardDisabled,
-    CannotSpawnPiece
+    CannotSpawnPiece,
+    /// The goal of the `GameMode` is completed.
+    GameFinished
 }
 
 fn reset_piece(piece: &mut Piece, board_width: usize, board_height: usize) {
@@ -57,16 +62,21 @@ fn find_nearest_y(cur_piece: &Piece, cell_holder: &CellHolder) -> u32 {
     y
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PieceMgr {
     pub cur_piece: Piece,
-    board_settings: BoardSettings,
+    pub(crate) board_settings: BoardSettings,
+    t_spin_settings: TSpinSettings,
+    spin_rules: SpinRules,
     pub cell_holder: Box<CellHolder>,
     hold_piece: Option<PieceType>,
+    hold_mode: HoldMode,
     is_hold_used: bool,
     pub piece_queue: PieceQueue,
     pub is_enabled: bool,
     last_move_type: LastMoveType,
+    /// Index of the wall kick test used by the last rotation.
+    last_kick: usize,
     pub nearest_y: u32
 }
 
@@ -75,7 +85,7 @@ impl PieceMgr {
 
         let board_settings = game_settings.board;
 
-        let mut piece_queue = PieceQueue::new(seed);
+        let mut piece_queue = PieceQueue::new(seed, &game_settings.piece_generator, game_settings.preview_size);
         let next_piece = piece_queue.next();
         let mut piece = Piece::new(next_piece);
         reset_piece(&mut piece, board_settings.width, board_settings.full_height());
@@ -86,12 +96,16 @@ impl PieceMgr {
         Self {
             cur_piece: piece,
             board_settings,
+            t_spin_settings: game_settings.t_spin,
+            spin_rules: game_settings.spin_rules,
             cell_holder,
             hold_piece: None,
+            hold_mode: game_settings.hold_mode,
             is_hold_used: false,
             piece_queue,
             is_enabled: true,
             last_move_type: LastMoveType::None,
+            last_kick: 0,
             nearest_y
         }
     }
@@ -124,10 +138,24 @@ impl PieceMgr {
         self.hold_piece
     }
 
+    /// Replaces the hold piece, for example, when the state of the board comes from a bot frontend.
+    pub fn set_hold_piece(&mut self, piece_type: Option<PieceType>) {
+        self.hold_piece = piece_type;
+    }
+
+    /// Returns `true` if the current piece can be held according to the `HoldMode`.
+    pub fn is_hold_available(&self) -> bool {
+        match self.hold_mode {
+            HoldMode::Disabled => false,
+            HoldMode::Once => !self.is_hold_used,
+            HoldMode::Unlimited => true
+        }
+    }
+
     /// Holds current piece if possible. If success, returns `Some(&Piece)`, otherwise `None`.
     pub fn try_hold_piece(&mut self) -> Option<Result<&Piece, BoardErrorReason>> {
-        // we can hold piece once per turn
-        if self.is_hold_used {
+        // depending on the hold mode, we can hold piece once per turn, any number of times or never
+        if !self.is_hold_available() {
             return None;
         }
 
@@ -162,7 +190,7 @@ impl PieceMgr {
             return false;
         }
 
-        if self.test_movement(-1, 0) {
+        if self.test_movement(&self.cur_piece, -1, 0) {
             self.cur_piece.move_left();
             self.last_move_type = LastMoveType::Movement;
             self.nearest_y = self.find_nearest_y();
@@ -187,7 +215,7 @@ impl PieceMgr {
             return false;
         }
 
-        if self.test_movement(1, 0) {
+        if self.test_movement(&self.cur_piece, 1, 0) {
             self.cur_piece.move_right();
             self.last_move_type = LastMoveType::Movement;
             self.nearest_y = self.find_nearest_y();
@@ -211,18 +239,10 @@ impl PieceMgr {
             return false;
         }
 
-        let piece = &self.cur_piece;
-        let rot_type = piece.get_rotation_type(rotation);
-        let tests = &wkd.get(piece.get_wall_kick_type())[&rot_type.0];
-
-        let test = self.test_rotation(WallKickCheckParams {
-            tests,
-            expected_pos: rot_type.1
-        });
-
-        if let Some(point) = test {
-            self.cur_piece.rotate(rotation, point.x, point.y);
+        if let Some((kick, piece)) = self.try_rotate(wkd, &self.cur_piece, rotation) {
+            self.cur_piece = piece;
             self.last_move_type = LastMoveType::Rotation;
+            self.last_kick = kick;
             self.nearest_y = self.find_nearest_y();
             return true;
         }
@@ -250,7 +270,7 @@ impl PieceMgr {
             return false;
         }
 
-        if self.test_movement(0, 1) {
+        if self.test_movement(&self.cur_piece, 0, 1) {
             self.cur_piece.move_down();
             self.last_move_type = LastMoveType::Movement;
             self.nearest_y = self.find_nearest_y();
@@ -276,17 +296,7 @@ impl PieceMgr {
         }
 
         let nearest_y = self.find_nearest_y();
-
-        let tspin_status = if self.cur_piece.get_type() == PieceType::T {
-            check_t_overhang(
-                &self.board_settings,
-                self.cur_piece.get_x() as i32,
-                self.cur_piece.get_y() as i32,
-                |p| { self.cell_holder.intersects(&p) },
-            )
-        } else {
-            TSpinStatus::None
-        };
+        let (tspin_status, spin) = self.check_spin(&self.cur_piece, self.last_move_type, self.last_kick);
 
         // failed to apply piece as the cells are occupied
         if !self.try_apply_piece(nearest_y) {
@@ -307,7 +317,8 @@ impl PieceMgr {
             lines_cleared,
             tspin_status,
             last_move_type: self.last_move_type,
-            occupied_cells_left: self.cell_holder.get_occupied_cell_count() as u32
+            occupied_cells_left: self.cell_holder.get_occupied_cell_count() as u32,
+            spin
         };
 
         self.reset_cur_piece();
@@ -337,13 +348,126 @@ impl PieceMgr {
         self.nearest_y = self.find_nearest_y();
     }
 
+    /// Replaces the current piece and the hold piece. Used for mirroring a remote board,
+    /// so the position is not checked against the cells.
+    pub fn set_piece_state(&mut self, piece: &PieceSnapshot, hold: Option<PieceType>) {
+        let mut cur_piece = Piece::new(piece.piece_type);
+        cur_piece.current_rotation = piece.rotation;
+        cur_piece.set_x(piece.x);
+        cur_piece.set_y(piece.y);
+
+        self.cur_piece = cur_piece;
+        self.hold_piece = hold;
+        self.update_nearest_y();
+    }
+
+    /// Takes pieces from the piece queue until it starts with `queue`, so the pieces
+    /// generated after it are the same as the ones of the board the `queue` came from.
+    /// The `queue` is usually the preview of the other board, not the whole queue.
+    /// It is only possible to catch up with a board of the same seed which is ahead.
+    /// Returns the number of pieces taken or `None` if the queue could not be matched.
+    pub fn sync_queue(&mut self, queue: &[PieceType]) -> Option<usize> {
+        // the number of pieces it is reasonable to be behind by
+        const MAX_STEPS: usize = 32;
+
+        for steps in 0..MAX_STEPS {
+            if self.piece_queue.queue.len() >= queue.len() && self.piece_queue.queue.iter().zip(queue).all(|(a, b)| a == b) {
+                return Some(steps);
+            }
+
+            self.piece_queue.next();
+            // a piece was spawned, so the hold is available again
+            self.is_hold_used = false;
+        }
+
+        None
+    }
+
     pub fn update_nearest_y(&mut self) {
         self.nearest_y = self.find_nearest_y();
     }
 
-    fn test_movement(&self, x: i32, y: i32) -> bool {
+    /// Returns every position the current piece can be locked at along with the shortest
+    /// sequence of the actions which puts it there.
+    pub fn find_placements(&self, wkd: &WallKickData) -> Vec<Placement> {
+        find_placements(self, wkd, &self.cur_piece, DropMode::Row)
+    }
 
-        let piece = &self.cur_piece;
+    /// Same as `find_placements()`, but the piece is never moved in the air, see `DropMode::Sonic`.
+    /// Used by the bots, which search a lot of boards.
+    pub fn find_placements_fast(&self, wkd: &WallKickData) -> Vec<Placement> {
+        find_placements(self, wkd, &self.cur_piece, DropMode::Sonic)
+    }
+
+    /// Same as `find_placements()`, but for a new piece of `piece_type`, for example, the hold piece.
+    pub fn find_placements_for(&self, wkd: &WallKickData, piece_type: PieceType) -> Vec<Placement> {
+        find_placements(self, wkd, &self.spawn_piece(piece_type), DropMode::Row)
+    }
+
+    /// Spawns a piece of `piece_type` at the starting position without putting it on the board.
+    pub(crate) fn spawn_piece(&self, piece_type: PieceType) -> Piece {
+        let mut piece = Piece::new(piece_type);
+        reset_piece(&mut piece, self.board_settings.width, self.board_settings.full_height());
+
+        piece
+    }
+
+    /// Returns the nearest Y coordinate `piece` fits at.
+    pub(crate) fn find_nearest_y_of(&self, piece: &Piece) -> u32 {
+        find_nearest_y(piece, &self.cell_holder)
+    }
+
+    /// Returns the T-Spin status and the spin of the all-spin rules `piece` would score
+    /// if it was hard dropped after `last_move_type`. The corners and the immobility are checked where
+    /// the piece was rotated, so a piece rotated in the air and dropped into a slot doesn't score a spin.
+    pub(crate) fn check_spin(
+        &self,
+        piece: &Piece,
+        last_move_type: LastMoveType,
+        last_kick: usize
+    ) -> (TSpinStatus, Option<PieceType>) {
+        if last_move_type != LastMoveType::Rotation {
+            return (TSpinStatus::None, None);
+        }
+
+        match piece.get_type() {
+            PieceType::T => {
+                let tspin_status = check_t_spin(
+                    &self.t_spin_settings,
+                    &self.board_settings,
+                    piece,
+                    last_kick,
+                    |p| { self.cell_holder.intersects(&p) },
+                );
+
+                (tspin_status, None)
+            }
+            PieceType::O => (TSpinStatus::None, None),
+            piece_type if self.spin_rules == SpinRules::AllSpins && self.is_immobile(piece) => {
+                (TSpinStatus::None, Some(piece_type))
+            }
+            _ => (TSpinStatus::None, None)
+        }
+    }
+
+    /// Rotates a copy of `piece` using the wall kicks. Returns the index of the passed test
+    /// and the rotated piece, or `None` if it can't be rotated.
+    pub(crate) fn try_rotate(&self, wkd: &WallKickData, piece: &Piece, rotation: RotationDirection) -> Option<(usize, Piece)> {
+        let rot_type = piece.get_rotation_type(rotation);
+        let tests = wkd.get_tests(piece.get_wall_kick_type(), &rot_type.0);
+
+        let (kick, point) = self.test_rotation(piece, WallKickCheckParams {
+            tests,
+            expected_pos: rot_type.1
+        })?;
+
+        let mut piece = *piece;
+        piece.rotate(rotation, point.x, point.y);
+
+        Some((kick, piece))
+    }
+
+    pub(crate) fn test_movement(&self, piece: &Piece, x: i32, y: i32) -> bool {
         let b = piece.get_bounds();
 
         if b.x + x < 0 || b.x + b.width as i32 + x > self.board_settings.width as i32 {
@@ -364,12 +488,24 @@ impl PieceMgr {
         !self.cell_holder.intersects_any(&new_pos)
     }
 
-    fn test_rotation(&self, kick_params: WallKickCheckParams) -> Option<Point> {
+    /// Returns true if `piece` can't move left, right or up.
+    fn is_immobile(&self, piece: &Piece) -> bool {
+        [(-1, 0), (1, 0), (0, -1)].iter().all(|(dx, dy)| {
+            let adjusted = adjust_positions_clone(
+                piece.get_current_pos(),
+                Point::new(piece.get_x() as i32 + dx, piece.get_y() as i32 + dy)
+            );
+
+            self.cell_holder.intersects_any(&adjusted)
+        })
+    }
+
+    /// Returns the index of the first passed test and its offset.
+    fn test_rotation(&self, piece: &Piece, kick_params: WallKickCheckParams) -> Option<(usize, Point)> {
         let tests = kick_params.tests;
         let expected_pos = kick_params.expected_pos;
-        let piece = &self.cur_piece;
 
-        for t in tests {
+        for (i, t) in tests.iter().enumerate() {
             let test = Point::new(t.x, -t.y);
 
             let adjusted = adjust_positions_clone(
@@ -378,7 +514,7 @@ impl PieceMgr {
             );
 
             if !self.cell_holder.intersects_any(&adjusted) {
-                return Some(test);
+                return Some((i, test));
             }
         }
 
@@ -422,4 +558,245 @@ impl PieceMgr {
     pub fn disable(&mut self) {
         self.is_enabled = false;
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_settings::MAX_PREVIEW_SIZE;
+    use crate::piece::RotationState;
+
+    fn create_piece_mgr(hold_mode: HoldMode, preview_size: usize) -> PieceMgr {
+        let game_settings = GameSettings {
+            hold_mode,
+            preview_size,
+            ..Default::default()
+        };
+
+        PieceMgr::new(&game_settings, 1337)
+    }
+
+    #[test]
+    fn hold_once() {
+        let mut piece_mgr = create_piece_mgr(HoldMode::Once, 5);
+        let first = piece_mgr.get_piece().get_type();
+
+        assert!(piece_mgr.try_hold_piece().is_some());
+        assert_eq!(piece_mgr.get_hold_piece(), Some(first));
+        assert!(!piece_mgr.is_hold_available());
+        assert!(piece_mgr.try_hold_piece().is_none());
+
+        piece_mgr.hard_drop().unwrap();
+        assert!(piece_mgr.is_hold_available());
+    }
+
+    #[test]
+    fn hold_disabled() {
+        let mut piece_mgr = create_piece_mgr(HoldMode::Disabled, 5);
+
+        assert!(!piece_mgr.is_hold_available());
+        assert!(piece_mgr.try_hold_piece().is_none());
+        assert_eq!(piece_mgr.get_hold_piece(), None);
+    }
+
+    #[test]
+    fn hold_unlimited() {
+        let mut piece_mgr = create_piece_mgr(HoldMode::Unlimited, 5);
+        let first = piece_mgr.get_piece().get_type();
+
+        piece_mgr.try_hold_piece().unwrap().unwrap();
+        let second = piece_mgr.get_piece().get_type();
+        piece_mgr.try_hold_piece().unwrap().unwrap();
+
+        assert_eq!(piece_mgr.get_piece().get_type(), first);
+        assert_eq!(piece_mgr.get_hold_piece(), Some(second));
+        assert!(piece_mgr.is_hold_available());
+    }
+
+    #[test]
+    fn preview_size() {
+        for preview_size in 0..=MAX_PREVIEW_SIZE {
+            let piece_mgr = create_piece_mgr(HoldMode::Once, preview_size);
+            assert_eq!(piece_mgr.piece_queue.get_preview().count(), preview_size);
+        }
+
+        let piece_mgr = create_piece_mgr(HoldMode::Once, 10);
+        assert_eq!(piece_mgr.piece_queue.get_preview().count(), MAX_PREVIEW_SIZE);
+
+        // the pieces don't depend on the preview size
+        let mut a = create_piece_mgr(HoldMode::Once, 0);
+        let mut b = create_piece_mgr(HoldMode::Once, 7);
+        for _ in 0..10 {
+            assert_eq!(a.get_piece().get_type(), b.get_piece().get_type());
+            a.hard_drop().unwrap();
+            b.hard_drop().unwrap();
+        }
+    }
+
+    /// Fills the bottom rows of the board with `layout`, where `#` is an occupied cell,
+    /// puts the piece at `pos`, rotates it and hard drops it.
+    fn drop_piece(
+        game_settings: GameSettings,
+        piece_type: PieceType,
+        layout: &[&str],
+        state: RotationState,
+        pos: (u32, u32),
+        rotations: &[RotationDirection]
+    ) -> HardDropInfo {
+        let wkd = WallKickData::new(&game_settings.wall_kick_data_mode);
+        let mut piece_mgr = PieceMgr::new(&game_settings, 1337);
+
+        piece_mgr.cell_holder.fill_bottom(layout);
+
+        let mut piece = Piece::new(piece_type);
+        piece.current_rotation = state;
+        piece.set_x(pos.0);
+        piece.set_y(pos.1);
+        piece_mgr.cur_piece = piece;
+
+        for rotation in rotations {
+            assert!(piece_mgr.rotate(&wkd, *rotation));
+        }
+
+        piece_mgr.hard_drop().unwrap()
+    }
+
+    fn drop_t(
+        t_spin: TSpinSettings,
+        layout: &[&str],
+        state: RotationState,
+        pos: (u32, u32),
+        rotations: &[RotationDirection]
+    ) -> HardDropInfo {
+        let game_settings = GameSettings { t_spin, ..Default::default() };
+
+        drop_piece(game_settings, PieceType::T, layout, state, pos, rotations)
+    }
+
+    const TSD: [&str; 3] = [
+        "#.........",
+        "...#######",
+        "#.########"
+    ];
+
+    /// The T piece gets into the slot with the last kick test, so both of the front corners
+    /// are not occupied.
+    const TST_KICK: [&str; 5] = [
+        "##........",
+        "#.........",
+        "#.########",
+        "#..#######",
+        "#........."
+    ];
+
+    const WALL_MINI: [&str; 2] = [
+        "..........",
+        ".#########"
+    ];
+
+    #[test]
+    fn t_spin_double() {
+        let res = drop_t(TSpinSettings::default(), &TSD, RotationState::Clockwise, (1, 38), &[RotationDirection::Clockwise]);
+
+        assert_eq!(res.tspin_status, TSpinStatus::Full);
+        assert_eq!(res.lines_cleared, 2);
+    }
+
+    #[test]
+    fn t_spin_tst_kick_is_upgraded() {
+        let res = drop_t(TSpinSettings::default(), &TST_KICK, RotationState::Initial, (2, 36), &[RotationDirection::Clockwise]);
+        assert_eq!(res.tspin_status, TSpinStatus::Full);
+        assert_eq!(res.lines_cleared, 2);
+
+        let t_spin = TSpinSettings { upgrade_kick: None, ..Default::default() };
+        let res = drop_t(t_spin, &TST_KICK, RotationState::Initial, (2, 36), &[RotationDirection::Clockwise]);
+        assert_eq!(res.tspin_status, TSpinStatus::Mini);
+    }
+
+    #[test]
+    fn t_spin_mini_against_wall() {
+        let res = drop_t(TSpinSettings::default(), &WALL_MINI, RotationState::Initial, (1, 38), &[RotationDirection::Clockwise]);
+        assert_eq!(res.tspin_status, TSpinStatus::Mini);
+        assert_eq!(res.lines_cleared, 1);
+
+        let t_spin = TSpinSettings { walls_are_corners: false, ..Default::default() };
+        let res = drop_t(t_spin, &WALL_MINI, RotationState::Initial, (1, 38), &[RotationDirection::Clockwise]);
+        assert_eq!(res.tspin_status, TSpinStatus::None);
+
+        let t_spin = TSpinSettings { minis: false, ..Default::default() };
+        let res = drop_t(t_spin, &WALL_MINI, RotationState::Initial, (1, 38), &[RotationDirection::Clockwise]);
+        assert_eq!(res.tspin_status, TSpinStatus::None);
+    }
+
+    #[test]
+    fn fake_t_spins() {
+        // the piece is in the slot, but it has not been rotated there
+        let res = drop_t(TSpinSettings::default(), &TSD, RotationState::Deg180, (1, 38), &[]);
+        assert_eq!(res.tspin_status, TSpinStatus::None);
+        assert_eq!(res.lines_cleared, 2);
+
+        // rotated on a flat floor
+        let res = drop_t(TSpinSettings::default(), &[], RotationState::Clockwise, (4, 38), &[RotationDirection::Clockwise]);
+        assert_eq!(res.tspin_status, TSpinStatus::None);
+
+        let t_spin = TSpinSettings { enabled: false, ..Default::default() };
+        let res = drop_t(t_spin, &TSD, RotationState::Clockwise, (1, 38), &[RotationDirection::Clockwise]);
+        assert_eq!(res.tspin_status, TSpinStatus::None);
+    }
+
+    /// The T piece can be dropped into the slot from above, so it can only get there with a spin
+    /// if it is rotated in the slot.
+    const OPEN_T_SLOT: [&str; 3] = [
+        "#.........",
+        "#.........",
+        "#.########"
+    ];
+
+    #[test]
+    fn t_rotated_in_the_air_is_not_a_spin() {
+        let res = drop_t(TSpinSettings::default(), &OPEN_T_SLOT, RotationState::Initial, (1, 20), &[RotationDirection::Clockwise]);
+        assert_eq!(res.tspin_status, TSpinStatus::None);
+        assert_eq!(res.lines_cleared, 1);
+    }
+
+    /// The S piece is kicked to the left into the slot and can't move out of it.
+    const S_SPIN: [&str; 4] = [
+        "#.........",
+        "..........",
+        "..########",
+        "#.########"
+    ];
+
+    #[test]
+    fn all_spin_immobile_piece() {
+        let game_settings = GameSettings { spin_rules: SpinRules::AllSpins, ..Default::default() };
+        let res = drop_piece(game_settings.clone(), PieceType::S, &S_SPIN, RotationState::Initial, (1, 38), &[RotationDirection::Clockwise]);
+        assert_eq!(res.spin, Some(PieceType::S));
+        assert_eq!(res.tspin_status, TSpinStatus::None);
+        assert_eq!(res.lines_cleared, 2);
+
+        // the piece could move up
+        let mut layout = S_SPIN;
+        layout[0] = "..........";
+        let res = drop_piece(game_settings, PieceType::S, &layout, RotationState::Initial, (1, 38), &[RotationDirection::Clockwise]);
+        assert_eq!(res.spin, None);
+
+        let res = drop_piece(GameSettings::default(), PieceType::S, &S_SPIN, RotationState::Initial, (1, 38), &[RotationDirection::Clockwise]);
+        assert_eq!(res.spin, None);
+    }
+
+    /// The vertical S piece fits the slot, but can be dropped into it from above.
+    const OPEN_S_SLOT: [&str; 3] = [
+        "#..#######",
+        "#..#######",
+        "##.#######"
+    ];
+
+    #[test]
+    fn all_spin_rotated_in_the_air_is_not_a_spin() {
+        let game_settings = GameSettings { spin_rules: SpinRules::AllSpins, ..Default::default() };
+        let res = drop_piece(game_settings, PieceType::S, &OPEN_S_SLOT, RotationState::Initial, (1, 20), &[RotationDirection::Clockwise]);
+        assert_eq!(res.spin, None);
+        assert_eq!(res.lines_cleared, 2);
+    }
+}
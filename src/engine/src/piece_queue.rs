@@ -4,26 +4,34 @@
  */
 
 use std::collections::VecDeque;
+use crate::game_settings::MAX_PREVIEW_SIZE;
 use crate::piece::PieceType;
-use crate::piece_generators::{PieceGenerator, PieceGeneratorBag7};
+use crate::piece_generators::{PieceGenerator, PieceGeneratorFactory, PieceGeneratorType};
 
-#[derive(Debug)]
+/// Queue of the next pieces. The queue always holds `MAX_PREVIEW_SIZE` pieces
+/// regardless of how many of them are shown to the player, so the boards with different
+/// preview sizes stay comparable.
+#[derive(Debug, Clone)]
 pub struct PieceQueue {
     pub queue: VecDeque<PieceType>,
-    piece_generator: Box<PieceGeneratorBag7>,
+    preview_size: usize,
+    piece_generator: Box<dyn PieceGenerator>,
+    generator_type: PieceGeneratorType,
     next_piece: PieceType,
     seed: u64
 }
 
 impl PieceQueue {
-    pub fn new(seed: u64) -> Self {
+    pub fn new(seed: u64, generator_type: &PieceGeneratorType, preview_size: usize) -> Self {
 
-        let mut piece_generator = Box::new(PieceGeneratorBag7::new(seed));
-        let queue = piece_generator.init();
+        let mut piece_generator = PieceGeneratorFactory::create(generator_type, seed);
+        let queue = piece_generator.init(MAX_PREVIEW_SIZE);
 
         Self {
             queue,
+            preview_size: preview_size.min(MAX_PREVIEW_SIZE),
             piece_generator,
+            generator_type: generator_type.clone(),
             next_piece: PieceType::Pixel,
             seed
         }
@@ -34,20 +42,36 @@ impl PieceQueue {
     }
 
     fn set_piece(&mut self) -> PieceType {
-        self.next_piece = self.piece_generator.next();
-        let next = self.queue.pop_front().unwrap();
+        let next = self.queue.pop_front().unwrap_or_else(|| self.piece_generator.next());
 
-        self.queue.push_back(self.next_piece);
+        // the queue received from the server only holds the preview, so it may need more than one piece
+        while self.queue.len() < MAX_PREVIEW_SIZE {
+            self.next_piece = self.piece_generator.next();
+            self.queue.push_back(self.next_piece);
+        }
 
         next
     }
 
+    /// Returns the next pieces shown to the player.
+    pub fn get_preview(&self) -> impl Iterator<Item = &PieceType> {
+        self.queue.iter().take(self.preview_size)
+    }
+
+    pub fn get_preview_size(&self) -> usize {
+        self.preview_size
+    }
+
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
     pub fn reset(&mut self, new_seed: Option<u64>) {
 
         let seed = new_seed.unwrap_or_else(|| self.seed);
 
-        let mut piece_generator = Box::new(PieceGeneratorBag7::new(seed));
-        let queue = piece_generator.init();
+        let mut piece_generator = PieceGeneratorFactory::create(&self.generator_type, seed);
+        let queue = piece_generator.init(MAX_PREVIEW_SIZE);
 
         self.queue = queue;
         self.piece_generator = piece_generator;
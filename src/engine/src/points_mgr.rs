@@ -0,0 +1,189 @@
+/*
+ * Copyright (c) Grigory Alfyorov. Licensed under the MIT License.
+ * See the LICENSE file in the repository root for full licence text.
+ */
+
+use serde::{Deserialize, Serialize};
+use crate::game_settings::PointsSettings;
+use crate::replays::HardDropInfo;
+use crate::scoring::TSpinStatus;
+
+/// Points for clearing 0 to 4 lines.
+const LINE_CLEAR_POINTS: [u32; 5] = [0, 100, 300, 500, 800];
+/// Points for the T-Spins clearing 0 to 3 lines.
+const T_SPIN_POINTS: [u32; 4] = [400, 800, 1200, 1600];
+/// Points for the Mini T-Spins and the all-spins clearing 0 to 2 lines.
+const T_SPIN_MINI_POINTS: [u32; 3] = [100, 200, 400];
+/// Points for the all clears with 1 to 4 lines.
+const ALL_CLEAR_POINTS: [u32; 4] = [800, 1200, 1800, 2000];
+const B2B_ALL_CLEAR_POINTS: u32 = 3200;
+const COMBO_POINTS: u32 = 50;
+const SOFT_DROP_POINTS: u32 = 1;
+const HARD_DROP_POINTS: u32 = 2;
+
+fn get_points(table: &[u32], lines: u32) -> u32 {
+    table[(lines as usize).min(table.len() - 1)]
+}
+
+/// Single-player score of the guideline games.
+///
+/// Line clears and spins are multiplied by the current level, difficult clears in a B2B chain
+/// give 1.5 times more points. The level advances every `PointsSettings::lines_per_level` lines.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PointsMgr {
+    settings: PointsSettings,
+    pub score: u64,
+    pub level: u32,
+    pub lines: u32,
+    /// Drop points of the current piece.
+    drop_points: u32
+}
+
+impl PointsMgr {
+    pub fn new(settings: &PointsSettings) -> Self {
+        Self {
+            settings: *settings,
+            score: 0,
+            level: settings.start_level,
+            lines: 0,
+            drop_points: 0
+        }
+    }
+
+    /// Awards the points for the piece soft dropped by `cells` cells.
+    pub fn soft_drop(&mut self, cells: u32) {
+        self.add_drop_points(cells * SOFT_DROP_POINTS);
+    }
+
+    /// Awards the points for the placement of the piece hard dropped by `cells` cells.
+    /// `b2b` and `combo` are the values of `ScoringMgr` after the placement.
+    /// Returns all of the points awarded for the piece, including the drop points.
+    pub fn hard_drop(&mut self, hard_drop_info: &HardDropInfo, cells: u32, b2b: u32, combo: u32) -> u32 {
+        self.add_drop_points(cells * HARD_DROP_POINTS);
+
+        let lines = hard_drop_info.lines_cleared;
+        let is_spin = hard_drop_info.spin.is_some();
+
+        let mut points = match hard_drop_info.tspin_status {
+            TSpinStatus::Full => get_points(&T_SPIN_POINTS, lines),
+            TSpinStatus::Mini => get_points(&T_SPIN_MINI_POINTS, lines),
+            TSpinStatus::None if is_spin => get_points(&T_SPIN_MINI_POINTS, lines),
+            TSpinStatus::None => get_points(&LINE_CLEAR_POINTS, lines)
+        } * self.level;
+
+        let is_difficult = lines == 4 || (lines > 0 && (hard_drop_info.tspin_status != TSpinStatus::None || is_spin));
+        let is_b2b = is_difficult && b2b >= 2;
+        if is_b2b {
+            points = points * 3 / 2;
+        }
+
+        if lines > 0 && combo >= 2 {
+            points += COMBO_POINTS * (combo - 1) * self.level;
+        }
+
+        if lines > 0 && hard_drop_info.occupied_cells_left == 0 {
+            points += if is_b2b && lines == 4 {
+                B2B_ALL_CLEAR_POINTS
+            } else {
+                get_points(&ALL_CLEAR_POINTS, lines - 1)
+            } * self.level;
+        }
+
+        self.score += points as u64;
+        self.lines += lines;
+
+        if let Some(levels_up) = self.lines.checked_div(self.settings.lines_per_level) {
+            let max_level = self.settings.max_level.max(self.settings.start_level);
+            self.level = (self.settings.start_level + levels_up).min(max_level);
+        }
+
+        let res = points + self.drop_points;
+        self.drop_points = 0;
+
+        res
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(&self.settings);
+    }
+
+    fn add_drop_points(&mut self, points: u32) {
+        self.score += points as u64;
+        self.drop_points += points;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::piece::PieceType;
+
+    fn create_points_mgr() -> PointsMgr {
+        PointsMgr::new(&PointsSettings {
+            start_level: 2,
+            lines_per_level: 4,
+            max_level: 3,
+            level_gravity: true
+        })
+    }
+
+    fn info(lines_cleared: u32, tspin_status: TSpinStatus) -> HardDropInfo {
+        HardDropInfo {
+            lines_cleared,
+            tspin_status,
+            occupied_cells_left: 10,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn points_are_multiplied_by_level() {
+        let mut points_mgr = create_points_mgr();
+
+        points_mgr.soft_drop(3);
+        // 500 * 2 for the triple, 3 cells soft dropped and 5 cells hard dropped
+        assert_eq!(points_mgr.hard_drop(&info(3, TSpinStatus::None), 5, 0, 1), 1000 + 3 + 10);
+        assert_eq!(points_mgr.score, 1013);
+        assert_eq!(points_mgr.hard_drop(&info(0, TSpinStatus::Mini), 0, 0, 0), 100 * 2);
+        assert_eq!(points_mgr.hard_drop(&info(2, TSpinStatus::None), 0, 0, 0), 300 * 2);
+
+        assert_eq!(points_mgr.level, 3);
+
+        let mut spin = info(1, TSpinStatus::None);
+        spin.spin = Some(PieceType::S);
+        assert_eq!(points_mgr.hard_drop(&spin, 0, 0, 0), 200 * 3);
+    }
+
+    #[test]
+    fn b2b_and_combo_bonuses() {
+        let mut points_mgr = create_points_mgr();
+
+        // the first T-Spin Double starts the chain, the second one continues it
+        assert_eq!(points_mgr.hard_drop(&info(2, TSpinStatus::Full), 0, 1, 1), 1200 * 2);
+        // the level is raised after the points are awarded
+        assert_eq!(points_mgr.hard_drop(&info(2, TSpinStatus::Full), 0, 2, 2), 1200 * 2 * 3 / 2 + 50 * 2);
+        assert_eq!(points_mgr.level, 3);
+        // singles are never B2B
+        assert_eq!(points_mgr.hard_drop(&info(1, TSpinStatus::None), 0, 2, 3), 100 * 3 + 50 * 2 * 3);
+
+        let mut all_clear = info(4, TSpinStatus::None);
+        all_clear.occupied_cells_left = 0;
+        assert_eq!(points_mgr.hard_drop(&all_clear, 0, 2, 1), 800 * 3 * 3 / 2 + 3200 * 3);
+    }
+
+    #[test]
+    fn level_advances_up_to_max() {
+        let mut points_mgr = create_points_mgr();
+
+        points_mgr.hard_drop(&info(3, TSpinStatus::None), 0, 0, 0);
+        assert_eq!(points_mgr.level, 2);
+        points_mgr.hard_drop(&info(1, TSpinStatus::None), 0, 0, 0);
+        assert_eq!(points_mgr.level, 3);
+        points_mgr.hard_drop(&info(4, TSpinStatus::None), 0, 0, 0);
+        assert_eq!(points_mgr.level, 3);
+        assert_eq!(points_mgr.lines, 8);
+
+        points_mgr.reset();
+        assert_eq!((points_mgr.score, points_mgr.level, points_mgr.lines), (0, 2, 0));
+    }
+}
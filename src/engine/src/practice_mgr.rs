@@ -0,0 +1,184 @@
+/*
+ * Copyright (c) Grigory Alfyorov. Licensed under the MIT License.
+ * See the LICENSE file in the repository root for full licence text.
+ */
+
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use crate::game_mode::GameMode;
+use crate::garbage_mgr::next_hole_x;
+use crate::rng_manager::PRACTICE_RNG_STREAM;
+
+/// Maximum number of the cheese race rows on the board at once.
+pub const CHEESE_VISIBLE_ROWS: u32 = 10;
+
+/// Generates the garbage of the practice modes: the rows of `GameMode::CheeseRace`
+/// and the garbage arriving on the timer in `GameMode::Survival`.
+///
+/// The holes are generated from the seed of the game, so the same garbage is generated
+/// again when the game is replayed. Because of that it is not recorded by `ReplayMgr`.
+#[derive(Debug, Clone)]
+pub struct PracticeMgr {
+    game_mode: GameMode,
+    seed: u64,
+    rng: ChaCha8Rng,
+    last_hole_x: Option<u32>,
+    /// Cheese race rows which haven't been pushed onto the board yet.
+    rows_left: u32,
+    /// Time the next survival garbage arrives at in seconds.
+    next_garbage_sec: f32
+}
+
+impl PracticeMgr {
+    /// Returns `None` if `game_mode` doesn't have any practice garbage.
+    pub fn new(game_mode: GameMode, seed: u64) -> Option<Self> {
+        if !matches!(game_mode, GameMode::CheeseRace { .. } | GameMode::Survival { .. }) {
+            return None;
+        }
+
+        let mut practice_mgr = Self {
+            game_mode,
+            seed,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            last_hole_x: None,
+            rows_left: 0,
+            next_garbage_sec: 0.0
+        };
+        practice_mgr.reset(None);
+
+        Some(practice_mgr)
+    }
+
+    /// Returns the holes of the cheese race rows to push onto the board, one per row,
+    /// so it has `CHEESE_VISIBLE_ROWS` rows of garbage again. `garbage_rows` is the number of
+    /// the garbage rows on the board.
+    pub fn refill_cheese(&mut self, garbage_rows: u32, width: usize) -> Vec<u32> {
+        if !matches!(self.game_mode, GameMode::CheeseRace { .. }) {
+            return vec![];
+        }
+
+        let amount = CHEESE_VISIBLE_ROWS.saturating_sub(garbage_rows).min(self.rows_left);
+        self.rows_left -= amount;
+
+        (0..amount).map(|_| self.next_hole_x(width, 1.0)).collect()
+    }
+
+    /// Returns the holes of the survival garbage arrived by `elapsed_sec`, one per row.
+    pub fn update(&mut self, elapsed_sec: f32, width: usize) -> Vec<u32> {
+        let GameMode::Survival { interval_sec, rows, messiness } = self.game_mode else {
+            return vec![];
+        };
+
+        let mut holes = vec![];
+        while interval_sec > 0.0 && elapsed_sec >= self.next_garbage_sec {
+            self.next_garbage_sec += interval_sec;
+            holes.extend((0..rows).map(|_| self.next_hole_x(width, messiness)));
+        }
+
+        holes
+    }
+
+    /// Restarts the garbage from the beginning. The seed of the game is kept if `new_seed` is `None`.
+    pub fn reset(&mut self, new_seed: Option<u64>) {
+        if let Some(seed) = new_seed {
+            self.seed = seed;
+        }
+
+        self.rng = ChaCha8Rng::seed_from_u64(self.seed);
+        self.rng.set_stream(PRACTICE_RNG_STREAM);
+        self.last_hole_x = None;
+        self.rows_left = match self.game_mode {
+            GameMode::CheeseRace { lines } => lines,
+            _ => 0
+        };
+        self.next_garbage_sec = match self.game_mode {
+            GameMode::Survival { interval_sec, .. } => interval_sec,
+            _ => 0.0
+        };
+    }
+
+    /// Keeps the hole of the previous row or moves it to another column with the chance `messiness`.
+    fn next_hole_x(&mut self, width: usize, messiness: f32) -> u32 {
+        let hole_x = next_hole_x(&mut self.rng, self.last_hole_x, width, messiness);
+        self.last_hole_x = Some(hole_x);
+
+        hole_x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use super::*;
+    use crate::board::Board;
+    use crate::game_settings::GameSettings;
+    use crate::time_mgr::TimeMgr;
+    use crate::wall_kick_data::WallKickData;
+
+    const SEED: u64 = 1337;
+
+    fn create_board(game_mode: GameMode) -> Board {
+        let game_settings = GameSettings::for_game_mode(game_mode);
+        let wkd = Arc::new(WallKickData::new(&game_settings.wall_kick_data_mode));
+
+        Board::new(game_settings, wkd, SEED)
+    }
+
+    #[test]
+    fn cheese_is_refilled_up_to_lines() {
+        let mut practice_mgr = PracticeMgr::new(GameMode::CheeseRace { lines: 14 }, SEED).unwrap();
+
+        let holes = practice_mgr.refill_cheese(0, 10);
+        assert_eq!(holes.len(), CHEESE_VISIBLE_ROWS as usize);
+        assert!(holes.windows(2).all(|w| w[0] != w[1]));
+
+        assert!(practice_mgr.refill_cheese(10, 10).is_empty());
+        assert_eq!(practice_mgr.refill_cheese(7, 10).len(), 3);
+        assert_eq!(practice_mgr.refill_cheese(0, 10).len(), 1);
+        assert!(practice_mgr.refill_cheese(0, 10).is_empty());
+
+        practice_mgr.reset(None);
+        assert_eq!(practice_mgr.refill_cheese(0, 10), holes);
+    }
+
+    #[test]
+    fn survival_garbage_arrives_on_timer() {
+        let game_mode = GameMode::Survival { interval_sec: 1.0, rows: 2, messiness: 0.0 };
+        let mut practice_mgr = PracticeMgr::new(game_mode, SEED).unwrap();
+
+        assert!(practice_mgr.update(0.5, 10).is_empty());
+        let holes = practice_mgr.update(3.2, 10);
+        assert_eq!(holes.len(), 6);
+        assert!(holes.iter().all(|x| *x == holes[0]));
+        assert!(practice_mgr.update(3.9, 10).is_empty());
+
+        assert!(PracticeMgr::new(GameMode::sprint(), SEED).is_none());
+    }
+
+    #[test]
+    fn cheese_race_board_is_seed_deterministic() {
+        let mut board = create_board(GameMode::cheese_race());
+        let layout = board.get_cell_holder().get_layout().to_vec();
+
+        assert_eq!(board.get_cell_holder().get_garbage_row_count(), CHEESE_VISIBLE_ROWS);
+        assert_eq!(create_board(GameMode::cheese_race()).get_cell_holder().get_layout(), &layout[..]);
+
+        board.hard_drop().unwrap();
+        board.reset(None);
+        assert_eq!(board.get_cell_holder().get_layout(), &layout[..]);
+        assert!(board.replay_mgr.garbage.is_empty());
+    }
+
+    #[test]
+    fn survival_garbage_lands_on_board() {
+        let mut board = create_board(GameMode::Survival { interval_sec: 1.0, rows: 3, messiness: 0.5 });
+
+        let mut time_mgr = TimeMgr::new();
+        time_mgr.update(1.0);
+        board.update(&time_mgr);
+        assert_eq!(board.get_cell_holder().get_garbage_row_count(), 0);
+
+        board.hard_drop().unwrap();
+        assert_eq!(board.get_cell_holder().get_garbage_row_count(), 3);
+    }
+}
@@ -0,0 +1,468 @@
+/*
+ * Copyright (c) Grigory Alfyorov. Licensed under the MIT License.
+ * See the LICENSE file in the repository root for full licence text.
+ */
+
+//! Messages exchanged between the game server and the clients over WebSocket.
+//! Every message is sent as a JSON text frame.
+
+use std::fmt::{Display, Formatter};
+use serde::{Deserialize, Serialize};
+use crate::board::Board;
+use crate::board_command::BoardMoveDir;
+use crate::board_sync::SyncError;
+use crate::cell_holder::{diff_rows, Row, RowDelta};
+use crate::garbage_mgr::{GarbageMgr, IncomingDamage};
+use crate::piece::{PieceType, RotationDirection, RotationState};
+use crate::piece_mgr::{BoardErrorReason, PieceMgr};
+use crate::replays::{MoveAction, MoveResult};
+use crate::targeting::TargetingStrategy;
+
+pub type ConnId = usize;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WsBoardCommand {
+    Create,
+    Destroy(ConnId),
+    Move(BoardMoveDir, u32),
+    Rotate(RotationDirection),
+    HardDrop,
+    // delta
+    SoftDrop(u32),
+    HoldPiece,
+    /// Chooses who receives the attacks of the player.
+    SetTargeting(TargetingStrategy),
+}
+
+impl WsBoardCommand {
+    /// Executes the command on the `board`.
+    /// Returns the result of the move if the piece was placed.
+    pub fn apply(&self, board: &mut Board) -> Option<Result<MoveResult, BoardErrorReason>> {
+        match *self {
+            // boards are created and destroyed by the server, the targeting is handled by the server as well
+            WsBoardCommand::Create | WsBoardCommand::Destroy(_) | WsBoardCommand::SetTargeting(_) => None,
+            WsBoardCommand::Move(dir, amount) => {
+                board.move_to(dir, amount);
+                None
+            }
+            WsBoardCommand::Rotate(dir) => {
+                board.rotate(dir);
+                None
+            }
+            WsBoardCommand::HardDrop => Some(board.hard_drop()),
+            WsBoardCommand::SoftDrop(amount) => {
+                board.soft_drop(amount);
+                None
+            }
+            WsBoardCommand::HoldPiece => {
+                board.try_hold_piece();
+                None
+            }
+        }
+    }
+
+    /// Converts the `actions` of the player to the commands, merging the repeated moves and soft drops.
+    pub fn from_actions(actions: &[MoveAction]) -> Vec<WsBoardCommand> {
+        let mut commands: Vec<WsBoardCommand> = Vec::new();
+
+        for action in actions {
+            let cmd = match action {
+                MoveAction::MoveLeft => WsBoardCommand::Move(BoardMoveDir::Left, 1),
+                MoveAction::MoveRight => WsBoardCommand::Move(BoardMoveDir::Right, 1),
+                MoveAction::RotateCW => WsBoardCommand::Rotate(RotationDirection::Clockwise),
+                MoveAction::RotateCCW => WsBoardCommand::Rotate(RotationDirection::CounterClockwise),
+                MoveAction::RotateDeg180 => WsBoardCommand::Rotate(RotationDirection::Deg180),
+                MoveAction::SoftDrop => WsBoardCommand::SoftDrop(1),
+                MoveAction::HardDrop => WsBoardCommand::HardDrop,
+                MoveAction::HoldPiece => WsBoardCommand::HoldPiece,
+                // the gravity is applied by the server itself
+                MoveAction::GravityDrop => continue
+            };
+
+            match (commands.last_mut(), cmd) {
+                (Some(WsBoardCommand::Move(last_dir, amount)), WsBoardCommand::Move(dir, _)) if *last_dir == dir => {
+                    *amount += 1;
+                }
+                (Some(WsBoardCommand::SoftDrop(amount)), WsBoardCommand::SoftDrop(_)) => {
+                    *amount += 1;
+                }
+                _ => commands.push(cmd)
+            }
+        }
+
+        commands
+    }
+}
+
+/// Messages sent by the client.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum WsAction {
+    Chat(String),
+    BoardCommand(WsBoardCommand),
+    /// Board command numbered by the client. The server acknowledges it with `WsServerMessage::InputAck`.
+    BoardInput {
+        seq: u64,
+        cmd: WsBoardCommand
+    },
+    StartMatch,
+    ListLobbies,
+    JoinLobby(String),
+    /// Requests a full snapshot of the board of the player `conn`.
+    /// Sent when the client misses a `BoardDelta`.
+    RequestResync(ConnId),
+}
+
+/// Messages sent by the server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum WsServerMessage {
+    /// Full state of the board of the player `conn` at the sequence number `seq`.
+    BoardSnapshot {
+        conn: ConnId,
+        seq: u64,
+        snapshot: BoardSnapshot
+    },
+    /// Changes of the board of the player `conn` since the previous sequence number.
+    BoardDelta {
+        conn: ConnId,
+        delta: BoardDelta
+    },
+    /// The inputs of the player `conn` up to and including `input` are applied to
+    /// the board state of sequence number `seq`.
+    InputAck {
+        conn: ConnId,
+        input: u64,
+        seq: u64
+    },
+    /// Result of a hard drop of the player `conn`.
+    MoveResult {
+        conn: ConnId,
+        result: MoveResult
+    },
+    /// The player `from` has sent `damage` to the player `to`.
+    Attack {
+        from: ConnId,
+        to: ConnId,
+        damage: i32
+    },
+    /// The player `conn` has chosen the targeting `strategy`.
+    TargetingChanged {
+        conn: ConnId,
+        strategy: TargetingStrategy
+    },
+    Lobby(LobbyEvent),
+    Chat(ChatMessage),
+    Error(WsError)
+}
+
+impl WsServerMessage {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", content = "data")]
+pub enum LobbyEvent {
+    LobbyList(Vec<LobbyListing>),
+    /// Sent to the player who joined the lobby.
+    Joined {
+        lobby_id: String,
+        /// Connection id of the player who joined.
+        conn: ConnId,
+        players: Vec<LobbyPlayer>
+    },
+    PlayerJoined(LobbyPlayer),
+    PlayerLeft(LobbyPlayer),
+    MatchStarted {
+        seed: u64,
+        players: Vec<ConnId>
+    },
+    PlayerDead {
+        conn: ConnId,
+        reason: BoardErrorReason
+    },
+    /// The player `conn` was topped out by the garbage of the player `by`,
+    /// who now has `badges` badges.
+    KnockOut {
+        conn: ConnId,
+        by: ConnId,
+        badges: u32
+    },
+    MatchEnded {
+        winner: Option<ConnId>
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LobbyListing {
+    pub uuid: String,
+    pub name: String,
+    pub creator_username: String,
+    pub player_limit: usize,
+    pub player_count: usize,
+    pub is_started: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LobbyPlayer {
+    pub conn: ConnId,
+    pub username: String
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    /// Username of the sender. `None` for the messages sent by the server itself.
+    pub from: Option<String>,
+    pub message: String
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WsErrorKind {
+    InvalidMessage,
+    LobbyNotFound,
+    LobbyFull,
+    NotInLobby,
+    MatchAlreadyStarted,
+    NoMatchRunning
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WsError {
+    pub kind: WsErrorKind,
+    pub message: String
+}
+
+impl WsError {
+    pub fn new(kind: WsErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into()
+        }
+    }
+}
+
+impl Display for WsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}: {}", self.kind, self.message)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PieceSnapshot {
+    pub piece_type: PieceType,
+    pub x: u32,
+    pub y: u32,
+    pub rotation: RotationState
+}
+
+/// Everything a client needs to render a board.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BoardSnapshot {
+    pub width: usize,
+    pub height: usize,
+    pub cells: Vec<Row>,
+    pub piece: PieceSnapshot,
+    pub hold: Option<PieceType>,
+    pub queue: Vec<PieceType>,
+    pub garbage_queue: Vec<IncomingDamage>,
+    pub is_dead: bool
+}
+
+impl BoardSnapshot {
+    pub fn new(board: &Board) -> Self {
+        Self::from_parts(board.get_piece_mgr(), &board.garbage_mgr, board.is_dead)
+    }
+
+    pub fn from_parts(piece_mgr: &PieceMgr, garbage_mgr: &GarbageMgr, is_dead: bool) -> Self {
+        let cell_holder = &piece_mgr.cell_holder;
+        let piece = piece_mgr.get_piece();
+
+        Self {
+            width: cell_holder.width,
+            height: cell_holder.height,
+            cells: cell_holder.get_layout().to_vec(),
+            piece: PieceSnapshot {
+                piece_type: piece.get_type(),
+                x: piece.get_x(),
+                y: piece.get_y(),
+                rotation: piece.current_rotation
+            },
+            hold: piece_mgr.get_hold_piece(),
+            queue: piece_mgr.piece_queue.get_preview().copied().collect(),
+            garbage_queue: garbage_mgr.queue.iter().copied().collect(),
+            is_dead
+        }
+    }
+
+    /// Returns the changes required to turn the current snapshot into `newer`.
+    pub fn diff(&self, newer: &BoardSnapshot, seq: u64) -> BoardDelta {
+        let piece = if self.piece != newer.piece || self.hold != newer.hold {
+            Some(PieceDelta { piece: newer.piece, hold: newer.hold })
+        } else {
+            None
+        };
+
+        BoardDelta {
+            seq,
+            rows: diff_rows(&self.cells, &newer.cells),
+            piece,
+            queue: (self.queue != newer.queue).then(|| newer.queue.clone()),
+            garbage_queue: (self.garbage_queue != newer.garbage_queue).then(|| newer.garbage_queue.clone()),
+            is_dead: (self.is_dead != newer.is_dead).then_some(newer.is_dead)
+        }
+    }
+
+    /// Applies the changes of `delta`. Returns `Err(SyncError::InvalidRow)` without changing anything
+    /// if `delta` has a row which doesn't fit the snapshot.
+    pub fn apply_delta(&mut self, delta: &BoardDelta) -> Result<(), SyncError> {
+        delta.check_rows(self.width, self.cells.len())?;
+
+        for row in delta.rows.iter() {
+            self.cells[row.y] = row.row.clone();
+        }
+        if let Some(piece) = delta.piece {
+            self.piece = piece.piece;
+            self.hold = piece.hold;
+        }
+        if let Some(queue) = &delta.queue {
+            self.queue = queue.clone();
+        }
+        if let Some(garbage_queue) = &delta.garbage_queue {
+            self.garbage_queue = garbage_queue.clone();
+        }
+        if let Some(is_dead) = delta.is_dead {
+            self.is_dead = is_dead;
+        }
+
+        Ok(())
+    }
+}
+
+/// The current piece and the hold piece. They always change together when holding.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PieceDelta {
+    pub piece: PieceSnapshot,
+    pub hold: Option<PieceType>
+}
+
+/// Changes of a board between two consecutive sequence numbers.
+/// Only the changed rows and parts of the state are present.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BoardDelta {
+    pub seq: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rows: Vec<RowDelta>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub piece: Option<PieceDelta>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queue: Option<Vec<PieceType>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub garbage_queue: Option<Vec<IncomingDamage>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_dead: Option<bool>
+}
+
+impl BoardDelta {
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+            && self.piece.is_none()
+            && self.queue.is_none()
+            && self.garbage_queue.is_none()
+            && self.is_dead.is_none()
+    }
+
+    /// Checks that every row of the delta fits the board of `width` by `height` cells.
+    pub fn check_rows(&self, width: usize, height: usize) -> Result<(), SyncError> {
+        match self.rows.iter().find(|r| r.y >= height || r.row.cells.len() != width || r.row.width != width) {
+            Some(row) => Err(SyncError::InvalidRow { y: row.y }),
+            None => Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use super::*;
+    use crate::game_settings::GameSettings;
+    use crate::wall_kick_data::WallKickData;
+
+    fn create_board() -> Board {
+        let game_settings = GameSettings::default();
+        let wkd = Arc::new(WallKickData::new(&game_settings.wall_kick_data_mode));
+
+        Board::new(game_settings, wkd, 1337)
+    }
+
+    #[test]
+    fn snapshot_roundtrip() {
+        let mut board = create_board();
+        board.move_left(2);
+        board.hard_drop().unwrap();
+        board.try_hold_piece();
+        board.attack(3);
+
+        let msg = WsServerMessage::BoardSnapshot {
+            conn: 7,
+            seq: 3,
+            snapshot: BoardSnapshot::new(&board)
+        };
+
+        match WsServerMessage::from_json(&msg.to_json()).unwrap() {
+            WsServerMessage::BoardSnapshot { conn, seq, snapshot } => {
+                assert_eq!(conn, 7);
+                assert_eq!(seq, 3);
+                assert_eq!(snapshot, BoardSnapshot::new(&board));
+                assert_eq!(snapshot.cells.as_slice(), board.get_cell_holder().get_layout());
+                assert!(snapshot.hold.is_some());
+                assert_eq!(snapshot.garbage_queue.len(), 1);
+                // only the pieces shown to the player leave the board
+                assert_eq!(snapshot.queue.len(), board.game_settings.preview_size);
+            }
+            msg => panic!("unexpected message {:?}", msg)
+        }
+    }
+
+    #[test]
+    fn messages_are_tagged() {
+        let msg = WsServerMessage::Error(WsError::new(WsErrorKind::LobbyFull, "lobby is full"));
+        let json: serde_json::Value = serde_json::from_str(&msg.to_json()).unwrap();
+        assert_eq!(json["type"], "Error");
+        assert_eq!(json["data"]["kind"], "LobbyFull");
+
+        let msg = WsServerMessage::Lobby(LobbyEvent::MatchEnded { winner: Some(3) });
+        let json: serde_json::Value = serde_json::from_str(&msg.to_json()).unwrap();
+        assert_eq!(json["type"], "Lobby");
+        assert_eq!(json["data"]["event"], "MatchEnded");
+        assert_eq!(json["data"]["data"]["winner"], 3);
+    }
+
+    #[test]
+    fn actions_are_merged_into_commands() {
+        let actions = [
+            MoveAction::MoveLeft,
+            MoveAction::MoveLeft,
+            MoveAction::MoveRight,
+            MoveAction::GravityDrop,
+            MoveAction::SoftDrop,
+            MoveAction::SoftDrop,
+            MoveAction::RotateCW,
+            MoveAction::SoftDrop,
+            MoveAction::HardDrop
+        ];
+
+        assert_eq!(WsBoardCommand::from_actions(&actions), vec![
+            WsBoardCommand::Move(BoardMoveDir::Left, 2),
+            WsBoardCommand::Move(BoardMoveDir::Right, 1),
+            WsBoardCommand::SoftDrop(2),
+            WsBoardCommand::Rotate(RotationDirection::Clockwise),
+            WsBoardCommand::SoftDrop(1),
+            WsBoardCommand::HardDrop
+        ]);
+    }
+}
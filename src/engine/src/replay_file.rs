@@ -0,0 +1,420 @@
+/*
+ * Copyright (c) Grigory Alfyorov. Licensed under the MIT License.
+ * See the LICENSE file in the repository root for full licence text.
+ */
+
+use std::fmt::{Display, Formatter};
+use serde::{Deserialize, Serialize};
+use crate::board::Board;
+use crate::game_settings::GameSettings;
+use crate::replay_player::ReplayPlayer;
+use crate::replays::{GarbageEventKind, MoveAction, ReplayGarbage, ReplayMove};
+
+/// Version of the replay file layout. Must be incremented on every breaking change
+/// of the header, body or binary encoding.
+pub const REPLAY_FORMAT_VERSION: u16 = 1;
+/// Version of the engine that writes the replays. Replays recorded with another
+/// version of the engine are not guaranteed to play back the same way, so they are rejected.
+pub const ENGINE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+const MAGIC: &[u8; 4] = b"QRPL";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplayPlayerInfo {
+    pub user_id: Option<String>,
+    pub username: String
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplayHeader {
+    pub format_version: u16,
+    pub engine_version: String,
+    pub seed: u64,
+    pub game_settings: GameSettings,
+    pub player: ReplayPlayerInfo
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReplayBody {
+    pub moves: Vec<ReplayMove>,
+    pub garbage: Vec<ReplayGarbage>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplayError {
+    InvalidMagic,
+    IncompatibleVersion {
+        format_version: u16,
+        engine_version: String
+    },
+    UnexpectedEof,
+    InvalidData(String)
+}
+
+impl Display for ReplayError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplayError::InvalidMagic => write!(f, "Not a replay file"),
+            ReplayError::IncompatibleVersion { format_version, engine_version } => write!(
+                f,
+                "Incompatible replay: format version {}, engine version {} (expected {}, {})",
+                format_version, engine_version, REPLAY_FORMAT_VERSION, ENGINE_VERSION
+            ),
+            ReplayError::UnexpectedEof => write!(f, "Unexpected end of replay file"),
+            ReplayError::InvalidData(msg) => write!(f, "Invalid replay data: {}", msg)
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+/// A self-contained replay file: everything needed to reproduce a recorded game.
+///
+/// Can be stored either as JSON or in a compact binary form. The binary form starts with
+/// the `QRPL` magic and the format version, followed by the JSON encoded header and the
+/// moves and garbage events packed as little-endian values.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Replay {
+    pub header: ReplayHeader,
+    pub body: ReplayBody
+}
+
+impl Replay {
+    pub fn new(game_settings: GameSettings, seed: u64, player: ReplayPlayerInfo, body: ReplayBody) -> Self {
+        Self {
+            header: ReplayHeader {
+                format_version: REPLAY_FORMAT_VERSION,
+                engine_version: ENGINE_VERSION.to_string(),
+                seed,
+                game_settings,
+                player
+            },
+            body
+        }
+    }
+
+    /// Creates a replay of everything recorded on the `board` so far.
+    pub fn from_board(board: &Board, player: ReplayPlayerInfo) -> Self {
+        let body = ReplayBody {
+            moves: board.replay_mgr.moves.clone(),
+            garbage: board.replay_mgr.garbage.clone()
+        };
+
+        Self::new(board.game_settings.clone(), board.get_seed(), player, body)
+    }
+
+    /// Creates a `ReplayPlayer` ready to play back this replay.
+    pub fn player(&self) -> ReplayPlayer {
+        ReplayPlayer::new(self.header.game_settings.clone(), self.header.seed, self.body.moves.clone())
+            .with_garbage(self.body.garbage.clone())
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    /// Parses a JSON replay. The version is checked before the rest of the replay,
+    /// so replays of older versions are rejected even if their layout differs.
+    pub fn from_json(json: &str) -> Result<Self, ReplayError> {
+        let value: serde_json::Value = serde_json::from_str(json)
+            .map_err(|e| ReplayError::InvalidData(e.to_string()))?;
+
+        let header = &value["header"];
+        let format_version = header["format_version"]
+            .as_u64()
+            .ok_or_else(|| ReplayError::InvalidData("Missing format version".to_string()))?;
+        let engine_version = header["engine_version"].as_str().unwrap_or_default();
+
+        check_version(format_version as u16, engine_version)?;
+
+        serde_json::from_value(value).map_err(|e| ReplayError::InvalidData(e.to_string()))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let header = serde_json::to_vec(&self.header).unwrap();
+
+        let mut bytes = Vec::with_capacity(
+            MAGIC.len() + 2 + 4 + header.len()
+                + 4 + self.body.moves.len() * 5
+                + 4 + self.body.garbage.len() * 17
+        );
+
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&self.header.format_version.to_le_bytes());
+        bytes.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&header);
+
+        bytes.extend_from_slice(&(self.body.moves.len() as u32).to_le_bytes());
+        for mv in self.body.moves.iter() {
+            bytes.push(move_action_to_byte(mv.action));
+            bytes.extend_from_slice(&mv.timestamp.to_le_bytes());
+        }
+
+        bytes.extend_from_slice(&(self.body.garbage.len() as u32).to_le_bytes());
+        for g in self.body.garbage.iter() {
+            bytes.push(match g.kind {
+                GarbageEventKind::Attack => 0,
+                GarbageEventKind::Immediate => 1
+            });
+            bytes.extend_from_slice(&g.timestamp.to_le_bytes());
+            bytes.extend_from_slice(&(g.move_index as u32).to_le_bytes());
+            bytes.extend_from_slice(&g.amount.to_le_bytes());
+            bytes.extend_from_slice(&g.hole_x.to_le_bytes());
+        }
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ReplayError> {
+        let mut reader = ByteReader { bytes, pos: 0 };
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(ReplayError::InvalidMagic);
+        }
+
+        let format_version = reader.read_u16()?;
+        if format_version != REPLAY_FORMAT_VERSION {
+            return Err(ReplayError::IncompatibleVersion {
+                format_version,
+                engine_version: String::new()
+            });
+        }
+
+        let header_len = reader.read_u32()? as usize;
+        let header: ReplayHeader = serde_json::from_slice(reader.take(header_len)?)
+            .map_err(|e| ReplayError::InvalidData(e.to_string()))?;
+
+        check_version(header.format_version, &header.engine_version)?;
+
+        let moves_len = reader.read_u32()? as usize;
+        let mut moves = Vec::with_capacity(moves_len.min(bytes.len()));
+        for _ in 0..moves_len {
+            let action = move_action_from_byte(reader.read_u8()?)?;
+            let timestamp = reader.read_f32()?;
+            moves.push(ReplayMove { action, timestamp });
+        }
+
+        let garbage_len = reader.read_u32()? as usize;
+        let mut garbage = Vec::with_capacity(garbage_len.min(bytes.len()));
+        for _ in 0..garbage_len {
+            let kind = match reader.read_u8()? {
+                0 => GarbageEventKind::Attack,
+                1 => GarbageEventKind::Immediate,
+                b => return Err(ReplayError::InvalidData(format!("Unknown garbage kind {}", b)))
+            };
+
+            garbage.push(ReplayGarbage {
+                kind,
+                timestamp: reader.read_f32()?,
+                move_index: reader.read_u32()? as usize,
+                amount: reader.read_i32()?,
+                hole_x: reader.read_u32()?
+            });
+        }
+
+        Ok(Self {
+            header,
+            body: ReplayBody { moves, garbage }
+        })
+    }
+}
+
+fn check_version(format_version: u16, engine_version: &str) -> Result<(), ReplayError> {
+    if format_version != REPLAY_FORMAT_VERSION || engine_version != ENGINE_VERSION {
+        return Err(ReplayError::IncompatibleVersion {
+            format_version,
+            engine_version: engine_version.to_string()
+        });
+    }
+
+    Ok(())
+}
+
+fn move_action_to_byte(action: MoveAction) -> u8 {
+    match action {
+        MoveAction::MoveLeft => 0,
+        MoveAction::MoveRight => 1,
+        MoveAction::RotateCW => 2,
+        MoveAction::RotateCCW => 3,
+        MoveAction::RotateDeg180 => 4,
+        MoveAction::SoftDrop => 5,
+        MoveAction::HardDrop => 6,
+        MoveAction::HoldPiece => 7,
+        MoveAction::GravityDrop => 8
+    }
+}
+
+fn move_action_from_byte(byte: u8) -> Result<MoveAction, ReplayError> {
+    Ok(match byte {
+        0 => MoveAction::MoveLeft,
+        1 => MoveAction::MoveRight,
+        2 => MoveAction::RotateCW,
+        3 => MoveAction::RotateCCW,
+        4 => MoveAction::RotateDeg180,
+        5 => MoveAction::SoftDrop,
+        6 => MoveAction::HardDrop,
+        7 => MoveAction::HoldPiece,
+        8 => MoveAction::GravityDrop,
+        _ => return Err(ReplayError::InvalidData(format!("Unknown move action {}", byte)))
+    })
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ReplayError> {
+        let end = self.pos.checked_add(len).ok_or(ReplayError::UnexpectedEof)?;
+        let slice = self.bytes.get(self.pos..end).ok_or(ReplayError::UnexpectedEof)?;
+        self.pos = end;
+
+        Ok(slice)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], ReplayError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn read_u8(&mut self) -> Result<u8, ReplayError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, ReplayError> {
+        Ok(u16::from_le_bytes(self.take_array()?))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, ReplayError> {
+        Ok(u32::from_le_bytes(self.take_array()?))
+    }
+
+    fn read_i32(&mut self) -> Result<i32, ReplayError> {
+        Ok(i32::from_le_bytes(self.take_array()?))
+    }
+
+    fn read_f32(&mut self) -> Result<f32, ReplayError> {
+        Ok(f32::from_le_bytes(self.take_array()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use super::*;
+    use crate::time_mgr::TimeMgr;
+    use crate::wall_kick_data::WallKickData;
+
+    const SEED: u64 = 420;
+    const DT: f32 = 1.0 / 60.0;
+
+    /// Plays a game in which the board receives both queued and immediate garbage.
+    fn play_game() -> Board {
+        let mut game_settings = GameSettings::default();
+        // the delay countdown depends on the amount of frames, which are not recorded
+        game_settings.attack.garbage_delay_ms = 0;
+        let wkd = Arc::new(WallKickData::new(&game_settings.wall_kick_data_mode));
+        let mut board = Board::new(game_settings, wkd, SEED);
+        let mut time_mgr = TimeMgr::new();
+
+        for i in 0..8u32 {
+            for _ in 0..30 {
+                time_mgr.update(DT);
+                board.update(&time_mgr);
+            }
+
+            if i % 4 == 1 {
+                board.attack(2);
+            }
+            if i == 4 {
+                board.push_garbage(3, 0);
+            }
+
+            if i % 2 == 0 {
+                board.move_left(i % 5);
+            } else {
+                board.move_right(i % 3 + 1);
+            }
+
+            board.hard_drop().unwrap();
+        }
+
+        board
+    }
+
+    fn player_info() -> ReplayPlayerInfo {
+        ReplayPlayerInfo {
+            user_id: Some("d3c8a0a1".to_string()),
+            username: "lunacys".to_string()
+        }
+    }
+
+    #[test]
+    fn binary_roundtrip() {
+        let replay = Replay::from_board(&play_game(), player_info());
+        assert!(!replay.body.garbage.is_empty());
+
+        let loaded = Replay::from_bytes(&replay.to_bytes()).unwrap();
+        assert_eq!(replay, loaded);
+    }
+
+    #[test]
+    fn json_roundtrip() {
+        let replay = Replay::from_board(&play_game(), player_info());
+
+        let loaded = Replay::from_json(&replay.to_json()).unwrap();
+        assert_eq!(replay, loaded);
+    }
+
+    #[test]
+    fn loaded_replay_reproduces_the_game() {
+        let board = play_game();
+        let replay = Replay::from_bytes(&Replay::from_board(&board, player_info()).to_bytes()).unwrap();
+
+        let mut player = replay.player();
+        player.play_to_end();
+
+        assert!(player.is_finished());
+        assert_eq!(
+            board.get_cell_holder().get_layout(),
+            player.get_board().get_cell_holder().get_layout()
+        );
+        assert_eq!(board.replay_mgr.garbage, player.get_board().replay_mgr.garbage);
+    }
+
+    #[test]
+    fn rejects_incompatible_versions() {
+        let mut replay = Replay::from_board(&play_game(), player_info());
+        replay.header.engine_version = "0.0.0-old".to_string();
+
+        assert!(matches!(
+            Replay::from_bytes(&replay.to_bytes()),
+            Err(ReplayError::IncompatibleVersion { .. })
+        ));
+        assert!(matches!(
+            Replay::from_json(&replay.to_json()),
+            Err(ReplayError::IncompatibleVersion { .. })
+        ));
+
+        replay.header.engine_version = ENGINE_VERSION.to_string();
+        replay.header.format_version = REPLAY_FORMAT_VERSION + 1;
+
+        assert!(matches!(
+            Replay::from_bytes(&replay.to_bytes()),
+            Err(ReplayError::IncompatibleVersion { .. })
+        ));
+        assert!(matches!(
+            Replay::from_json(&replay.to_json()),
+            Err(ReplayError::IncompatibleVersion { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let bytes = Replay::from_board(&play_game(), player_info()).to_bytes();
+
+        assert_eq!(Replay::from_bytes(b"NOPE").unwrap_err(), ReplayError::InvalidMagic);
+        assert_eq!(Replay::from_bytes(&bytes[..bytes.len() - 3]).unwrap_err(), ReplayError::UnexpectedEof);
+        assert!(matches!(Replay::from_json("{}"), Err(ReplayError::InvalidData(_))));
+    }
+}
@@ -0,0 +1,390 @@
+/*
+ * Copyright (c) Grigory Alfyorov. Licensed under the MIT License.
+ * See the LICENSE file in the repository root for full licence text.
+ */
+
+use std::sync::Arc;
+use crate::board::Board;
+use crate::game_settings::GameSettings;
+use crate::piece_mgr::BoardErrorReason;
+use crate::replays::{GarbageEventKind, MoveAction, MoveResult, ReplayGarbage, ReplayMove};
+use crate::time_mgr::TimeMgr;
+use crate::wall_kick_data::WallKickData;
+
+/// Plays back moves recorded by `ReplayMgr` on a fresh `Board`.
+///
+/// Gravity of the underlying board is disabled during playback: every gravity soft drop and
+/// lock hard drop that happened during the original game is already a part of the recording,
+/// so the board only receives the recorded actions at their recorded timestamps.
+/// Received garbage is injected from the recorded `ReplayGarbage` events.
+/// Given the same seed and `GameSettings` the playback reproduces the exact same
+/// cell layout, `BoardStats` and `MoveResult` sequence.
+#[derive(Debug)]
+pub struct ReplayPlayer {
+    board: Board,
+    game_settings: GameSettings,
+    wkd: Arc<WallKickData>,
+    seed: u64,
+    moves: Vec<ReplayMove>,
+    garbage: Vec<ReplayGarbage>,
+    /// Index of the next move to play.
+    cursor: usize,
+    /// Index of the next garbage event to play.
+    garbage_cursor: usize,
+    time_mgr: TimeMgr,
+    results: Vec<Result<MoveResult, BoardErrorReason>>,
+    pub is_paused: bool
+}
+
+impl ReplayPlayer {
+    pub fn new(game_settings: GameSettings, seed: u64, moves: Vec<ReplayMove>) -> Self {
+        let wkd = Arc::new(WallKickData::new(&game_settings.wall_kick_data_mode));
+
+        Self::with_wall_kick_data(game_settings, wkd, seed, moves)
+    }
+
+    pub fn with_wall_kick_data(
+        game_settings: GameSettings,
+        wkd: Arc<WallKickData>,
+        seed: u64,
+        moves: Vec<ReplayMove>
+    ) -> Self {
+        let board = create_board(&game_settings, &wkd, seed);
+
+        Self {
+            board,
+            game_settings,
+            wkd,
+            seed,
+            moves,
+            garbage: vec![],
+            cursor: 0,
+            garbage_cursor: 0,
+            time_mgr: TimeMgr::new(),
+            results: vec![],
+            is_paused: false
+        }
+    }
+
+    /// Sets the garbage received during the recorded game.
+    pub fn with_garbage(mut self, garbage: Vec<ReplayGarbage>) -> Self {
+        self.garbage = garbage;
+        self
+    }
+
+    /// Advances the playback clock by `dt` seconds and plays every move recorded up to that point.
+    /// Does nothing while paused. Returns the results of the hard drops played during this call.
+    pub fn update(&mut self, dt: f32) -> Vec<Result<MoveResult, BoardErrorReason>> {
+        if self.is_paused {
+            return vec![];
+        }
+
+        let target = self.time_mgr.elapsed_sec + dt;
+        let first_new = self.results.len();
+        self.play_until(target);
+
+        self.results[first_new..].to_vec()
+    }
+
+    /// Moves the playback to the timestamp `timestamp` (in seconds).
+    /// Seeking backwards replays the game from the beginning, as the board cannot be rewound.
+    pub fn seek(&mut self, timestamp: f32) {
+        if timestamp < self.time_mgr.elapsed_sec {
+            self.restart();
+        }
+
+        self.play_until(timestamp);
+    }
+
+    /// Plays moves up to and including the next hard drop.
+    /// Returns `None` if there are no more pieces in the recording.
+    pub fn step_piece(&mut self) -> Option<Result<MoveResult, BoardErrorReason>> {
+        while let Some(action) = self.play_next() {
+            if action == Some(MoveAction::HardDrop) {
+                return self.results.last().cloned();
+            }
+        }
+
+        None
+    }
+
+    /// Moves the playback right after the hard drop of piece number `piece_index`,
+    /// where `0` is the initial state of the board.
+    pub fn seek_piece(&mut self, piece_index: usize) {
+        if piece_index < self.get_piece_index() {
+            self.restart();
+        }
+
+        while self.get_piece_index() < piece_index {
+            if self.step_piece().is_none() {
+                break;
+            }
+        }
+    }
+
+    /// Plays all of the remaining moves and returns results of every hard drop in the recording.
+    pub fn play_to_end(&mut self) -> &[Result<MoveResult, BoardErrorReason>] {
+        while self.play_next().is_some() {}
+
+        &self.results
+    }
+
+    /// Resets the board and rewinds the playback to the very beginning.
+    pub fn restart(&mut self) {
+        self.board = create_board(&self.game_settings, &self.wkd, self.seed);
+        self.cursor = 0;
+        self.garbage_cursor = 0;
+        self.time_mgr.reset();
+        self.results.clear();
+    }
+
+    pub fn pause(&mut self) {
+        self.is_paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.is_paused = false;
+    }
+
+    pub fn get_board(&self) -> &Board {
+        &self.board
+    }
+
+    /// Returns the results of all of the hard drops played so far.
+    pub fn get_results(&self) -> &[Result<MoveResult, BoardErrorReason>] {
+        &self.results
+    }
+
+    /// Returns the amount of pieces hard dropped so far.
+    pub fn get_piece_index(&self) -> usize {
+        self.results.len()
+    }
+
+    /// Returns current playback time in seconds.
+    pub fn get_elapsed(&self) -> f32 {
+        self.time_mgr.elapsed_sec
+    }
+
+    /// Returns the timestamp of the last recorded event.
+    pub fn get_duration(&self) -> f32 {
+        let last_move = self.moves.last().map_or(0.0, |m| m.timestamp);
+        let last_garbage = self.garbage.last().map_or(0.0, |g| g.timestamp);
+
+        last_move.max(last_garbage)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.cursor >= self.moves.len() && self.garbage_cursor >= self.garbage.len()
+    }
+
+    /// Returns the timestamp of the next recorded event, be it a move or received garbage.
+    fn next_timestamp(&self) -> Option<f32> {
+        match self.next_garbage() {
+            Some(garbage) => Some(garbage.timestamp),
+            None => self.moves.get(self.cursor).map(|m| m.timestamp)
+        }
+    }
+
+    /// Returns the next garbage event if it must be played before the next move.
+    fn next_garbage(&self) -> Option<&ReplayGarbage> {
+        self.garbage
+            .get(self.garbage_cursor)
+            .filter(|g| g.move_index <= self.cursor)
+    }
+
+    /// Plays the next recorded event. Returns `None` if there are no events left,
+    /// `Some(None)` if garbage was received and `Some(Some(action))` if a move was played.
+    fn play_next(&mut self) -> Option<Option<MoveAction>> {
+        if let Some(garbage) = self.next_garbage().cloned() {
+            self.play_garbage(&garbage);
+            return Some(None);
+        }
+
+        let mv = self.moves.get(self.cursor)?.clone();
+        self.play_move(&mv);
+
+        Some(Some(mv.action))
+    }
+
+    fn play_until(&mut self, timestamp: f32) {
+        while self.next_timestamp().is_some_and(|t| t <= timestamp) {
+            self.play_next();
+        }
+
+        if timestamp > self.time_mgr.elapsed_sec {
+            self.time_mgr.advance_to(timestamp);
+            self.board.update(&self.time_mgr);
+        }
+    }
+
+    fn play_move(&mut self, mv: &ReplayMove) {
+        self.sync_time(mv.timestamp);
+
+        if let Some(res) = self.board.exec_action(mv.action) {
+            self.results.push(res);
+        }
+
+        self.cursor += 1;
+    }
+
+    fn play_garbage(&mut self, garbage: &ReplayGarbage) {
+        self.sync_time(garbage.timestamp);
+
+        match garbage.kind {
+            GarbageEventKind::Attack => self.board.attack_at(garbage.amount, garbage.hole_x),
+            GarbageEventKind::Immediate => self.board.push_garbage_at(garbage.amount as u32, garbage.hole_x)
+        }
+
+        self.garbage_cursor += 1;
+    }
+
+    /// Keeps the board's clock and stats in sync with the recording.
+    fn sync_time(&mut self, timestamp: f32) {
+        // the last frame is empty if the event has the same timestamp,
+        // so the garbage delays are not advanced twice
+        self.time_mgr.advance_to(timestamp.max(self.time_mgr.elapsed_sec));
+
+        self.board.update(&self.time_mgr);
+    }
+}
+
+fn create_board(game_settings: &GameSettings, wkd: &Arc<WallKickData>, seed: u64) -> Board {
+    let mut board = Board::new(game_settings.clone(), Arc::clone(wkd), seed);
+    // all of the gravity moves are already recorded
+    board.gravity_mgr.disable();
+
+    board
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::piece::RotationDirection;
+
+    const SEED: u64 = 1337;
+    const DT: f32 = 1.0 / 60.0;
+
+    /// Plays a short scripted game with gravity enabled and returns the board and its results.
+    fn play_game() -> (Board, Vec<Result<MoveResult, BoardErrorReason>>) {
+        let game_settings = GameSettings::default();
+        let wkd = Arc::new(WallKickData::new(&game_settings.wall_kick_data_mode));
+        let mut board = Board::new(game_settings, wkd, SEED);
+        let mut time_mgr = TimeMgr::new();
+        let mut results = vec![];
+
+        for i in 0..14u32 {
+            // let gravity do some work between the pieces
+            for _ in 0..(20 + i * 7) {
+                time_mgr.update(DT);
+                if let Some(res) = board.update(&time_mgr) {
+                    results.push(res);
+                }
+            }
+
+            match i % 4 {
+                0 => { board.move_left(i % 5); }
+                1 => { board.move_right(i % 4 + 1); }
+                2 => { board.rotate(RotationDirection::Clockwise); board.move_left(2); }
+                _ => { board.rotate(RotationDirection::CounterClockwise); board.move_right(3); }
+            }
+
+            if i % 5 == 3 {
+                board.try_hold_piece();
+            }
+
+            time_mgr.update(DT);
+            board.update(&time_mgr);
+            results.push(board.hard_drop());
+        }
+
+        (board, results)
+    }
+
+    fn assert_results_eq(
+        a: &[Result<MoveResult, BoardErrorReason>],
+        b: &[Result<MoveResult, BoardErrorReason>]
+    ) {
+        assert_eq!(a.len(), b.len());
+
+        for (a, b) in a.iter().zip(b.iter()) {
+            match (a, b) {
+                (Ok(a), Ok(b)) => {
+                    assert_eq!(a.timestamp, b.timestamp);
+                    assert_eq!(a.mod_bits, b.mod_bits);
+                    assert_eq!(a.b2b, b.b2b);
+                    assert_eq!(a.combo, b.combo);
+                    assert_eq!(a.hard_drop_info.lines_cleared, b.hard_drop_info.lines_cleared);
+                    assert_eq!(a.hard_drop_info.occupied_cells_left, b.hard_drop_info.occupied_cells_left);
+                    assert_eq!(a.move_queue, b.move_queue);
+                }
+                (Err(_), Err(_)) => {}
+                _ => panic!("results differ: {:?} vs {:?}", a, b)
+            }
+        }
+    }
+
+    #[test]
+    fn playback_reproduces_the_game() {
+        let (board, results) = play_game();
+
+        let mut player = ReplayPlayer::new(GameSettings::default(), SEED, board.replay_mgr.moves.clone());
+        let replayed = player.play_to_end().to_vec();
+
+        assert!(player.is_finished());
+        assert_results_eq(&results, &replayed);
+        assert_eq!(
+            board.get_cell_holder().get_layout(),
+            player.get_board().get_cell_holder().get_layout()
+        );
+
+        let (s1, s2) = (board.board_stats, player.get_board().board_stats);
+        assert_eq!(s1.total_pieces, s2.total_pieces);
+        assert_eq!(s1.singles, s2.singles);
+        assert_eq!(s1.doubles, s2.doubles);
+        assert_eq!(s1.max_combo, s2.max_combo);
+        assert_eq!(board.replay_mgr.moves, player.get_board().replay_mgr.moves);
+    }
+
+    #[test]
+    fn step_and_seek_by_piece() {
+        let (board, results) = play_game();
+        let mut player = ReplayPlayer::new(GameSettings::default(), SEED, board.replay_mgr.moves.clone());
+
+        player.step_piece();
+        player.step_piece();
+        assert_eq!(player.get_piece_index(), 2);
+
+        player.seek_piece(10);
+        assert_eq!(player.get_piece_index(), 10);
+
+        // seeking backwards restarts the playback
+        player.seek_piece(5);
+        assert_eq!(player.get_piece_index(), 5);
+        assert_results_eq(&results[..5], player.get_results());
+
+        player.seek_piece(usize::MAX);
+        assert!(player.is_finished());
+        assert_results_eq(&results, player.get_results());
+    }
+
+    #[test]
+    fn update_respects_pause_and_seek() {
+        let (board, _) = play_game();
+        let mut player = ReplayPlayer::new(GameSettings::default(), SEED, board.replay_mgr.moves.clone());
+
+        player.pause();
+        assert!(player.update(100.0).is_empty());
+        assert_eq!(player.get_elapsed(), 0.0);
+
+        player.resume();
+        let played = player.update(player.get_duration() / 2.0);
+        assert!(!played.is_empty());
+        assert!(!player.is_finished());
+
+        player.seek(0.0);
+        assert_eq!(player.get_piece_index(), 0);
+
+        player.seek(player.get_duration());
+        assert!(player.is_finished());
+    }
+}
@@ -6,8 +6,10 @@
 use serde::{Deserialize, Serialize};
 use crate::cell_holder::CellHolder;
 use crate::damage_calculation::{calculate_damage, create_board_move_bits};
+use crate::game_mode::GameResult;
 use crate::game_settings::AttackSettings;
 use crate::garbage_mgr::{GarbageHardDropResult, GarbageMgr};
+use crate::piece::PieceType;
 use crate::scoring::{ScoringMgr, TSpinStatus};
 use crate::time_mgr::{TimeMgr};
 
@@ -18,10 +20,14 @@ pub struct HardDropInfo {
     pub lines_cleared: u32,
     pub tspin_status: TSpinStatus,
     pub last_move_type: LastMoveType,
-    pub occupied_cells_left: u32
+    pub occupied_cells_left: u32,
+    /// Type of the piece if it has performed a spin under the all-spin rules.
+    /// T-Spins are reported by `tspin_status` only.
+    #[serde(default)]
+    pub spin: Option<PieceType>
 }
 
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum MoveAction {
     MoveLeft,
     MoveRight,
@@ -32,6 +38,8 @@ pub enum MoveAction {
 
     SoftDrop,
     HardDrop,
+    /// The piece is moved down one cell by the gravity. Unlike `SoftDrop`, it is not awarded any points.
+    GravityDrop,
 
     HoldPiece
 }
@@ -47,7 +55,8 @@ impl Default for HardDropInfo {
             lines_cleared: 0,
             tspin_status: TSpinStatus::None,
             last_move_type: LastMoveType::None,
-            occupied_cells_left: 0
+            occupied_cells_left: 0,
+            spin: None
         }
     }
 }
@@ -59,6 +68,18 @@ pub struct MoveResult {
     pub mod_bits: u32,
     pub b2b: u32,
     pub combo: u32,
+    /// Length of the B2B chain broken by the move, 0 if no chain was broken.
+    #[serde(default)]
+    pub broken_b2b: u32,
+    /// Badges of the player at the time of the move.
+    #[serde(default)]
+    pub badges: u32,
+    /// Points of the single-player scoring awarded for the piece, including its drop points.
+    #[serde(default)]
+    pub points: u32,
+    /// Result of the game if the move has finished it.
+    #[serde(default)]
+    pub game_result: Option<GameResult>,
     /// False if the piece fails to spawn or apply to the cell holder. True otherwise.
     pub is_success: bool,
     pub attack: GarbageHardDropResult,
@@ -87,6 +108,10 @@ impl Default for MoveResult {
             mod_bits: 0,
             b2b: 0,
             combo: 0,
+            broken_b2b: 0,
+            badges: 0,
+            points: 0,
+            game_result: None,
             is_success: false,
             attack: GarbageHardDropResult::default(),
             move_queue: vec![],
@@ -104,14 +129,31 @@ impl MoveResult {
         cell_holder: &CellHolder,
         move_queue: Vec<(f32, MoveAction)>,
         cur_sec: f32
+    ) -> MoveResult {
+        let mut result = Self::without_garbage(scoring_mgr, hard_drop_info, attack_settings, cell_holder);
+        result.move_queue = move_queue;
+        result.timestamp = cur_sec;
+        result.attack = garbage_mgr.hard_drop(hard_drop_info.lines_cleared, result.attack.out_damage);
+
+        result
+    }
+
+    /// Composes the result of the move without touching the garbage queue,
+    /// so `attack.out_damage` is the attack before cancelling the incoming garbage.
+    /// Used for looking ahead, for example, by the bots.
+    pub fn without_garbage(
+        scoring_mgr: &ScoringMgr,
+        hard_drop_info: HardDropInfo,
+        attack_settings: &AttackSettings,
+        cell_holder: &CellHolder
     ) -> MoveResult {
         let mut result = MoveResult {
             is_success: true,
             b2b: scoring_mgr.b2b,
             combo: scoring_mgr.combo,
+            broken_b2b: scoring_mgr.broken_b2b,
+            badges: scoring_mgr.badges,
             hard_drop_info,
-            move_queue,
-            timestamp: cur_sec,
             ..Default::default()
         };
 
@@ -122,8 +164,7 @@ impl MoveResult {
         );
         result.mod_bits = bits;
 
-        let dmg = calculate_damage(attack_settings, &result);
-        result.attack = garbage_mgr.hard_drop(hard_drop_info.lines_cleared, dmg as i32);
+        result.attack.out_damage = calculate_damage(attack_settings, &result) as i32;
 
         result
     }
@@ -149,9 +190,26 @@ pub struct BoardStats {
     pub tspin_singles: u32,
     pub tspin_doubles: u32,
     pub tspin_triples: u32,
+    pub s_spins: u32,
+    pub z_spins: u32,
+    pub l_spins: u32,
+    pub j_spins: u32,
+    pub i_spins: u32,
     pub all_clears: u32,
+    pub lines: u32,
+    /// Rows containing garbage cleared by the player.
+    pub garbage_cleared: u32,
+    /// Score of the single-player scoring, 0 if it is disabled.
+    pub score: u64,
+    /// Level of the single-player scoring, 0 if it is disabled.
+    pub level: u32,
     pub max_combo: u32,
-    pub max_b2b: u32
+    pub max_b2b: u32,
+    /// Opponents knocked out by the player.
+    pub kos: u32,
+    pub badges: u32,
+    /// Result of the game, `None` until the game is finished.
+    pub game_result: Option<GameResult>
 }
 
 impl BoardStats {
@@ -169,6 +227,7 @@ impl BoardStats {
     /// Updates all current stats using data from `HardDropInfo` and `ScoringMgr`.
     pub fn hard_drop(&mut self, hard_drop_info: &HardDropInfo, scoring_mgr: &ScoringMgr) {
         self.total_pieces += 1;
+        self.lines += hard_drop_info.lines_cleared;
         
         match hard_drop_info.lines_cleared {
             1 => self.singles += 1,
@@ -199,6 +258,15 @@ impl BoardStats {
             }
         }
 
+        match hard_drop_info.spin {
+            Some(PieceType::S) => self.s_spins += 1,
+            Some(PieceType::Z) => self.z_spins += 1,
+            Some(PieceType::L) => self.l_spins += 1,
+            Some(PieceType::J) => self.j_spins += 1,
+            Some(PieceType::I) => self.i_spins += 1,
+            _ => {}
+        }
+
         if hard_drop_info.occupied_cells_left == 0 {
             self.all_clears += 1;
         }
@@ -222,21 +290,54 @@ impl BoardStats {
         self.tspin_singles = default.tspin_singles;
         self.tspin_doubles = default.tspin_doubles;
         self.tspin_triples = default.tspin_triples;
+        self.s_spins = default.s_spins;
+        self.z_spins = default.z_spins;
+        self.l_spins = default.l_spins;
+        self.j_spins = default.j_spins;
+        self.i_spins = default.i_spins;
         self.all_clears = default.all_clears;
+        self.lines = default.lines;
+        self.garbage_cleared = default.garbage_cleared;
+        self.score = default.score;
+        self.level = default.level;
+        self.game_result = default.game_result;
         self.max_combo = default.max_combo;
         self.max_b2b = default.max_b2b;
+        self.kos = default.kos;
+        self.badges = default.badges;
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ReplayMove {
     pub action: MoveAction,
     pub timestamp: f32
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GarbageEventKind {
+    /// Damage queued by `Board::attack`. It lands on the board according to the garbage delay.
+    Attack,
+    /// Garbage rows pushed directly onto the board by `Board::push_garbage`.
+    Immediate
+}
+
+/// Garbage received by the board. Holes are generated randomly, so they must be
+/// recorded alongside the moves to be able to reproduce the game.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplayGarbage {
+    pub kind: GarbageEventKind,
+    pub timestamp: f32,
+    /// Amount of moves recorded before the garbage was received.
+    pub move_index: usize,
+    pub amount: i32,
+    pub hole_x: u32
+}
+
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct ReplayMgr {
     pub moves: Vec<ReplayMove>,
+    pub garbage: Vec<ReplayGarbage>,
     pub cur_move_queue: Vec<(f32, MoveAction)>
 }
 
@@ -253,6 +354,16 @@ impl ReplayMgr {
         self.cur_move_queue.push((timestamp, move_action));
     }
 
+    pub fn push_garbage(&mut self, timestamp: f32, kind: GarbageEventKind, amount: i32, hole_x: u32) {
+        self.garbage.push(ReplayGarbage {
+            kind,
+            timestamp,
+            move_index: self.moves.len(),
+            amount,
+            hole_x
+        });
+    }
+
     pub fn end_move(&mut self) -> Vec<(f32, MoveAction)> {
         let res = self.cur_move_queue.clone();
         self.cur_move_queue.clear();
@@ -262,6 +373,7 @@ impl ReplayMgr {
 
     pub fn reset(&mut self) {
         self.moves.clear();
+        self.garbage.clear();
         self.cur_move_queue.clear();
     }
 }
\ No newline at end of file
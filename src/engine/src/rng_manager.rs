@@ -9,6 +9,16 @@ use rand::distributions::uniform::{SampleRange, SampleUniform};
 use rand::prelude::*;
 use rand_chacha::ChaCha8Rng;
 
+// Streams of the game seed of the random generators other than the piece generator,
+// which uses the stream 0, so no two of them repeat the same random numbers.
+
+/// Stream of the garbage holes, see `GarbageMgr`.
+pub const GARBAGE_RNG_STREAM: u64 = 1;
+/// Stream of the practice garbage, see `PracticeMgr`.
+pub const PRACTICE_RNG_STREAM: u64 = 2;
+/// Stream of the mistakes of the bots.
+pub const BOT_MISTAKE_RNG_STREAM: u64 = 3;
+
 #[derive(Debug, Clone)]
 pub struct RngManager {
     rng: ChaCha8Rng,
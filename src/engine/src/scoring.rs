@@ -7,31 +7,16 @@ use std::collections::VecDeque;
 use serde::{Deserialize, Serialize};
 use crate::replays::{HardDropInfo, LastMoveType};
 
-#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum TSpinStatus {
     /// T-Spin wasn't performed.
     None,
-    /// Full T-Spin: at least 3 occupied cells around the corners of the T piece.
+    /// Full T-Spin: at least 3 occupied corners around the T piece including both of the front ones.
     Full,
-    /// Mini T-Spin: 2 occupied cells around the corners of the T piece.
+    /// Mini T-Spin: at least 3 occupied corners around the T piece, but one of the front ones is empty.
     Mini
 }
 
-pub mod thresholds {
-    pub const COMBO_1_THRESHOLD: u32 = 2;
-    pub const COMBO_2_THRESHOLD: u32 = 6;
-    pub const COMBO_3_THRESHOLD: u32 = 10;
-    pub const COMBO_4_THRESHOLD: u32 = 15;
-    pub const COMBO_5_THRESHOLD: u32 = 18;
-
-
-    pub const B2B_1_THRESHOLD: u32 = 2;
-    pub const B2B_2_THRESHOLD: u32 = 5;
-    pub const B2B_3_THRESHOLD: u32 = 10;
-    pub const B2B_4_THRESHOLD: u32 = 30;
-    pub const B2B_5_THRESHOLD: u32 = 60;
-}
-
 pub mod damage_mods {
     pub const NONE: u32 = 0;
 
@@ -45,17 +30,17 @@ pub mod damage_mods {
 
     pub const ALL_CLEAR: u32 = 1 << 7;
 
-    pub const B2B_1: u32 = 1 << 8;
-    pub const B2B_2: u32 = 1 << 9;
-    pub const B2B_3: u32 = 1 << 10;
-    pub const B2B_4: u32 = 1 << 11;
-    pub const B2B_5: u32 = 1 << 12;
-
-    pub const COMBO_1: u32 = 1 << 13;
-    pub const COMBO_2: u32 = 1 << 14;
-    pub const COMBO_3: u32 = 1 << 15;
-    pub const COMBO_4: u32 = 1 << 16;
-    pub const COMBO_5: u32 = 1 << 17;
+    /// The move continues a back-to-back chain.
+    pub const B2B: u32 = 1 << 8;
+
+    /// The move continues a combo.
+    pub const COMBO: u32 = 1 << 13;
+
+    pub const S_SPIN: u32 = 1 << 18;
+    pub const Z_SPIN: u32 = 1 << 19;
+    pub const L_SPIN: u32 = 1 << 20;
+    pub const J_SPIN: u32 = 1 << 21;
+    pub const I_SPIN: u32 = 1 << 22;
 }
 
 pub fn has_flag(value: u32, flag: u32) -> bool {
@@ -84,7 +69,11 @@ impl DamageMgr {
 #[derive(Default, Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct ScoringMgr {
     pub combo: u32,
-    pub b2b: u32
+    pub b2b: u32,
+    /// Length of the B2B chain broken by the last hard drop, 0 if no chain was broken.
+    pub broken_b2b: u32,
+    /// Badges collected for knocking out the opponents, they raise the outgoing attack.
+    pub badges: u32
 }
 
 impl ScoringMgr {
@@ -96,16 +85,20 @@ impl ScoringMgr {
         // Do not break B2B if and only if the player:
         //  - haven't cleared any lines,
         //  - cleared exactly 4 lines,
-        //  - performed a T-Spin which must include a rotation of the piece.
+        //  - performed a T-Spin or an all-spin which must include a rotation of the piece.
         // Otherwise break it.
+        self.broken_b2b = 0;
+
         if hard_drop_info.lines_cleared == 4 ||
             (hard_drop_info.last_move_type == LastMoveType::Rotation &&
                 (
-                    hard_drop_info.lines_cleared >= 1 && hard_drop_info.tspin_status != TSpinStatus::None
+                    hard_drop_info.lines_cleared >= 1 &&
+                        (hard_drop_info.tspin_status != TSpinStatus::None || hard_drop_info.spin.is_some())
                 )) {
             self.b2b += 1;
 
         } else if hard_drop_info.lines_cleared != 0 {
+            self.broken_b2b = self.b2b;
             self.b2b = 0;
         }
 
@@ -122,5 +115,7 @@ impl ScoringMgr {
     pub fn reset(&mut self) {
         self.combo = 0;
         self.b2b = 0;
+        self.broken_b2b = 0;
+        self.badges = 0;
     }
 }
\ No newline at end of file
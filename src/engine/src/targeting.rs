@@ -0,0 +1,250 @@
+/*
+ * Copyright (c) Grigory Alfyorov. Licensed under the MIT License.
+ * See the LICENSE file in the repository root for full licence text.
+ */
+
+use std::collections::HashMap;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use crate::board::Board;
+use crate::protocol::ConnId;
+
+/// Decides which opponents receive the attacks of a player.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TargetingStrategy {
+    /// A random opponent receives the whole attack.
+    #[default]
+    Random,
+    /// The attack is split evenly between all of the opponents.
+    Even,
+    /// The attack is split evenly between the opponents whose last attack has targeted the player.
+    /// Falls back to `Random` if nobody is attacking the player.
+    Attackers,
+    /// The opponent closest to topping out receives the whole attack.
+    KOs,
+    /// The opponent with the most badges receives the whole attack, the ties are broken by `KOs`.
+    Badges
+}
+
+/// State of an opponent which can be targeted.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TargetCandidate {
+    pub conn: ConnId,
+    /// How close the opponent is to topping out: the height of the stack plus the queued garbage.
+    pub danger: u32,
+    pub badges: u32
+}
+
+impl TargetCandidate {
+    pub fn new(conn: ConnId, board: &Board) -> Self {
+        Self {
+            conn,
+            danger: board.get_cell_holder().get_stack_height() + board.garbage_mgr.get_queued_amount(),
+            badges: board.get_badges()
+        }
+    }
+}
+
+/// Routes the attacks of the players of a match according to their `TargetingStrategy`.
+#[derive(Debug, Clone)]
+pub struct TargetingMgr {
+    rng: ChaCha8Rng,
+    strategies: HashMap<ConnId, TargetingStrategy>,
+    /// Opponents who have received the last attack of every player.
+    last_targets: HashMap<ConnId, Vec<ConnId>>
+}
+
+impl TargetingMgr {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            strategies: HashMap::new(),
+            last_targets: HashMap::new()
+        }
+    }
+
+    pub fn set_strategy(&mut self, conn: ConnId, strategy: TargetingStrategy) {
+        self.strategies.insert(conn, strategy);
+    }
+
+    pub fn get_strategy(&self, conn: ConnId) -> TargetingStrategy {
+        self.strategies.get(&conn).copied().unwrap_or_default()
+    }
+
+    /// Returns the players whose last attack has targeted `conn`, sorted.
+    pub fn get_attackers(&self, conn: ConnId) -> Vec<ConnId> {
+        let mut attackers: Vec<ConnId> = self.last_targets
+            .iter()
+            .filter_map(|(from, targets)| targets.contains(&conn).then_some(*from))
+            .collect();
+        attackers.sort_unstable();
+
+        attackers
+    }
+
+    /// Splits `damage` sent by `from` between `candidates`, which are the opponents still alive.
+    /// Returns the opponents receiving the attack along with their part of the damage.
+    pub fn route(&mut self, from: ConnId, damage: i32, candidates: &[TargetCandidate]) -> Vec<(ConnId, i32)> {
+        // the order of the candidates must not affect the random choices
+        let mut candidates: Vec<TargetCandidate> = candidates
+            .iter()
+            .filter(|c| c.conn != from)
+            .copied()
+            .collect();
+        candidates.sort_unstable_by_key(|c| c.conn);
+
+        if damage <= 0 || candidates.is_empty() {
+            return vec![];
+        }
+
+        let targets: Vec<ConnId> = match self.get_strategy(from) {
+            TargetingStrategy::Random => vec![self.pick_random(&candidates)],
+            TargetingStrategy::Even => candidates.iter().map(|c| c.conn).collect(),
+            TargetingStrategy::Attackers => {
+                let attackers = self.get_attackers(from);
+                let targets: Vec<ConnId> = candidates
+                    .iter()
+                    .filter_map(|c| attackers.contains(&c.conn).then_some(c.conn))
+                    .collect();
+
+                if targets.is_empty() {
+                    vec![self.pick_random(&candidates)]
+                } else {
+                    targets
+                }
+            }
+            TargetingStrategy::KOs => vec![self.pick_max_by_key(&candidates, |c| (c.danger, 0))],
+            TargetingStrategy::Badges => vec![self.pick_max_by_key(&candidates, |c| (c.badges, c.danger))]
+        };
+
+        let res = self.split(damage, &targets);
+        self.last_targets.insert(from, res.iter().map(|(conn, _)| *conn).collect());
+
+        res
+    }
+
+    /// Forgets the player who has left the match.
+    pub fn remove_player(&mut self, conn: ConnId) {
+        self.strategies.remove(&conn);
+        self.last_targets.remove(&conn);
+
+        for targets in self.last_targets.values_mut() {
+            targets.retain(|target| *target != conn);
+        }
+    }
+
+    /// Prepares for a new match with the seed `seed`. The strategies of the players are kept.
+    pub fn reset(&mut self, seed: u64) {
+        self.rng = ChaCha8Rng::seed_from_u64(seed);
+        self.last_targets.clear();
+    }
+
+    fn pick_random(&mut self, candidates: &[TargetCandidate]) -> ConnId {
+        candidates[self.rng.gen_range(0..candidates.len())].conn
+    }
+
+    /// Picks the candidate with the greatest key, the ties are broken randomly.
+    fn pick_max_by_key<K: Ord>(&mut self, candidates: &[TargetCandidate], key: impl Fn(&TargetCandidate) -> K) -> ConnId {
+        let max = candidates.iter().map(&key).max().unwrap();
+        let best: Vec<TargetCandidate> = candidates
+            .iter()
+            .filter(|c| key(c) == max)
+            .copied()
+            .collect();
+
+        self.pick_random(&best)
+    }
+
+    /// Splits `damage` evenly between `targets`. The remainder goes to the random targets.
+    fn split(&mut self, damage: i32, targets: &[ConnId]) -> Vec<(ConnId, i32)> {
+        let count = targets.len() as i32;
+        let mut res: Vec<(ConnId, i32)> = targets.iter().map(|conn| (*conn, damage / count)).collect();
+
+        res.shuffle(&mut self.rng);
+        for (_, dmg) in res.iter_mut().take((damage % count) as usize) {
+            *dmg += 1;
+        }
+
+        res.retain(|(_, dmg)| *dmg > 0);
+        res.sort_unstable_by_key(|(conn, _)| *conn);
+
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(conn: ConnId, danger: u32, badges: u32) -> TargetCandidate {
+        TargetCandidate { conn, danger, badges }
+    }
+
+    fn candidates() -> Vec<TargetCandidate> {
+        vec![
+            candidate(1, 4, 0),
+            candidate(2, 12, 1),
+            candidate(3, 8, 3),
+            candidate(4, 2, 3)
+        ]
+    }
+
+    #[test]
+    fn even_split_covers_every_opponent() {
+        let mut targeting = TargetingMgr::new(1337);
+        targeting.set_strategy(1, TargetingStrategy::Even);
+
+        let res = targeting.route(1, 8, &candidates());
+        assert_eq!(res.iter().map(|(conn, _)| *conn).collect::<Vec<_>>(), vec![2, 3, 4]);
+        assert_eq!(res.iter().map(|(_, dmg)| *dmg).sum::<i32>(), 8);
+        assert!(res.iter().all(|(_, dmg)| *dmg == 2 || *dmg == 3));
+
+        // too little damage for everyone
+        assert_eq!(targeting.route(1, 1, &candidates()).len(), 1);
+    }
+
+    #[test]
+    fn kos_and_badges_pick_their_target() {
+        let mut targeting = TargetingMgr::new(1337);
+        targeting.set_strategy(1, TargetingStrategy::KOs);
+        targeting.set_strategy(2, TargetingStrategy::Badges);
+
+        assert_eq!(targeting.route(1, 4, &candidates()), vec![(2, 4)]);
+        // 3 and 4 have the same amount of badges, but 3 is in more danger
+        assert_eq!(targeting.route(2, 4, &candidates()), vec![(3, 4)]);
+    }
+
+    #[test]
+    fn attackers_are_targeted_back() {
+        let mut targeting = TargetingMgr::new(1337);
+        targeting.set_strategy(1, TargetingStrategy::Attackers);
+
+        // nobody is attacking the player yet
+        let res = targeting.route(1, 2, &candidates());
+        assert_eq!(res.len(), 1);
+
+        targeting.set_strategy(3, TargetingStrategy::Even);
+        targeting.route(3, 3, &candidates());
+        assert_eq!(targeting.get_attackers(1), vec![3]);
+        assert_eq!(targeting.route(1, 5, &candidates()), vec![(3, 5)]);
+
+        targeting.remove_player(3);
+        assert!(targeting.get_attackers(1).is_empty());
+    }
+
+    #[test]
+    fn random_is_deterministic() {
+        let mut a = TargetingMgr::new(42);
+        let mut b = TargetingMgr::new(42);
+        let mut reversed = candidates();
+        reversed.reverse();
+
+        for _ in 0..20 {
+            let res = a.route(1, 3, &candidates());
+            assert_eq!(res, b.route(1, 3, &reversed));
+            assert_ne!(res[0].0, 1);
+        }
+    }
+}
@@ -36,6 +36,17 @@ impl TimeMgr {
         self.elapsed_sec += dt;
     }
 
+    /// Moves the clock to the absolute time `elapsed_sec`, setting `last_dt` to the difference.
+    /// Used when time comes from a recording rather than from frame deltas.
+    pub fn advance_to(&mut self, elapsed_sec: f32) {
+        if !self.is_enabled {
+            return;
+        }
+
+        self.last_dt = elapsed_sec - self.elapsed_sec;
+        self.elapsed_sec = elapsed_sec;
+    }
+
     pub fn reset(&mut self) {
         self.elapsed_sec = 0.0;
         self.last_dt = 0.0;
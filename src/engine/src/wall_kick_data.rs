@@ -4,15 +4,27 @@
  */
 
 use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 use std::sync::Mutex;
 use serde::{Deserialize, Serialize};
 use once_cell::sync::Lazy;
 use crate::piece::RotationMove;
 use crate::primitives::Point;
 
-#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+/// Rotation system used for the wall kicks.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum WallKickDataMode {
-    Standard
+    /// Standard SRS as described by the guideline, with simple 180 kicks.
+    Standard,
+    /// SRS+ as used by TETR.IO: symmetric kicks of the I piece and its own 180 kick table.
+    SrsPlus,
+    /// Arika Rotation System kicks: a piece tries to move one cell right, then one cell left.
+    /// The I piece never kicks. The ARS spawn orientations and the center column rule are not applied.
+    Ars,
+    /// Nintendo Rotation System: no kicks at all, the rotation fails if the piece does not fit.
+    Nrs,
+    /// User supplied kick table, for example loaded with `WallKickData::from_json()`.
+    Custom(Box<WallKickData>)
 }
 
 type WK = HashMap<RotationMove, Vec<Point>>;
@@ -22,7 +34,31 @@ pub enum WallKickType {
     Default, PieceI, PieceO
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum WallKickDataError {
+    InvalidJson(String),
+    MissingRotation {
+        kick_type: WallKickType,
+        rotation: RotationMove
+    }
+}
+
+impl Display for WallKickDataError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WallKickDataError::InvalidJson(msg) => write!(f, "Invalid wall kick data: {}", msg),
+            WallKickDataError::MissingRotation { kick_type, rotation } => write!(
+                f,
+                "Wall kick data for {:?} has no tests for {:?}",
+                kick_type, rotation
+            )
+        }
+    }
+}
+
+impl std::error::Error for WallKickDataError {}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct WallKickData {
     default: WK,
     piece_i: WK,
@@ -30,7 +66,7 @@ pub struct WallKickData {
 }
 
 pub static WALL_KICK_DATA: Lazy<Mutex<WallKickData>> = Lazy::new(|| {
-    let wkd = WallKickData::new(WallKickDataMode::Standard);
+    let wkd = WallKickData::new(&WallKickDataMode::Standard);
     Mutex::new(wkd)
 });
 
@@ -58,53 +94,141 @@ const WKD_LEFT_TO_INIT_DEG180: [Point; 5] = [
     Point { x: -1, y: 2 },
 ];
 
-impl WallKickData {
-    pub fn new(mode: WallKickDataMode) -> Self {
+/// Every rotation a kick table must have the tests for.
+const ROTATION_MOVES: [RotationMove; 12] = [
+    RotationMove::InitToRight,
+    RotationMove::RightToInit,
+    RotationMove::RightToDeg180,
+    RotationMove::Deg180ToRight,
+    RotationMove::Deg180ToLeft,
+    RotationMove::LeftToDeg180,
+    RotationMove::LeftToInit,
+    RotationMove::InitToLeft,
+    RotationMove::InitToDeg180,
+    RotationMove::Deg180ToInit,
+    RotationMove::RightToLeft,
+    RotationMove::LeftToRight,
+];
+
+const NO_KICKS: [Point; 1] = [Point { x: 0, y: 0 }];
+
+const ARS_KICKS: [Point; 3] = [
+    Point { x: 0, y: 0 },
+    Point { x: 1, y: 0 },
+    Point { x: -1, y: 0 },
+];
+
+/// Creates a table with the same tests for every rotation.
+fn uniform_table(tests: &[Point]) -> WK {
+    ROTATION_MOVES
+        .iter()
+        .map(|rotation| (*rotation, tests.to_vec()))
+        .collect()
+}
 
+impl WallKickData {
+    pub fn new(mode: &WallKickDataMode) -> Self {
         match mode {
-            WallKickDataMode::Standard => {
-                let default = HashMap::from([
-                    ( RotationMove::InitToRight,   Vec::from(WKD_TO_RIGHT)),
-                    ( RotationMove::RightToInit,   vec![Point::new(0, 0), Point::new(1 , 0), Point::new(1, -1),  Point::new(0, 2),  Point::new(1, 2)] ),
-                    ( RotationMove::RightToDeg180, vec![Point::new(0, 0), Point::new(1 , 0), Point::new(1, -1),  Point::new(0, 1),  Point::new(0, 2)] ),
-                    ( RotationMove::Deg180ToRight, Vec::from(WKD_TO_RIGHT)),
-                    ( RotationMove::Deg180ToLeft,  Vec::from(WKD_TO_LEFT)),
-                    ( RotationMove::LeftToDeg180,  Vec::from(WKD_LEFT_TO_INIT_DEG180)),
-                    ( RotationMove::LeftToInit,    Vec::from(WKD_LEFT_TO_INIT_DEG180)),
-                    ( RotationMove::InitToLeft,    Vec::from(WKD_TO_LEFT)),
-                    ( RotationMove::InitToDeg180,  vec![Point::new(0, 0), Point::new(0 , 1), Point::new(0, 2)] ),
-                    ( RotationMove::Deg180ToInit,  vec![Point::new(0, 0), Point::new(0 ,-1), Point::new(0, -2)] ),
-                ]);
-
-                let piece_i = HashMap::from([
-                    ( RotationMove::InitToRight,  vec![ Point::new(0, 0), Point::new(-2, 0), Point::new(1, 0),  Point::new(-2, -1), Point::new(1, 2) ] ),
-                    ( RotationMove::RightToInit,  vec![ Point::new(0, 0), Point::new(2, 0),  Point::new(-1, 0), Point::new(2, 1),   Point::new(-1, -2) ] ),
-                    ( RotationMove::RightToDeg180, vec![ Point::new(0, 0), Point::new(-1, 0), Point::new(2, 0),  Point::new(-1, 2),  Point::new(2, -1) ] ),
-                    ( RotationMove::Deg180ToRight, vec![ Point::new(0, 0), Point::new(1, 0),  Point::new(-2, 0), Point::new(1, -2),  Point::new(-2, 1) ] ),
-                    ( RotationMove::Deg180ToLeft,  vec![ Point::new(0, 0), Point::new(2, 0),  Point::new(-1, 0), Point::new(2, 1),   Point::new(-1, -2) ] ),
-                    ( RotationMove::LeftToDeg180,  vec![ Point::new(0, 0), Point::new(-2, 0), Point::new(1, 0),  Point::new(-2, -1), Point::new(1, 2) ] ),
-                    ( RotationMove::LeftToInit,   vec![ Point::new(0, 0), Point::new(1, 0),  Point::new(-2, 0), Point::new(1, -2),  Point::new(-2, 1) ] ),
-                    ( RotationMove::InitToLeft,   vec![ Point::new(0, 0), Point::new(-1, 0), Point::new(2, 0),  Point::new(-1, 2),  Point::new(2, -1) ] ),
-                    ( RotationMove::InitToDeg180, vec![ Point::new(0, 0), Point::new(0, 1),  Point::new(0, 2) ] ),
-                    ( RotationMove::Deg180ToInit, vec![ Point::new(0, 0), Point::new(0, -1), Point::new(0, -2) ] ),
-                ]);
-
-                let piece_o = HashMap::from([
-                    ( RotationMove::InitToRight,  vec![ Point::new(0, 0) ] ),
-                    ( RotationMove::RightToInit,  vec![ Point::new(0, 0) ] ),
-                    ( RotationMove::RightToDeg180, vec![ Point::new(0, 0) ] ),
-                    ( RotationMove::Deg180ToRight, vec![ Point::new(0, 0) ] ),
-                    ( RotationMove::Deg180ToLeft,  vec![ Point::new(0, 0) ] ),
-                    ( RotationMove::LeftToDeg180,  vec![ Point::new(0, 0) ] ),
-                    ( RotationMove::LeftToInit,   vec![ Point::new(0, 0) ] ),
-                    ( RotationMove::InitToLeft,   vec![ Point::new(0, 0) ] ),
-                    ( RotationMove::InitToDeg180, vec![ Point::new(0, 0) ] ),
-                    ( RotationMove::Deg180ToInit, vec![ Point::new(0, 0) ] ),
-                ]);
-
-                WallKickData { default, piece_i, piece_o }
+            WallKickDataMode::Standard => Self::standard(),
+            WallKickDataMode::SrsPlus => Self::srs_plus(),
+            WallKickDataMode::Ars => WallKickData {
+                default: uniform_table(&ARS_KICKS),
+                piece_i: uniform_table(&NO_KICKS),
+                piece_o: uniform_table(&NO_KICKS)
+            },
+            WallKickDataMode::Nrs => WallKickData {
+                default: uniform_table(&NO_KICKS),
+                piece_i: uniform_table(&NO_KICKS),
+                piece_o: uniform_table(&NO_KICKS)
+            },
+            WallKickDataMode::Custom(wkd) => wkd.as_ref().clone()
+        }
+    }
+
+    /// Loads a custom kick table. The offsets use the guideline notation, so positive Y goes up.
+    /// Every kick type must have the tests for every rotation.
+    pub fn from_json(json: &str) -> Result<Self, WallKickDataError> {
+        let wkd: WallKickData = serde_json::from_str(json)
+            .map_err(|e| WallKickDataError::InvalidJson(e.to_string()))?;
+
+        wkd.validate()?;
+
+        Ok(wkd)
+    }
+
+    pub fn validate(&self) -> Result<(), WallKickDataError> {
+        for kick_type in [WallKickType::Default, WallKickType::PieceI, WallKickType::PieceO] {
+            let table = self.get(&kick_type);
+
+            for rotation in ROTATION_MOVES {
+                if table.get(&rotation).map_or(true, |tests| tests.is_empty()) {
+                    return Err(WallKickDataError::MissingRotation { kick_type, rotation });
+                }
             }
         }
+
+        Ok(())
+    }
+
+    fn standard() -> Self {
+        let default = HashMap::from([
+            ( RotationMove::InitToRight,   Vec::from(WKD_TO_RIGHT)),
+            ( RotationMove::RightToInit,   vec![Point::new(0, 0), Point::new(1 , 0), Point::new(1, -1),  Point::new(0, 2),  Point::new(1, 2)] ),
+            ( RotationMove::RightToDeg180, vec![Point::new(0, 0), Point::new(1 , 0), Point::new(1, -1),  Point::new(0, 1),  Point::new(0, 2)] ),
+            ( RotationMove::Deg180ToRight, Vec::from(WKD_TO_RIGHT)),
+            ( RotationMove::Deg180ToLeft,  Vec::from(WKD_TO_LEFT)),
+            ( RotationMove::LeftToDeg180,  Vec::from(WKD_LEFT_TO_INIT_DEG180)),
+            ( RotationMove::LeftToInit,    Vec::from(WKD_LEFT_TO_INIT_DEG180)),
+            ( RotationMove::InitToLeft,    Vec::from(WKD_TO_LEFT)),
+            ( RotationMove::InitToDeg180,  vec![Point::new(0, 0), Point::new(0 , 1), Point::new(0, 2)] ),
+            ( RotationMove::Deg180ToInit,  vec![Point::new(0, 0), Point::new(0 ,-1), Point::new(0, -2)] ),
+            ( RotationMove::RightToLeft,   Vec::from(WKD_TO_LEFT)),
+            ( RotationMove::LeftToRight,   Vec::from(WKD_TO_RIGHT)),
+        ]);
+
+        let piece_i = HashMap::from([
+            ( RotationMove::InitToRight,  vec![ Point::new(0, 0), Point::new(-2, 0), Point::new(1, 0),  Point::new(-2, -1), Point::new(1, 2) ] ),
+            ( RotationMove::RightToInit,  vec![ Point::new(0, 0), Point::new(2, 0),  Point::new(-1, 0), Point::new(2, 1),   Point::new(-1, -2) ] ),
+            ( RotationMove::RightToDeg180, vec![ Point::new(0, 0), Point::new(-1, 0), Point::new(2, 0),  Point::new(-1, 2),  Point::new(2, -1) ] ),
+            ( RotationMove::Deg180ToRight, vec![ Point::new(0, 0), Point::new(1, 0),  Point::new(-2, 0), Point::new(1, -2),  Point::new(-2, 1) ] ),
+            ( RotationMove::Deg180ToLeft,  vec![ Point::new(0, 0), Point::new(2, 0),  Point::new(-1, 0), Point::new(2, 1),   Point::new(-1, -2) ] ),
+            ( RotationMove::LeftToDeg180,  vec![ Point::new(0, 0), Point::new(-2, 0), Point::new(1, 0),  Point::new(-2, -1), Point::new(1, 2) ] ),
+            ( RotationMove::LeftToInit,   vec![ Point::new(0, 0), Point::new(1, 0),  Point::new(-2, 0), Point::new(1, -2),  Point::new(-2, 1) ] ),
+            ( RotationMove::InitToLeft,   vec![ Point::new(0, 0), Point::new(-1, 0), Point::new(2, 0),  Point::new(-1, 2),  Point::new(2, -1) ] ),
+            ( RotationMove::InitToDeg180, vec![ Point::new(0, 0), Point::new(0, 1),  Point::new(0, 2) ] ),
+            ( RotationMove::Deg180ToInit, vec![ Point::new(0, 0), Point::new(0, -1), Point::new(0, -2) ] ),
+            ( RotationMove::RightToLeft,  vec![ Point::new(0, 0), Point::new(2, 0),  Point::new(-1, 0), Point::new(2, 1),   Point::new(-1, -2) ] ),
+            ( RotationMove::LeftToRight,  vec![ Point::new(0, 0), Point::new(-2, 0), Point::new(1, 0),  Point::new(-2, -1), Point::new(1, 2) ] ),
+        ]);
+
+        WallKickData { default, piece_i, piece_o: uniform_table(&NO_KICKS) }
+    }
+
+    fn srs_plus() -> Self {
+        let mut default = Self::standard().default;
+        default.extend([
+            ( RotationMove::InitToDeg180, vec![ Point::new(0, 0), Point::new(0, 1),  Point::new(1, 1),  Point::new(-1, 1), Point::new(1, 0),  Point::new(-1, 0) ] ),
+            ( RotationMove::Deg180ToInit, vec![ Point::new(0, 0), Point::new(0, -1), Point::new(-1, -1), Point::new(1, -1), Point::new(-1, 0), Point::new(1, 0) ] ),
+            ( RotationMove::RightToLeft,  vec![ Point::new(0, 0), Point::new(1, 0),  Point::new(1, 2),  Point::new(1, 1),  Point::new(0, 2),  Point::new(0, 1) ] ),
+            ( RotationMove::LeftToRight,  vec![ Point::new(0, 0), Point::new(-1, 0), Point::new(-1, 2), Point::new(-1, 1), Point::new(0, 2),  Point::new(0, 1) ] ),
+        ]);
+
+        let piece_i = HashMap::from([
+            ( RotationMove::InitToRight,  vec![ Point::new(0, 0), Point::new(1, 0),  Point::new(-2, 0), Point::new(-2, -1), Point::new(1, 2) ] ),
+            ( RotationMove::RightToInit,  vec![ Point::new(0, 0), Point::new(-1, 0), Point::new(2, 0),  Point::new(-1, -2), Point::new(2, 1) ] ),
+            ( RotationMove::RightToDeg180, vec![ Point::new(0, 0), Point::new(-1, 0), Point::new(2, 0),  Point::new(-1, 2),  Point::new(2, -1) ] ),
+            ( RotationMove::Deg180ToRight, vec![ Point::new(0, 0), Point::new(-2, 0), Point::new(1, 0),  Point::new(-2, 1),  Point::new(1, -2) ] ),
+            ( RotationMove::Deg180ToLeft,  vec![ Point::new(0, 0), Point::new(2, 0),  Point::new(-1, 0), Point::new(2, 1),   Point::new(-1, -2) ] ),
+            ( RotationMove::LeftToDeg180,  vec![ Point::new(0, 0), Point::new(1, 0),  Point::new(-2, 0), Point::new(1, -2),  Point::new(-2, 1) ] ),
+            ( RotationMove::LeftToInit,   vec![ Point::new(0, 0), Point::new(1, 0),  Point::new(-2, 0), Point::new(1, 2),   Point::new(-2, -1) ] ),
+            ( RotationMove::InitToLeft,   vec![ Point::new(0, 0), Point::new(-1, 0), Point::new(2, 0),  Point::new(2, -1),  Point::new(-1, 2) ] ),
+            ( RotationMove::InitToDeg180, Vec::from(NO_KICKS) ),
+            ( RotationMove::Deg180ToInit, Vec::from(NO_KICKS) ),
+            ( RotationMove::RightToLeft,  Vec::from(NO_KICKS) ),
+            ( RotationMove::LeftToRight,  Vec::from(NO_KICKS) ),
+        ]);
+
+        WallKickData { default, piece_i, piece_o: uniform_table(&NO_KICKS) }
     }
 
     pub fn get(&self, t: &WallKickType) -> &WK {
@@ -115,6 +239,14 @@ impl WallKickData {
         }
     }
 
+    /// Returns the tests for the `rotation` of the pieces with the kick type `t`.
+    /// A rotation missing from a custom table is performed without kicks.
+    pub fn get_tests(&self, t: &WallKickType, rotation: &RotationMove) -> &[Point] {
+        self.get(t)
+            .get(rotation)
+            .map_or(&NO_KICKS, |tests| tests.as_slice())
+    }
+
     pub fn get_default(&self) -> &WK {
         &self.default
     }
@@ -130,6 +262,128 @@ impl WallKickData {
 
 impl Default for WallKickData {
     fn default() -> Self {
-        WallKickData::new(WallKickDataMode::Standard)
+        WallKickData::new(&WallKickDataMode::Standard)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell_holder::CellType;
+    use crate::game_settings::GameSettings;
+    use crate::piece::{Piece, PieceType, RotationDirection, RotationState};
+    use crate::piece_mgr::PieceMgr;
+
+    const MODES: [WallKickDataMode; 4] = [
+        WallKickDataMode::Standard,
+        WallKickDataMode::SrsPlus,
+        WallKickDataMode::Ars,
+        WallKickDataMode::Nrs
+    ];
+
+    /// Places the piece at `pos` on a board with the `blocks` filled and rotates it.
+    /// Returns the position of the piece after the rotation or `None` if the rotation failed.
+    fn rotate_at(
+        mode: &WallKickDataMode,
+        piece_type: PieceType,
+        state: RotationState,
+        pos: (u32, u32),
+        blocks: &[(usize, usize)],
+        rotation: RotationDirection
+    ) -> Option<(u32, u32)> {
+        let game_settings = GameSettings {
+            wall_kick_data_mode: mode.clone(),
+            ..Default::default()
+        };
+        let wkd = WallKickData::new(&game_settings.wall_kick_data_mode);
+        let mut piece_mgr = PieceMgr::new(&game_settings, 0);
+
+        let mut piece = Piece::new(piece_type);
+        piece.current_rotation = state;
+        piece.set_x(pos.0);
+        piece.set_y(pos.1);
+        piece_mgr.cur_piece = piece;
+
+        for (x, y) in blocks {
+            piece_mgr.cell_holder.set_cell_at(*x, *y, CellType::Garbage);
+        }
+
+        if !piece_mgr.rotate(&wkd, rotation) {
+            assert_eq!(piece_mgr.cur_piece.current_rotation, state);
+            return None;
+        }
+
+        let piece = piece_mgr.get_piece();
+        Some((piece.get_x(), piece.get_y()))
+    }
+
+    #[test]
+    fn builtin_tables_are_complete() {
+        for mode in MODES {
+            assert_eq!(WallKickData::new(&mode).validate(), Ok(()), "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn t_kicks_off_left_wall() {
+        let expected = [Some((1, 10)), Some((1, 10)), Some((1, 10)), None];
+
+        for (mode, expected) in MODES.iter().zip(expected) {
+            let res = rotate_at(mode, PieceType::T, RotationState::Clockwise, (0, 10), &[], RotationDirection::Clockwise);
+            assert_eq!(res, expected, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn t_180_under_overhang() {
+        // the cell under the T and the cell to the left of the one above it are filled
+        let blocks = [(4, 11), (3, 9)];
+        let expected = [Some((4, 8)), Some((5, 9)), Some((5, 10)), None];
+
+        for (mode, expected) in MODES.iter().zip(expected) {
+            let res = rotate_at(mode, PieceType::T, RotationState::Initial, (4, 10), &blocks, RotationDirection::Deg180);
+            assert_eq!(res, expected, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn i_kicks_are_mode_specific() {
+        let expected = [Some((2, 10)), Some((5, 10)), None, None];
+
+        for (mode, expected) in MODES.iter().zip(expected) {
+            let res = rotate_at(mode, PieceType::I, RotationState::Initial, (4, 10), &[(4, 11)], RotationDirection::Clockwise);
+            assert_eq!(res, expected, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn custom_table_from_json() {
+        let json = serde_json::to_string(&WallKickData::new(&WallKickDataMode::Nrs)).unwrap();
+        let mode = WallKickDataMode::Custom(Box::new(WallKickData::from_json(&json).unwrap()));
+
+        let res = rotate_at(&mode, PieceType::T, RotationState::Clockwise, (0, 10), &[], RotationDirection::Clockwise);
+        assert_eq!(res, None);
+        let res = rotate_at(&mode, PieceType::T, RotationState::Initial, (4, 10), &[], RotationDirection::Clockwise);
+        assert_eq!(res, Some((4, 10)));
+
+        // the custom table is sent along with the game settings
+        let game_settings = GameSettings { wall_kick_data_mode: mode, ..Default::default() };
+        let json = serde_json::to_string(&game_settings).unwrap();
+        assert_eq!(serde_json::from_str::<GameSettings>(&json).unwrap(), game_settings);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn incomplete_custom_table_is_rejected() {
+        let mut wkd = WallKickData::new(&WallKickDataMode::Standard);
+        wkd.piece_i.remove(&RotationMove::LeftToRight);
+        let json = serde_json::to_string(&wkd).unwrap();
+
+        assert_eq!(
+            WallKickData::from_json(&json),
+            Err(WallKickDataError::MissingRotation {
+                kick_type: WallKickType::PieceI,
+                rotation: RotationMove::LeftToRight
+            })
+        );
+        assert!(matches!(WallKickData::from_json("{}"), Err(WallKickDataError::InvalidJson(_))));
+    }
+}
@@ -10,9 +10,11 @@ use tokio::task::JoinHandle;
 use tokio::{pin, select};
 use uuid::Uuid;
 
+use quader_engine::{game_settings::GameSettings, protocol::LobbyListing};
+
 use crate::{
     auth::UserInfo,
-    ws::wsboard::{WsBoardMgr, WsBoardMgrHandle},
+    ws::wsboard::{MatchEvent, WsBoardMgr, WsBoardMgrHandle},
     ConnId, LobbyName, LobbyUuid,
 };
 
@@ -30,16 +32,6 @@ impl LobbySettings {
     }
 }
 
-#[derive(Debug, Clone, Serialize)]
-pub struct LobbyListing {
-    pub uuid: String,
-    pub name: String,
-    pub creator_username: String,
-    pub player_limit: usize,
-    pub player_count: usize,
-    pub is_started: bool,
-}
-
 #[derive(Debug)]
 pub struct LobbyContainer {
     pub lobby_map: HashMap<String, Lobby>,
@@ -133,7 +125,7 @@ pub struct Lobby {
     pub player_list: Vec<LobbyUser>,
     pub creator_username: String,
     pub is_started: bool,
-    board_mgr: WsBoardMgrHandle,
+    board_mgr: Option<WsBoardMgrHandle>,
     //board_mgr_task: JoinHandle<()>,
 }
 
@@ -144,9 +136,6 @@ impl Lobby {
         lobby_name: String,
         player_limit: usize,
     ) -> Self {
-        let (board_mgr, handle) = WsBoardMgr::new();
-        //let board_mgr = tokio::spawn(board_mgr.run());
-
         Self {
             uuid,
             lobby_name,
@@ -154,11 +143,37 @@ impl Lobby {
             player_list: vec![],
             creator_username,
             is_started: false,
-            board_mgr: handle,
+            board_mgr: None,
             //board_mgr_task: board_mgr,
         }
     }
 
+    /// Starts a new match for every player in the lobby. The board manager is spawned
+    /// on the first match and reused afterwards. Returns the seed of the match,
+    /// or `None` if the board manager has stopped, in which case it is spawned again next time.
+    pub async fn start_match(&mut self, event_tx: mpsc::UnboundedSender<MatchEvent>) -> Option<u64> {
+        let board_mgr = self.board_mgr.get_or_insert_with(|| {
+            let (board_mgr, handle) =
+                WsBoardMgr::new(self.uuid.clone(), GameSettings::default(), event_tx);
+            tokio::spawn(board_mgr.run());
+
+            handle
+        });
+
+        let players = self.player_list.iter().map(|p| p.conn).collect();
+        let Some(seed) = board_mgr.start(players).await else {
+            self.board_mgr = None;
+            return None;
+        };
+        self.is_started = true;
+
+        Some(seed)
+    }
+
+    pub fn board_mgr(&self) -> Option<&WsBoardMgrHandle> {
+        self.board_mgr.as_ref()
+    }
+
     pub fn start(mut self) {}
 
     pub fn from_settings(lobby_settings: LobbySettings, creator_username: String) -> Self {
@@ -215,6 +230,9 @@ impl Lobby {
         match index {
             Some(index) => {
                 self.player_list.remove(index);
+                if let Some(board_mgr) = &self.board_mgr {
+                    board_mgr.remove_player(conn);
+                }
                 true
             }
             None => false,
@@ -6,13 +6,11 @@
 use std::time::{Duration, Instant};
 
 use crate::auth::UserInfo;
+use crate::ws::server::ChatServerHandle;
 use crate::ConnId;
-use crate::{ws::server::ChatServerHandle, Msg};
 use actix_ws::{CloseReason, Message};
 use futures_util::StreamExt as _;
-use quader_engine::board_command::BoardMoveDir;
-use quader_engine::piece::RotationDirection;
-use serde::{Deserialize, Serialize};
+use quader_engine::protocol::{LobbyEvent, WsAction, WsError, WsErrorKind, WsServerMessage};
 use tokio::{pin, select, sync::mpsc, time::interval};
 
 const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
@@ -59,10 +57,10 @@ pub async fn chat_ws(
                                 last_heartbeat = Instant::now();
                             }
                             Message::Text(text) => {
-                                if let Err(err) = process_text_msg(&chat_server, &mut session, &text, conn_id, &user_info).await {
-                                        log::error!("Error while processing message: {:?}", err);
-                                        let _ = session.text(format!("error while processing message: {:?}", err)).await;
-                                    }
+                                if let Err(err) = process_text_msg(&chat_server, &mut session, &text, conn_id).await {
+                                    log::error!("Error while processing message: {:?}", err);
+                                    send(&mut session, WsServerMessage::Error(err)).await;
+                                }
                             }
                             Message::Binary(_bin) => {
                                 log::warn!("unexpected binary message");
@@ -85,9 +83,9 @@ pub async fn chat_ws(
                 }
             }
             msg = msg_rx => {
-                // chat messages received from other room participants
-                if let Some(msg) = &msg {
-                    session.text(msg).await.unwrap();
+                // messages received from the server and other room participants
+                if let Some(msg) = msg {
+                    send(&mut session, msg).await;
                 } else {
                     unreachable!("all connection message senders were dropped; chat server may have panicked")
                 }
@@ -114,25 +112,8 @@ pub async fn chat_ws(
     let _ = session.close(close_reason).await;
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum WsBoardCommand {
-    Create,
-    Destroy(ConnId),
-    Move(BoardMoveDir, u32),
-    Rotate(RotationDirection),
-    HardDrop,
-    // delta
-    SoftDrop(u32),
-    HoldPiece,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum WsAction {
-    Chat(Msg),
-    BoardCommand(WsBoardCommand),
-    StartMatch,
-    ListLobbies,
-    JoinLobby(String),
+async fn send(session: &mut actix_ws::Session, msg: WsServerMessage) {
+    let _ = session.text(msg.to_json()).await;
 }
 
 async fn process_text_msg(
@@ -140,43 +121,43 @@ async fn process_text_msg(
     session: &mut actix_ws::Session,
     text: &str,
     conn: ConnId,
-    user_info: &UserInfo
-) -> Result<(), serde_json::Error> {
+) -> Result<(), WsError> {
     let msg = text.trim();
 
-    let action = serde_json::from_str::<WsAction>(msg)?;
+    let action = serde_json::from_str::<WsAction>(msg)
+        .map_err(|err| WsError::new(WsErrorKind::InvalidMessage, err.to_string()))?;
 
     match action {
         WsAction::Chat(msg) => {
-            let msg = format!("{}: {msg}", user_info.username);
-
             chat_server.send_message(conn, msg).await;
         }
         WsAction::BoardCommand(cmd) => {
-            log::info!("conn {conn}: got a board cmd: {:?}", cmd);
-            let msg = chat_server.on_board_cmd(conn, cmd).await;
-            session.text(msg).await.unwrap();
+            log::debug!("conn {conn}: got a board cmd: {:?}", cmd);
+            chat_server.on_board_cmd(conn, None, cmd).await?;
+        }
+        WsAction::BoardInput { seq, cmd } => {
+            log::debug!("conn {conn}: got board input #{seq}: {:?}", cmd);
+            chat_server.on_board_cmd(conn, Some(seq), cmd).await?;
         }
         WsAction::StartMatch => {
             log::info!("conn {conn}: starting match");
-            chat_server.start_match(conn).await;
+            chat_server.start_match(conn).await?;
         }
         WsAction::ListLobbies => {
             log::info!("conn {conn}: listing lobbies");
 
             let lobbies = chat_server.list_lobbies().await;
-            let lobbies = serde_json::to_string(&lobbies).unwrap();
-            session.text(lobbies).await.unwrap();
-            /* for lobby in lobbies {
-                session.text(lobby).await.unwrap();
-            } */
+            send(session, WsServerMessage::Lobby(LobbyEvent::LobbyList(lobbies))).await;
         }
         WsAction::JoinLobby(lobby) => {
             log::info!("conn {conn}: joining lobby {lobby}");
 
-            chat_server.join_lobby(conn, &lobby).await;
+            chat_server.join_lobby(conn, &lobby).await?;
+        }
+        WsAction::RequestResync(board) => {
+            log::debug!("conn {conn}: resync of board {board} requested");
 
-            session.text(format!("joined lobby {lobby}")).await.unwrap();
+            chat_server.resync(conn, board).await?;
         }
     }
 
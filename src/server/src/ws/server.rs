@@ -5,9 +5,13 @@
 
 use crate::{
     auth::UserInfo,
-    lobbies::{Lobby, LobbyContainer, LobbyListing, LobbySettings},
+    lobbies::{Lobby, LobbyContainer, LobbySettings},
     ConnId, Msg,
 };
+use quader_engine::protocol::{
+    ChatMessage, LobbyEvent, LobbyListing, LobbyPlayer, WsBoardCommand, WsError, WsErrorKind,
+    WsServerMessage,
+};
 use rand::{thread_rng, Rng as _};
 use std::{
     collections::HashMap,
@@ -17,22 +21,24 @@ use std::{
         Arc,
     },
 };
-use tokio::sync::{mpsc, oneshot};
-use crate::lobbies::LobbyNotification;
+use tokio::{
+    select,
+    sync::{mpsc, oneshot},
+};
 
-use super::handler::WsBoardCommand;
+use super::wsboard::{MatchEvent, WsBoardMgrHandle};
 
 #[derive(Debug)]
 struct Session {
     pub conn: ConnId,
     pub user_info: UserInfo,
-    pub conn_tx: mpsc::UnboundedSender<Msg>,
+    pub conn_tx: mpsc::UnboundedSender<WsServerMessage>,
 }
 
 #[derive(Debug)]
 enum Command {
     Connect {
-        conn_tx: mpsc::UnboundedSender<Msg>,
+        conn_tx: mpsc::UnboundedSender<WsServerMessage>,
         user_info: UserInfo,
         res_tx: oneshot::Sender<ConnId>,
     },
@@ -45,7 +51,7 @@ enum Command {
     JoinLobby {
         conn: ConnId,
         lobby_id: String,
-        res_tx: oneshot::Sender<()>,
+        res_tx: oneshot::Sender<Result<(), WsError>>,
     },
     Message {
         msg: Msg,
@@ -54,12 +60,18 @@ enum Command {
     },
     BoardCommand {
         cmd: WsBoardCommand,
+        input: Option<u64>,
         conn: ConnId,
-        res_tx: oneshot::Sender<String>,
+        res_tx: oneshot::Sender<Result<(), WsError>>,
     },
     StartMatch {
         conn: ConnId,
-        res_tx: oneshot::Sender<()>,
+        res_tx: oneshot::Sender<Result<(), WsError>>,
+    },
+    Resync {
+        conn: ConnId,
+        board: ConnId,
+        res_tx: oneshot::Sender<Result<(), WsError>>,
     },
 }
 
@@ -95,6 +107,8 @@ pub struct ChatServer {
     lobby_container: LobbyContainer,
     visitor_count: Arc<AtomicUsize>,
     cmd_rx: mpsc::UnboundedReceiver<Command>,
+    match_tx: mpsc::UnboundedSender<MatchEvent>,
+    match_rx: mpsc::UnboundedReceiver<MatchEvent>,
 }
 
 impl ChatServer {
@@ -105,6 +119,7 @@ impl ChatServer {
         log::debug!("created lobbies");
 
         let (cmd_tx, cmd_rx) = mpsc::unbounded_channel();
+        let (match_tx, match_rx) = mpsc::unbounded_channel();
 
         log::debug!("setting up channels");
 
@@ -114,6 +129,8 @@ impl ChatServer {
                 lobby_container,
                 visitor_count: Arc::new(AtomicUsize::new(0)),
                 cmd_rx,
+                match_tx,
+                match_rx,
             },
             ChatServerHandle { cmd_tx },
         );
@@ -123,32 +140,52 @@ impl ChatServer {
         res
     }
 
-    async fn send_system_message(&self, lobby_id: &str, skip: ConnId, msg: impl Into<String>) {
+    /// Sends `msg` to every player of the lobby `lobby_id` except `skip`.
+    fn broadcast(&self, lobby_id: &str, skip: ConnId, msg: WsServerMessage) {
         if let Some(lobby) = self.lobby_container.lobby_map.get(lobby_id) {
-            let msg = msg.into();
-
             for user in lobby.player_list.iter() {
                 if user.conn != skip {
-                    if let Some(tx) = self.sessions.get(&user.conn) {
-                        let _ = tx.conn_tx.send(format!("{}", msg.clone()));
-                    }
+                    self.send_to(user.conn, msg.clone());
                 }
             }
         }
     }
 
+    fn send_to(&self, conn: ConnId, msg: WsServerMessage) {
+        if let Some(session) = self.sessions.get(&conn) {
+            let _ = session.conn_tx.send(msg);
+        }
+    }
+
     async fn send_message(&self, conn: ConnId, msg: impl Into<String>) {
-        if let Some(lobby) = self
-            .lobby_container
+        if let Some(lobby) = self.find_lobby_id(conn) {
+            let msg = WsServerMessage::Chat(ChatMessage {
+                from: Some(self.username(conn).to_owned()),
+                message: msg.into(),
+            });
+
+            self.broadcast(&lobby, conn, msg);
+        };
+    }
+
+    fn find_lobby_id(&self, conn: ConnId) -> Option<String> {
+        self.lobby_container
             .lobby_map
             .iter()
-            .find_map(|(lobby_id, lobby)| lobby.contains_player_conn(conn).then_some(lobby_id))
-        {
-            self.send_system_message(&lobby, conn, msg).await;
-        };
+            .find_map(|(lobby_id, lobby)| lobby.contains_player_conn(conn).then(|| lobby_id.clone()))
     }
 
-    async fn connect(&mut self, tx: mpsc::UnboundedSender<Msg>, user_info: UserInfo) -> ConnId {
+    fn username(&self, conn: ConnId) -> &str {
+        self.sessions
+            .get(&conn)
+            .map_or("Someone", |s| s.user_info.username.as_str())
+    }
+
+    async fn connect(
+        &mut self,
+        tx: mpsc::UnboundedSender<WsServerMessage>,
+        user_info: UserInfo,
+    ) -> ConnId {
         log::info!("{} joined", &user_info.username);
 
         let id = thread_rng().gen::<usize>();
@@ -172,10 +209,7 @@ impl ChatServer {
         let session = self.sessions.get(&conn_id);
         let mut lobby_ids: Vec<String> = vec![];
 
-        let username = match session {
-            Some(s) => &s.user_info.username,
-            None => "Someone",
-        };
+        let username = self.username(conn_id).to_owned();
 
         log::info!("{} disconnected", username);
 
@@ -187,103 +221,188 @@ impl ChatServer {
             }
         }
 
+        let player = LobbyPlayer {
+            conn: conn_id,
+            username,
+        };
         for lobby_id in lobby_ids {
-            self.send_system_message(&lobby_id, 0, format!("{} disconnected", username))
-                .await;
+            self.broadcast(
+                &lobby_id,
+                conn_id,
+                WsServerMessage::Lobby(LobbyEvent::PlayerLeft(player.clone())),
+            );
         }
+
+        self.sessions.remove(&conn_id);
     }
 
-    async fn exec_board_cmd(&mut self, conn: ConnId, cmd: WsBoardCommand) -> String {
-        /* match cmd {
-            WsBoardCommand::Create => {
-                let board = Board::new(
-                    quader_engine::game_settings::GameSettings::default(),
-                    self.wkd.clone(),
-                    self.seed
-                );
-                self.boards.insert(conn.clone(), board);
-                let (bm, handle) = WsBoardMgr::new();
-                let handle = tokio::spawn(bm.run());
-                handle.await.ok();
-            },
-            WsBoardCommand::Destroy(id) => {
-                self.boards.remove(&id);
-            },
-            WsBoardCommand::Move(dir, amount) => {
-                let board = self.boards.get_mut(&conn).unwrap();
-                board.move_to(dir, amount);
-            },
-            WsBoardCommand::Rotate(dir) => {
-                let board = self.boards.get_mut(&conn).unwrap();
-                board.rotate(dir);
-            },
-            WsBoardCommand::HardDrop => {
-                let board = self.boards.get_mut(&conn).unwrap();
-                let _res = board.hard_drop();
-            },
-            WsBoardCommand::SoftDrop(amount) => {
-                let board = self.boards.get_mut(&conn).unwrap();
-                board.soft_drop(amount);
-            },
-            WsBoardCommand::HoldPiece => {
-                let board = self.boards.get_mut(&conn).unwrap();
-                board.try_hold_piece();
+    /// Returns the board manager of the running match of the lobby `conn` is in.
+    fn running_board_mgr(&self, conn: ConnId) -> Result<WsBoardMgrHandle, WsError> {
+        let lobby_id = self.find_lobby_id(conn).ok_or_else(not_in_lobby)?;
+
+        self.lobby_container
+            .get_lobby(&lobby_id)
+            .filter(|lobby| lobby.is_started)
+            .and_then(|lobby| lobby.board_mgr().cloned())
+            .ok_or_else(|| WsError::new(WsErrorKind::NoMatchRunning, "no match is running"))
+    }
+
+    async fn exec_board_cmd(
+        &mut self,
+        conn: ConnId,
+        input: Option<u64>,
+        cmd: WsBoardCommand,
+    ) -> Result<(), WsError> {
+        let board_mgr = self.running_board_mgr(conn)?;
+
+        // results of the command are sent to the players by the board manager
+        board_mgr.board_cmd(conn, input, cmd).await;
+
+        Ok(())
+    }
+
+    /// Sends the full state of the board of the player `board` to `conn`.
+    async fn resync(&mut self, conn: ConnId, board: ConnId) -> Result<(), WsError> {
+        let board_mgr = self.running_board_mgr(conn)?;
+
+        let (seq, snapshot) = board_mgr.snapshot(board).await.ok_or_else(|| {
+            WsError::new(WsErrorKind::InvalidMessage, format!("no board for player {board}"))
+        })?;
+
+        self.send_to(
+            conn,
+            WsServerMessage::BoardSnapshot {
+                conn: board,
+                seq,
+                snapshot,
             },
-        } */
+        );
 
-        "ok".to_string()
+        Ok(())
     }
 
     fn list_lobbies(&self) -> Vec<LobbyListing> {
         self.lobby_container.list_lobbies()
     }
 
-    async fn join_lobby(&mut self, conn: ConnId, lobby_id: String) {
-        if let Some(session) = self.sessions.get(&conn) {
-            let res =
-                self.lobby_container
-                    .try_add_player(&lobby_id, conn, session.user_info.clone());
-            if res.is_ok() {
-                self.send_system_message(
-                    &lobby_id,
-                    0,
-                    format!("{} connected!", session.user_info.username),
-                )
-                .await;
-                log::info!(
-                    "player {} joined lobby {}",
-                    session.user_info.username,
-                    lobby_id
-                )
-            } else {
-                log::error!("error joining lobby {}", lobby_id);
-                //self.send_message(conn, format!("error")).await;
-            }
+    async fn join_lobby(&mut self, conn: ConnId, lobby_id: String) -> Result<(), WsError> {
+        let Some(session) = self.sessions.get(&conn) else {
+            return Ok(());
+        };
+
+        if !self.lobby_container.contains_id(&lobby_id) {
+            return Err(WsError::new(
+                WsErrorKind::LobbyNotFound,
+                format!("lobby {lobby_id} does not exist"),
+            ));
+        }
+
+        let user_info = session.user_info.clone();
+        if self
+            .lobby_container
+            .try_add_player(&lobby_id, conn, user_info.clone())
+            .is_err()
+        {
+            log::error!("error joining lobby {}", lobby_id);
+            return Err(WsError::new(
+                WsErrorKind::LobbyFull,
+                format!("lobby {lobby_id} is full"),
+            ));
         }
+
+        log::info!("player {} joined lobby {}", user_info.username, lobby_id);
+
+        let players = self
+            .lobby_container
+            .get_lobby(&lobby_id)
+            .map(|lobby| {
+                lobby
+                    .player_list
+                    .iter()
+                    .map(|user| LobbyPlayer {
+                        conn: user.conn,
+                        username: user.user_info.username.clone(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        self.send_to(
+            conn,
+            WsServerMessage::Lobby(LobbyEvent::Joined {
+                lobby_id: lobby_id.clone(),
+                conn,
+                players,
+            }),
+        );
+        self.broadcast(
+            &lobby_id,
+            conn,
+            WsServerMessage::Lobby(LobbyEvent::PlayerJoined(LobbyPlayer {
+                conn,
+                username: user_info.username,
+            })),
+        );
+
+        Ok(())
     }
 
-    async fn start_match(&self, conn: ConnId) {
-        // TODO: find lobby by ConnId
-        let lobby = self.lobby_container.lobby_map.get("0").unwrap();
-        let mut running_lobby = lobby.run();
-        running_lobby.start().await;
-        if let Ok(msg) = running_lobby.try_recv().await {
-            match msg {
-                LobbyNotification::Started => {
-                    log::info!("Lobby started!");
-                }
-                LobbyNotification::Ended => {
-                    log::info!("Lobby ended!");
+    async fn start_match(&mut self, conn: ConnId) -> Result<(), WsError> {
+        let lobby_id = self.find_lobby_id(conn).ok_or_else(not_in_lobby)?;
+
+        let lobby = self.lobby_container.lobby_map.get_mut(&lobby_id).unwrap();
+        if lobby.is_started {
+            return Err(WsError::new(
+                WsErrorKind::MatchAlreadyStarted,
+                "the match is already running",
+            ));
+        }
+
+        lobby.start_match(self.match_tx.clone()).await.ok_or_else(|| {
+            WsError::new(WsErrorKind::NoMatchRunning, "failed to start the match")
+        })?;
+
+        Ok(())
+    }
+
+    fn on_match_event(&mut self, match_event: MatchEvent) {
+        let MatchEvent { lobby_id, message } = match_event;
+
+        match &message {
+            WsServerMessage::Lobby(LobbyEvent::MatchStarted { seed, .. }) => {
+                log::info!("lobby {lobby_id}: match started, seed {seed}");
+            }
+            WsServerMessage::Lobby(LobbyEvent::PlayerDead { conn, reason }) => {
+                log::info!("lobby {lobby_id}: conn {conn}: board is dead, reason: {reason:?}");
+            }
+            WsServerMessage::Lobby(LobbyEvent::MatchEnded { winner }) => {
+                log::info!("lobby {lobby_id}: match ended, winner: {winner:?}");
+
+                if let Some(lobby) = self.lobby_container.lobby_map.get_mut(&lobby_id) {
+                    lobby.is_started = false;
                 }
             }
+            _ => {}
         }
-        running_lobby.stop().await;
+
+        self.broadcast(&lobby_id, 0, message);
     }
 
     pub async fn run(mut self) -> io::Result<()> {
         log::debug!("running server");
 
-        while let Some(cmd) = self.cmd_rx.recv().await {
+        loop {
+            let cmd = select! {
+                cmd = self.cmd_rx.recv() => match cmd {
+                    Some(cmd) => cmd,
+                    None => break,
+                },
+                Some(event) = self.match_rx.recv() => {
+                    self.on_match_event(event);
+                    continue;
+                }
+            };
+
             log::debug!("got cmd: {:?}", &cmd);
 
             match cmd {
@@ -303,13 +422,38 @@ impl ChatServer {
                     self.send_message(conn, msg).await;
                     let _ = res_tx.send(());
                 }
-                Command::BoardCommand { cmd, conn, res_tx } => {
-                    let res = self.exec_board_cmd(conn, cmd).await;
+                Command::BoardCommand {
+                    cmd,
+                    input,
+                    conn,
+                    res_tx,
+                } => {
+                    let res = self.exec_board_cmd(conn, input, cmd).await;
+                    let _ = res_tx.send(res);
+                }
+                Command::StartMatch { conn, res_tx } => {
+                    let res = self.start_match(conn).await;
+                    let _ = res_tx.send(res);
+                }
+                Command::Resync {
+                    conn,
+                    board,
+                    res_tx,
+                } => {
+                    let res = self.resync(conn, board).await;
+                    let _ = res_tx.send(res);
+                }
+                Command::ListLobbies { res_tx } => {
+                    let _ = res_tx.send(self.list_lobbies());
+                }
+                Command::JoinLobby {
+                    conn,
+                    lobby_id,
+                    res_tx,
+                } => {
+                    let res = self.join_lobby(conn, lobby_id).await;
                     let _ = res_tx.send(res);
                 }
-                Command::StartMatch { conn, res_tx } => {}
-                Command::ListLobbies { .. } => {}
-                Command::JoinLobby { .. } => {}
             }
         }
 
@@ -325,7 +469,7 @@ pub struct ChatServerHandle {
 impl ChatServerHandle {
     pub async fn connect(
         &self,
-        conn_tx: mpsc::UnboundedSender<String>,
+        conn_tx: mpsc::UnboundedSender<WsServerMessage>,
         user_info: UserInfo,
     ) -> ConnId {
         let (res_tx, res_rx) = oneshot::channel();
@@ -361,26 +505,46 @@ impl ChatServerHandle {
         self.cmd_tx.send(Command::Disconnect { conn }).unwrap();
     }
 
-    pub async fn on_board_cmd(&self, conn: ConnId, cmd: WsBoardCommand) -> String {
+    /// Executes `cmd` on the board of `conn`. `input` is the sequence number
+    /// the client has assigned to the command, which is acknowledged after the execution.
+    pub async fn on_board_cmd(
+        &self,
+        conn: ConnId,
+        input: Option<u64>,
+        cmd: WsBoardCommand,
+    ) -> Result<(), WsError> {
         let (res_tx, res_rx) = oneshot::channel();
 
         self.cmd_tx
-            .send(Command::BoardCommand { cmd, conn, res_tx })
+            .send(Command::BoardCommand {
+                cmd,
+                input,
+                conn,
+                res_tx,
+            })
             .unwrap();
 
-        let res = res_rx.await.unwrap();
-
-        res
+        res_rx.await.unwrap()
     }
 
-    pub async fn start_match(&self, conn: ConnId) {
+    pub async fn start_match(&self, conn: ConnId) -> Result<(), WsError> {
         let (res_tx, res_rx) = oneshot::channel();
 
         self.cmd_tx
             .send(Command::StartMatch { conn, res_tx })
             .unwrap();
 
-        res_rx.await.unwrap();
+        res_rx.await.unwrap()
+    }
+
+    pub async fn resync(&self, conn: ConnId, board: ConnId) -> Result<(), WsError> {
+        let (res_tx, res_rx) = oneshot::channel();
+
+        self.cmd_tx
+            .send(Command::Resync { conn, board, res_tx })
+            .unwrap();
+
+        res_rx.await.unwrap()
     }
 
     pub async fn list_lobbies(&self) -> Vec<LobbyListing> {
@@ -391,7 +555,11 @@ impl ChatServerHandle {
         res_rx.await.unwrap()
     }
 
-    pub async fn join_lobby(&self, conn: ConnId, lobby_id: impl Into<String>) {
+    pub async fn join_lobby(
+        &self,
+        conn: ConnId,
+        lobby_id: impl Into<String>,
+    ) -> Result<(), WsError> {
         let (res_tx, res_rx) = oneshot::channel();
 
         self.cmd_tx
@@ -406,6 +574,10 @@ impl ChatServerHandle {
     }
 }
 
+fn not_in_lobby() -> WsError {
+    WsError::new(WsErrorKind::NotInLobby, "you are not in a lobby")
+}
+
 #[cfg(debug_assertions)]
 fn seed_lobbies(lobby_container: &mut LobbyContainer) {
     log::debug!("Seeding lobbies");
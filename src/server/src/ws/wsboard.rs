@@ -1,104 +1,490 @@
-use std::{
-    collections::HashMap,
-    sync::Arc,
-    time::{Duration, Instant},
-};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
-use quader_engine::{board::Board, time_mgr::TimeMgr, wall_kick_data::WallKickData};
+use quader_engine::{
+    board::Board,
+    board_sync::BoardSyncSource,
+    game_settings::GameSettings,
+    piece_mgr::BoardErrorReason,
+    protocol::{BoardSnapshot, LobbyEvent, WsBoardCommand, WsServerMessage},
+    replays::MoveResult,
+    targeting::{TargetCandidate, TargetingMgr},
+    time_mgr::TimeMgr,
+    wall_kick_data::WallKickData,
+};
 use rand::{thread_rng, RngCore};
 use tokio::{
-    pin, select,
+    select,
     sync::{mpsc, oneshot},
 };
 
-use crate::ConnId;
+use crate::{ConnId, LobbyUuid};
 
+/// Fixed simulation step in seconds.
 const MS_PER_UPDATE: f32 = 1.0 / 30.0;
 
+type BoardCmdResult = Option<Result<MoveResult, BoardErrorReason>>;
+
+#[derive(Debug)]
+pub enum WsBoardMgrCommand {
+    Start {
+        players: Vec<ConnId>,
+        res_tx: oneshot::Sender<u64>,
+    },
+    RemovePlayer {
+        conn: ConnId,
+    },
+    BoardCommand {
+        conn: ConnId,
+        /// Sequence number of the input assigned by the client, if any.
+        input: Option<u64>,
+        cmd: WsBoardCommand,
+        res_tx: oneshot::Sender<BoardCmdResult>,
+    },
+    Snapshot {
+        conn: ConnId,
+        res_tx: oneshot::Sender<Option<(u64, BoardSnapshot)>>,
+    },
+    Stop,
+}
+
+/// Message produced by the match of the lobby `lobby_id`, which must be sent to every player of the lobby.
+#[derive(Debug, Clone)]
+pub struct MatchEvent {
+    pub lobby_id: LobbyUuid,
+    pub message: WsServerMessage,
+}
+
+/// Runs a match of a single lobby. The manager owns the boards of all of the players,
+/// so the server is the only source of truth of the game state: the commands sent by
+/// the clients are applied here, gravity is advanced at a fixed tick rate and
+/// the outgoing damage is routed to the opponents.
 #[derive(Debug)]
 pub struct WsBoardMgr {
     pub is_started: bool,
+    lobby_id: LobbyUuid,
+    game_settings: GameSettings,
     time_mgr: TimeMgr,
     wkd: Arc<WallKickData>,
     seed: u64,
     boards: HashMap<ConnId, Board>,
-    terminate_tx: Option<oneshot::Sender<()>>,
-    cmd_rx: mpsc::UnboundedReceiver<()>,
+    /// Last state of the boards sent to the players.
+    sync: HashMap<ConnId, BoardSyncSource>,
+    /// Decides who receives the attacks of every player.
+    targeting: TargetingMgr,
+    cmd_rx: mpsc::UnboundedReceiver<WsBoardMgrCommand>,
+    event_tx: mpsc::UnboundedSender<MatchEvent>,
 }
 
 impl WsBoardMgr {
-    pub fn new() -> (Self, WsBoardMgrHandle) {
+    pub fn new(
+        lobby_id: LobbyUuid,
+        game_settings: GameSettings,
+        event_tx: mpsc::UnboundedSender<MatchEvent>,
+    ) -> (Self, WsBoardMgrHandle) {
         let (cmd_tx, cmd_rx) = mpsc::unbounded_channel();
+        let seed = thread_rng().next_u64();
 
         (
             Self {
                 is_started: false,
+                lobby_id,
+                wkd: Arc::new(WallKickData::new(&game_settings.wall_kick_data_mode)),
+                game_settings,
                 time_mgr: TimeMgr::new(),
-                wkd: Arc::new(WallKickData::default()),
-                seed: thread_rng().next_u64(),
+                seed,
                 boards: HashMap::new(),
-                terminate_tx: None,
+                sync: HashMap::new(),
+                targeting: TargetingMgr::new(seed),
                 cmd_rx,
+                event_tx,
             },
             WsBoardMgrHandle { cmd_tx },
         )
     }
 
     pub async fn run(mut self) {
-        let (terminate_tx, terminate_rx) = oneshot::channel();
-        self.terminate_tx = Some(terminate_tx);
-        let mut interval = tokio::time::interval(Duration::from_millis(333));
+        let mut interval = tokio::time::interval(Duration::from_secs_f32(MS_PER_UPDATE));
 
-        self.is_started = true;
+        loop {
+            let is_started = self.is_started;
+
+            select! {
+                cmd = self.cmd_rx.recv() => {
+                    match cmd {
+                        Some(WsBoardMgrCommand::Stop) | None => break,
+                        Some(cmd) => self.handle_cmd(cmd),
+                    }
+                }
+                _ = interval.tick(), if is_started => {
+                    // the clock is advanced by a fixed step so the simulation
+                    // doesn't depend on the scheduling of the task
+                    self.time_mgr.update(MS_PER_UPDATE);
+                    self.update_boards();
+                }
+            }
+        }
 
-        let mut prev = Instant::now();
+        log::info!("lobby {}: board manager stopped", self.lobby_id);
+    }
 
-        loop {
-            let tick = interval.tick();
-            pin!(tick);
+    fn handle_cmd(&mut self, cmd: WsBoardMgrCommand) {
+        match cmd {
+            WsBoardMgrCommand::Start { players, res_tx } => {
+                self.start(players);
+                let _ = res_tx.send(self.seed);
+            }
+            WsBoardMgrCommand::RemovePlayer { conn } => {
+                self.targeting.remove_player(conn);
+                if self.boards.remove(&conn).is_some() {
+                    self.check_match_end();
+                }
+            }
+            WsBoardMgrCommand::BoardCommand {
+                conn,
+                input,
+                cmd,
+                res_tx,
+            } => {
+                let res = self.exec_board_cmd(conn, input, cmd);
+                let _ = res_tx.send(res);
+            }
+            WsBoardMgrCommand::Snapshot { conn, res_tx } => {
+                let snapshot = self
+                    .sync
+                    .get(&conn)
+                    .map(|sync| (sync.get_seq(), sync.get_snapshot().clone()));
+                let _ = res_tx.send(snapshot);
+            }
+            WsBoardMgrCommand::Stop => {}
+        }
+    }
 
-            let now = Instant::now();
-            let dt = now - prev;
-            prev = now;
+    fn start(&mut self, players: Vec<ConnId>) {
+        self.seed = thread_rng().next_u64();
+        self.time_mgr.reset();
+        self.targeting.reset(self.seed);
 
-            select! {
-                /* _ = update => {
+        // every player gets the same piece sequence
+        self.boards = players
+            .into_iter()
+            .map(|conn| {
+                (conn, Board::new(self.game_settings.clone(), Arc::clone(&self.wkd), self.seed))
+            })
+            .collect();
+        self.sync = self
+            .boards
+            .iter()
+            .map(|(conn, board)| (*conn, BoardSyncSource::new(board)))
+            .collect();
+
+        self.is_started = true;
+
+        log::info!(
+            "lobby {}: match started with {} players, seed {}",
+            self.lobby_id,
+            self.boards.len(),
+            self.seed
+        );
+        self.send_event(WsServerMessage::Lobby(LobbyEvent::MatchStarted {
+            seed: self.seed,
+            players: self.boards.keys().copied().collect(),
+        }));
+
+        for (conn, sync) in &self.sync {
+            self.send_event(WsServerMessage::BoardSnapshot {
+                conn: *conn,
+                seq: sync.get_seq(),
+                snapshot: sync.get_snapshot().clone(),
+            });
+        }
+    }
+
+    fn exec_board_cmd(
+        &mut self,
+        conn: ConnId,
+        input: Option<u64>,
+        cmd: WsBoardCommand,
+    ) -> BoardCmdResult {
+        if !self.is_started {
+            return None;
+        }
 
+        match cmd {
+            WsBoardCommand::Destroy(id) => {
+                // the players may only leave the match themselves
+                if id == conn {
+                    self.targeting.remove_player(id);
+                    if self.boards.remove(&id).is_some() {
+                        self.check_match_end();
+                    }
                 }
-                _ = terminate_rx => {
-                    log::info!("terminating board manager");
-                } */
-                _ = tick => {
-                    //log::debug!("TICK, dt: {:?}", dt.as_secs_f32());
-                    self.time_mgr.update(dt.as_secs_f32());
+                return None;
+            }
+            WsBoardCommand::SetTargeting(strategy) => {
+                if self.boards.contains_key(&conn) {
+                    self.targeting.set_strategy(conn, strategy);
+                    self.send_event(WsServerMessage::TargetingChanged { conn, strategy });
                 }
+                return None;
             }
+            _ => {}
+        }
+
+        let board = self.boards.get_mut(&conn)?;
+        let was_dead = board.is_dead;
+
+        // `Create` does nothing, boards are created for every player of the lobby when the match starts
+        let res = cmd.apply(board);
+
+        if let Some(res) = &res {
+            if !was_dead {
+                self.handle_result(conn, res.clone());
+            }
+        }
+        self.sync_board(conn);
+
+        if let (Some(input), Some(sync)) = (input, self.sync.get(&conn)) {
+            // the client replays the inputs after this one on top of the state `seq`
+            self.send_event(WsServerMessage::InputAck {
+                conn,
+                input,
+                seq: sync.get_seq(),
+            });
         }
+
+        res
     }
 
     fn update_boards(&mut self) {
+        let mut results = vec![];
+
         for (conn, board) in &mut self.boards {
-            match board.update(&self.time_mgr) {
-                Some(Err(err)) => {
-                    log::info!("conn {conn}: board is dead. reason: {err:?}");
+            if board.is_dead {
+                continue;
+            }
+
+            if let Some(res) = board.update(&self.time_mgr) {
+                results.push((*conn, res));
+            }
+        }
+
+        for (conn, res) in results {
+            self.handle_result(conn, res);
+        }
+
+        self.sync_boards();
+    }
+
+    fn sync_boards(&mut self) {
+        let conns: Vec<ConnId> = self.boards.keys().copied().collect();
+
+        for conn in conns {
+            self.sync_board(conn);
+        }
+    }
+
+    /// Sends the changes of the board of `conn` since the last time to the players.
+    fn sync_board(&mut self, conn: ConnId) {
+        let (Some(board), Some(sync)) = (self.boards.get(&conn), self.sync.get_mut(&conn)) else {
+            return;
+        };
+
+        if let Some(delta) = sync.delta(board) {
+            self.send_event(WsServerMessage::BoardDelta { conn, delta });
+        }
+    }
+
+    fn handle_result(&mut self, conn: ConnId, res: Result<MoveResult, BoardErrorReason>) {
+        match res {
+            Ok(result) => {
+                let damage = result.attack.out_damage;
+
+                self.send_event(WsServerMessage::MoveResult { conn, result });
+
+                if damage > 0 {
+                    self.send_attack(conn, damage);
+                }
+            }
+            Err(reason) => {
+                let is_dead = self.boards.get(&conn).is_some_and(|b| b.is_dead);
+
+                if is_dead {
+                    self.send_event(WsServerMessage::Lobby(LobbyEvent::PlayerDead { conn, reason }));
+                    self.resolve_ko(conn);
+                    self.check_match_end();
                 }
-                _ => {}
             }
         }
     }
+
+    /// Credits the KO of `victim` to the player whose garbage has landed on its board last,
+    /// if that player is still alive. The killer takes the badges of the victim.
+    fn resolve_ko(&mut self, victim: ConnId) {
+        let Some(victim_board) = self.boards.get(&victim) else {
+            return;
+        };
+        let victim_badges = victim_board.get_badges();
+
+        let Some(killer) = victim_board.garbage_mgr.get_last_sender() else {
+            return;
+        };
+        let Some(killer_board) = self.boards.get_mut(&killer).filter(|b| killer != victim && !b.is_dead) else {
+            return;
+        };
+
+        killer_board.credit_ko(victim_badges);
+        let badges = killer_board.get_badges();
+
+        self.send_event(WsServerMessage::Lobby(LobbyEvent::KnockOut { conn: victim, by: killer, badges }));
+    }
+
+    /// Splits `damage` between the opponents of `from` that are still alive
+    /// according to the targeting strategy of `from`.
+    fn send_attack(&mut self, from: ConnId, damage: i32) {
+        let candidates: Vec<TargetCandidate> = self
+            .boards
+            .iter()
+            .filter(|(conn, board)| **conn != from && !board.is_dead)
+            .map(|(conn, board)| TargetCandidate::new(*conn, board))
+            .collect();
+
+        for (to, damage) in self.targeting.route(from, damage, &candidates) {
+            if let Some(board) = self.boards.get_mut(&to) {
+                board.attack_from(damage, from);
+            }
+
+            self.send_event(WsServerMessage::Attack { from, to, damage });
+        }
+    }
+
+    /// Ends the match when there is one player left standing
+    /// or, in case of a single player match, when the player is dead.
+    fn check_match_end(&mut self) {
+        if !self.is_started {
+            return;
+        }
+
+        let alive: Vec<ConnId> = self
+            .boards
+            .iter()
+            .filter_map(|(conn, board)| (!board.is_dead).then_some(*conn))
+            .collect();
+
+        if alive.len() > 1 || (alive.len() == 1 && self.boards.len() == 1) {
+            return;
+        }
+
+        let winner = alive.first().copied();
+        self.is_started = false;
+
+        log::info!("lobby {}: match ended, winner: {winner:?}", self.lobby_id);
+        self.send_event(WsServerMessage::Lobby(LobbyEvent::MatchEnded { winner }));
+    }
+
+    fn send_event(&self, message: WsServerMessage) {
+        let _ = self.event_tx.send(MatchEvent {
+            lobby_id: self.lobby_id.clone(),
+            message,
+        });
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct WsBoardMgrHandle {
-    cmd_tx: mpsc::UnboundedSender<()>,
+    cmd_tx: mpsc::UnboundedSender<WsBoardMgrCommand>,
 }
 
 impl WsBoardMgrHandle {
-    pub async fn start(&self) {
+    /// Starts a new match for `players`. Returns the seed of the match,
+    /// or `None` if the manager has stopped.
+    pub async fn start(&self, players: Vec<ConnId>) -> Option<u64> {
+        let (res_tx, res_rx) = oneshot::channel();
+
+        self.cmd_tx
+            .send(WsBoardMgrCommand::Start { players, res_tx })
+            .ok()?;
+
+        res_rx.await.ok()
+    }
+
+    pub fn remove_player(&self, conn: ConnId) {
+        let _ = self.cmd_tx.send(WsBoardMgrCommand::RemovePlayer { conn });
+    }
+
+    pub async fn board_cmd(
+        &self,
+        conn: ConnId,
+        input: Option<u64>,
+        cmd: WsBoardCommand,
+    ) -> BoardCmdResult {
+        let (res_tx, res_rx) = oneshot::channel();
+
+        self.cmd_tx
+            .send(WsBoardMgrCommand::BoardCommand {
+                conn,
+                input,
+                cmd,
+                res_tx,
+            })
+            .ok()?;
+
+        res_rx.await.ok().flatten()
+    }
+
+    /// Returns the last state of the board of `conn` sent to the players along with its sequence number.
+    pub async fn snapshot(&self, conn: ConnId) -> Option<(u64, BoardSnapshot)> {
         let (res_tx, res_rx) = oneshot::channel();
 
-        self.cmd_tx.send(()).unwrap();
+        self.cmd_tx
+            .send(WsBoardMgrCommand::Snapshot { conn, res_tx })
+            .ok()?;
+
+        res_rx.await.ok().flatten()
+    }
+
+    pub fn stop(&self) {
+        let _ = self.cmd_tx.send(WsBoardMgrCommand::Stop);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn garbage_top_out_is_credited_as_ko() {
+        let mut game_settings = GameSettings::default();
+        // the garbage lands with the next piece
+        game_settings.attack.garbage_delay_ms = 0;
+
+        let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+        let (mut mgr, _handle) = WsBoardMgr::new("lobby".to_string(), game_settings, event_tx);
+        mgr.start(vec![1, 2]);
+
+        // the garbage of the player 1 keeps coming until the player 2 tops out
+        for _ in 0..20 {
+            if mgr.boards[&2].is_dead {
+                break;
+            }
+            mgr.boards.get_mut(&2).unwrap().attack_from(4, 1);
+            mgr.exec_board_cmd(2, None, WsBoardCommand::HardDrop);
+        }
+        assert!(mgr.boards[&2].is_dead);
+
+        let killer = &mgr.boards[&1];
+        assert_eq!(killer.get_badges(), 1);
+        assert_eq!(killer.board_stats.kos, 1);
 
-        res_rx.await.unwrap()
+        let events: Vec<WsServerMessage> = std::iter::from_fn(|| event_rx.try_recv().ok())
+            .map(|event| event.message)
+            .collect();
+        assert!(events.iter().any(|message| matches!(
+            message,
+            WsServerMessage::Lobby(LobbyEvent::KnockOut { conn: 2, by: 1, badges: 1 })
+        )));
+        assert!(events.iter().any(|message| matches!(
+            message,
+            WsServerMessage::Lobby(LobbyEvent::MatchEnded { winner: Some(1) })
+        )));
     }
 }
@@ -0,0 +1,13 @@
+/*
+ * Copyright (c) Grigory Alfyorov. Licensed under the MIT License.
+ * See the LICENSE file in the repository root for full licence text.
+ */
+
+//! The native bot speaking the Tetris Bot Protocol over stdin and stdout.
+
+use quader_skynet::native_bot::NativeBotSettings;
+use quader_skynet::tbp_frontend::run_tbp_frontend;
+
+fn main() -> std::io::Result<()> {
+    run_tbp_frontend(NativeBotSettings::default(), std::io::stdin().lock(), std::io::stdout())
+}
@@ -4,76 +4,65 @@
  */
 
 use std::sync::{Arc};
-use cold_clear::{BotPollState, Info, Interface};
-use libtetris::Move;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use quader_engine::board::Board;
-use quader_engine::cell_holder::BoolArray;
 use quader_engine::game_settings::GameSettings;
-use quader_engine::piece::{PieceType, RotationDirection};
 use quader_engine::piece_mgr::BoardErrorReason;
-use quader_engine::replays::MoveResult;
+use quader_engine::replays::{MoveAction, MoveResult};
+use quader_engine::rng_manager::BOT_MISTAKE_RNG_STREAM;
+use quader_engine::scoring::TSpinStatus;
 use quader_engine::time_mgr::TimeMgr;
 use quader_engine::wall_kick_data::WallKickData;
-use crate::{BotSettings, piece_type_to_piece};
-
+use crate::{BotSettings, BotStatus};
+use crate::bot_engine::{BotEngine, BotEngineError, BotEngineKind, BotMove, create_bot_engine};
+use crate::evaluation::{BoardFeatures, Weights};
+use crate::native_bot::play_actions;
 
 pub struct BotBoard {
     pub engine_board: Board,
     pub game_settings: GameSettings,
-    pub bot_interface: Box<Interface>,
+    pub bot_engine: Box<dyn BotEngine>,
     pub bot_settings: BotSettings,
     elapsed_secs: f32,
     hold_used: bool,
     pub is_enabled: bool,
-    move_requested: bool
-}
-
-
-fn create_bot_interface(board: &Board) -> Box<Interface> {
-    let mut bot_board = libtetris::Board::new();
-    bot_board.add_next_piece(piece_type_to_piece(board.piece_mgr.cur_piece.get_type()));
-    for pt in &board.piece_mgr.piece_queue.queue {
-        bot_board.add_next_piece(piece_type_to_piece(*pt));
-    }
-
-    #[cfg(target_arch = "wasm32")]
-    return Box::new(futures::executor::block_on(Interface::launch(
-        "cold_clear",
-        bot_board,
-        cold_clear::Options::default(),
-        cold_clear::evaluation::Standard::default()
-    )));
-    #[cfg(not(target_arch = "wasm32"))]
-    Box::new(Interface::launch(
-        bot_board,
-        cold_clear::Options::default(),
-        cold_clear::evaluation::Standard::default(),
-        None
-    ))
+    move_requested: bool,
+    wkd: Arc<WallKickData>,
+    rng: ChaCha8Rng,
+    /// Incoming garbage the bot has already reacted to.
+    garbage_seen: u32,
+    reaction_elapsed_secs: f32
 }
 
 impl BotBoard {
+    /// Creates the board played by the engine chosen by `bot_settings.engine`
+    /// as well as `bot_settings.difficulty` allows.
     pub fn new(
         game_settings: GameSettings,
         wkd: Arc<WallKickData>,
         seed: u64,
         bot_settings: BotSettings
-    ) -> Self {
+    ) -> Result<Self, BotEngineError> {
 
-        let board = Board::new(game_settings, wkd, seed);
+        let board = Board::new(game_settings.clone(), Arc::clone(&wkd), seed);
 
-        let bot_interface = create_bot_interface(&board);
+        let bot_engine = create_bot_engine(&bot_settings, &board, Arc::clone(&wkd))?;
 
-        Self {
+        Ok(Self {
             engine_board: board,
-            bot_interface,
+            bot_engine,
             game_settings,
             bot_settings,
             elapsed_secs: 0.0,
             hold_used: false,
             is_enabled: true,
-            move_requested: false
-        }
+            move_requested: false,
+            wkd,
+            rng: create_rng(seed),
+            garbage_seen: 0,
+            reaction_elapsed_secs: 0.0
+        })
     }
 
     pub fn update(&mut self, time_mgr: &TimeMgr) -> Option<Result<MoveResult, BoardErrorReason>> {
@@ -81,8 +70,12 @@ impl BotBoard {
             return None;
         }
 
+        if !self.react_to_garbage(time_mgr.last_dt) {
+            return self.engine_board.update(time_mgr);
+        }
+
         if !self.move_requested {
-            self.request_next_move(self.calc_incoming_garbage());
+            self.bot_engine.request_move(&self.engine_board, self.garbage_seen);
             self.move_requested = true;
         }
 
@@ -104,67 +97,56 @@ impl BotBoard {
         self.elapsed_secs = 0.0;
         self.is_enabled = true;
         self.move_requested = false;
+        self.rng = create_rng(self.engine_board.get_seed());
+        self.garbage_seen = 0;
+        self.reaction_elapsed_secs = 0.0;
 
-        self.bot_interface = create_bot_interface(&self.engine_board);
-    }
-
-    pub fn add_next_piece(&self, piece_type: PieceType) {
-        self.bot_interface.add_next_piece(piece_type_to_piece(piece_type));
+        self.bot_engine.reset(&self.engine_board);
     }
 
-    pub fn request_next_move(&self, incoming_garbage: u32) {
-        self.bot_interface.suggest_next_move(incoming_garbage);
-    }
+    /// Returns `false` while the bot hasn't noticed the new incoming garbage yet.
+    fn react_to_garbage(&mut self, dt: f32) -> bool {
+        let incoming = self.calc_incoming_garbage();
 
-    pub fn poll_next_move(&mut self) -> Result<(Move, Info), BotPollState> {
-        self.bot_interface.poll_next_move()
-    }
+        if incoming > self.garbage_seen {
+            self.reaction_elapsed_secs += dt;
+            if self.reaction_elapsed_secs < self.bot_settings.difficulty.garbage_reaction_secs {
+                return false;
+            }
+        }
 
-    /*pub fn block_next_move(&self) -> Option<(libtetris::Move, Info)> {
-        self.bot_interface.block_next_move()
-    }*/
+        self.garbage_seen = incoming;
+        self.reaction_elapsed_secs = 0.0;
 
-    pub fn play_next_move(&self, falling_piece: libtetris::FallingPiece) {
-       self.bot_interface.play_next_move(falling_piece);
+        true
     }
 
     fn do_bot_move(&mut self) -> Option<Result<MoveResult, BoardErrorReason>> {
 
-        let res = match self.poll_next_move() {
-            Ok((m, _info)) => {
-                self.play_next_move(m.expected_location);
-                //let _plan = info.plan();
+        let res = match self.bot_engine.poll_move() {
+            Ok(m) => {
+                let (m, is_mistake) = self.apply_mistakes(m);
 
                 if m.hold {
                     let _ = self.engine_board.try_hold_piece();
+                    // the first hold takes one more piece from the queue
                     if !self.hold_used {
-                        self.bot_interface.add_next_piece(
-                            piece_type_to_piece(
-                                *self.engine_board.piece_mgr.piece_queue.queue.back().unwrap()
-                            )
-                        );
+                        self.add_last_queue_piece();
                         self.hold_used = true;
                     }
                 }
 
-                for input in m.inputs.iter() {
-                    self.exec_input(input);
-                }
+                let res = m.actions
+                    .into_iter()
+                    .find_map(|action| self.engine_board.exec_action(action))
+                    .unwrap_or_else(|| self.engine_board.hard_drop());
 
-                match self.engine_board.hard_drop() {
+                match res {
                     Ok(hd) => {
-                        if !hd.attack.in_damage_queue.is_empty() {
-                            // update bot's board
-                            let new_board = self.engine_board.piece_mgr.cell_holder.to_bool_array();
-                            let mut field = [[false; 10]; 40];
-
-                            for (y, row) in new_board.iter().enumerate() {
-                                for (x, val) in row.iter().enumerate() {
-                                    field[39 - y][x] = *val;
-                                }
-                            }
-
-                            self.bot_interface.reset(field, hd.b2b > 0, hd.combo);
+                        // the engine expects the piece where it wanted it, so it gets the actual field
+                        // the same way as after the garbage
+                        if is_mistake || !hd.attack.in_damage_queue.is_empty() {
+                            self.bot_engine.garbage_received(&self.engine_board);
                         }
 
                         Some(Ok(hd))
@@ -174,18 +156,14 @@ impl BotBoard {
             }
             Err(err) => {
                 match err {
-                    BotPollState::Waiting => None,
-                    BotPollState::Dead => Some(Err(BoardErrorReason::BoardDead))
+                    BotStatus::Waiting => None,
+                    BotStatus::Dead => Some(Err(BoardErrorReason::BoardDead))
                 }
             }
         };
 
         if let Some(Ok(_)) = res {
-            self.bot_interface.add_next_piece(
-                piece_type_to_piece(
-                    *self.engine_board.piece_mgr.piece_queue.queue.back().unwrap()
-                )
-            );
+            self.add_last_queue_piece();
 
             self.move_requested = false;
         }
@@ -193,14 +171,72 @@ impl BotBoard {
         res
     }
 
-    fn exec_input(&mut self, input: &libtetris::PieceMovement) {
-        match input {
-            libtetris::PieceMovement::Left => { self.engine_board.move_left(1); },
-            libtetris::PieceMovement::Right => { self.engine_board.move_right(1); },
-            libtetris::PieceMovement::Cw => { self.engine_board.rotate(RotationDirection::Clockwise); },
-            libtetris::PieceMovement::Ccw => { self.engine_board.rotate(RotationDirection::CounterClockwise); },
-            libtetris::PieceMovement::SonicDrop => { self.engine_board.soft_drop(40); },
+    /// Spoils `bot_move` as the difficulty says. Returns the move to play and whether it was changed.
+    fn apply_mistakes(&mut self, bot_move: BotMove) -> (BotMove, bool) {
+        let difficulty = self.bot_settings.difficulty;
+        let mut bot_move = bot_move;
+        let mut is_mistake = false;
+
+        if difficulty.skip_tspin_rate > 0.0 && self.rng.gen_bool(difficulty.skip_tspin_rate.clamp(0.0, 1.0) as f64) {
+            if let Some(actions) = self.find_non_tspin_actions(&bot_move) {
+                bot_move.actions = actions;
+                is_mistake = true;
+            }
+        }
+
+        if difficulty.misdrop_rate > 0.0 && self.rng.gen_bool(difficulty.misdrop_rate.clamp(0.0, 1.0) as f64) {
+            let shift = if self.rng.gen_bool(0.5) { MoveAction::MoveLeft } else { MoveAction::MoveRight };
+            let hard_drop_pos = bot_move.actions
+                .iter()
+                .position(|a| *a == MoveAction::HardDrop)
+                .unwrap_or(bot_move.actions.len());
+
+            bot_move.actions.insert(hard_drop_pos, shift);
+            is_mistake = true;
+        }
+
+        (bot_move, is_mistake)
+    }
+
+    /// Returns the actions of the best placement without a T-Spin if `bot_move` does a T-Spin.
+    fn find_non_tspin_actions(&self, bot_move: &BotMove) -> Option<Vec<MoveAction>> {
+        let mut piece_mgr = self.engine_board.get_piece_mgr().clone();
+        if bot_move.hold {
+            piece_mgr.try_hold_piece();
+        }
+
+        let mut played = piece_mgr.clone();
+        let hard_drop_info = play_actions(&mut played, &self.wkd, &bot_move.actions).ok()?;
+        if hard_drop_info.tspin_status == TSpinStatus::None {
+            return None;
+        }
+
+        let visible_height = self.game_settings.board.height as u32;
+        // the other engines don't tell how they value the boards
+        let weights = match &self.bot_settings.engine {
+            BotEngineKind::Native(settings) => settings.weights,
+            _ => Weights::default()
         };
+
+        piece_mgr.find_placements_fast(&self.wkd)
+            .into_iter()
+            .filter(|p| p.tspin_status == TSpinStatus::None)
+            .filter_map(|p| {
+                let mut child = piece_mgr.clone();
+                play_actions(&mut child, &self.wkd, &p.actions).ok()?;
+
+                let value = BoardFeatures::new(&child.cell_holder).evaluate(&weights, visible_height);
+                Some((value, p.actions))
+            })
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, actions)| actions)
+    }
+
+    /// Tells the engine about the piece which has just appeared at the end of the preview.
+    fn add_last_queue_piece(&mut self) {
+        if let Some(piece_type) = self.engine_board.piece_mgr.piece_queue.get_preview().last() {
+            self.bot_engine.add_next_piece(*piece_type);
+        }
     }
 
     fn calc_incoming_garbage(&self) -> u32 {
@@ -209,4 +245,102 @@ impl BotBoard {
             .map(|q| q.amount)
             .fold(0, |acc, q| acc + q) as u32
     }
-}
\ No newline at end of file
+}
+
+fn create_rng(seed: u64) -> ChaCha8Rng {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    rng.set_stream(BOT_MISTAKE_RNG_STREAM);
+
+    rng
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BotDifficulty;
+
+    #[test]
+    fn native_bot_board_plays() {
+        let game_settings = GameSettings::default();
+        let wkd = Arc::new(WallKickData::new(&game_settings.wall_kick_data_mode));
+        let bot_settings = BotSettings { target_pps: 0.0, ..Default::default() };
+        let mut bot_board = BotBoard::new(game_settings, wkd, 1337, bot_settings).unwrap();
+
+        let mut time_mgr = TimeMgr::new();
+        time_mgr.update(1.0 / 60.0);
+
+        for _ in 0..5 {
+            assert!(matches!(bot_board.update(&time_mgr), Some(Ok(_))));
+        }
+        assert_eq!(bot_board.engine_board.board_stats.total_pieces, 5);
+
+        bot_board.reset(Some(42));
+        assert_eq!(bot_board.engine_board.board_stats.total_pieces, 0);
+        assert!(matches!(bot_board.update(&time_mgr), Some(Ok(_))));
+    }
+
+    #[test]
+    fn bot_board_makes_mistakes_and_reacts_late() {
+        let game_settings = GameSettings::default();
+        let wkd = Arc::new(WallKickData::new(&game_settings.wall_kick_data_mode));
+        let bot_settings = BotSettings {
+            target_pps: 0.0,
+            difficulty: BotDifficulty {
+                node_budget: 50,
+                misdrop_rate: 1.0,
+                garbage_reaction_secs: 0.45,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut bot_board = BotBoard::new(game_settings, wkd, 1337, bot_settings).unwrap();
+
+        let mut time_mgr = TimeMgr::new();
+        time_mgr.update(0.1);
+
+        // every move is a misdrop, but the bot keeps playing
+        for _ in 0..3 {
+            assert!(matches!(bot_board.update(&time_mgr), Some(Ok(_))));
+        }
+
+        bot_board.engine_board.garbage_mgr.attack_at(2, 0);
+        for _ in 0..4 {
+            assert!(bot_board.update(&time_mgr).is_none());
+        }
+        assert_eq!(bot_board.engine_board.board_stats.total_pieces, 3);
+
+        assert!(matches!(bot_board.update(&time_mgr), Some(Ok(_))));
+        assert_eq!(bot_board.garbage_seen, 2);
+    }
+
+    #[test]
+    #[cfg(not(feature = "cold-clear"))]
+    fn cold_clear_requires_feature() {
+        let game_settings = GameSettings::default();
+        let wkd = Arc::new(WallKickData::new(&game_settings.wall_kick_data_mode));
+        let bot_settings = BotSettings { engine: BotEngineKind::ColdClear, ..Default::default() };
+
+        assert!(matches!(
+            BotBoard::new(game_settings, wkd, 1337, bot_settings),
+            Err(BotEngineError::Unsupported)
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "cold-clear")]
+    fn cold_clear_requires_standard_board() {
+        use quader_engine::game_settings::BoardSettings;
+
+        let game_settings = GameSettings {
+            board: BoardSettings { width: 6, height: 12 },
+            ..Default::default()
+        };
+        let wkd = Arc::new(WallKickData::new(&game_settings.wall_kick_data_mode));
+        let bot_settings = BotSettings { engine: BotEngineKind::ColdClear, ..Default::default() };
+
+        assert!(matches!(
+            BotBoard::new(game_settings, wkd, 1337, bot_settings),
+            Err(BotEngineError::Unsupported)
+        ));
+    }
+}
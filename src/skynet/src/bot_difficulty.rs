@@ -0,0 +1,108 @@
+/*
+ * Copyright (c) Grigory Alfyorov. Licensed under the MIT License.
+ * See the LICENSE file in the repository root for full licence text.
+ */
+
+use crate::BotSettings;
+use crate::bot_engine::BotEngineKind;
+use crate::evaluation::Weights;
+use crate::native_bot::NativeBotSettings;
+
+/// How well the bot plays. The mistakes and the reaction delay are made by the `BotBoard`,
+/// so they work with every engine, while the search limits are up to the engine.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct BotDifficulty {
+    /// Time the bot may spend looking for a move in milliseconds, 0 means no limit.
+    pub think_time_ms: u32,
+    /// Number of the boards the bot may look at looking for a move, 0 means no limit.
+    pub node_budget: u32,
+    /// Chance of dropping the piece one column away from where the bot wanted it, from 0 to 1.
+    pub misdrop_rate: f32,
+    /// Chance of giving up a T-Spin for the best placement without it, from 0 to 1.
+    pub skip_tspin_rate: f32,
+    /// Seconds the bot needs to notice the new incoming garbage. It doesn't place the pieces meanwhile.
+    pub garbage_reaction_secs: f32
+}
+
+/// Ready-made bot settings for practice games.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BotPreset {
+    Easy,
+    Medium,
+    Hard,
+    /// Keeps the board low and clean, rarely sends anything.
+    Downstack,
+    /// Builds T-Spin setups whenever it can and keeps B2B going.
+    TSpinSpammer
+}
+
+impl BotPreset {
+    pub fn settings(self) -> BotSettings {
+        let (target_pps, native, difficulty) = match self {
+            BotPreset::Easy => (
+                0.8,
+                NativeBotSettings { beam_width: 4, depth: 1, ..Default::default() },
+                BotDifficulty {
+                    node_budget: 100,
+                    misdrop_rate: 0.15,
+                    skip_tspin_rate: 0.8,
+                    garbage_reaction_secs: 1.0,
+                    ..Default::default()
+                }
+            ),
+            BotPreset::Medium => (
+                1.5,
+                NativeBotSettings { beam_width: 8, depth: 2, ..Default::default() },
+                BotDifficulty {
+                    node_budget: 1000,
+                    misdrop_rate: 0.05,
+                    skip_tspin_rate: 0.3,
+                    garbage_reaction_secs: 0.5,
+                    ..Default::default()
+                }
+            ),
+            BotPreset::Hard => (
+                3.0,
+                NativeBotSettings::default(),
+                BotDifficulty::default()
+            ),
+            BotPreset::Downstack => (
+                1.5,
+                NativeBotSettings {
+                    weights: Weights {
+                        holes: -8.0,
+                        height: -1.0,
+                        well: 0.0,
+                        t_slots: 0.0,
+                        attack: 1.0,
+                        clear_waste: 0.0,
+                        b2b: 0.0,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                BotDifficulty { misdrop_rate: 0.02, garbage_reaction_secs: 0.3, ..Default::default() }
+            ),
+            BotPreset::TSpinSpammer => (
+                2.0,
+                NativeBotSettings {
+                    weights: Weights {
+                        t_slots: 6.0,
+                        attack: 4.0,
+                        clear_waste: -3.0,
+                        b2b: 3.0,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                BotDifficulty { garbage_reaction_secs: 0.3, ..Default::default() }
+            )
+        };
+
+        BotSettings {
+            target_pps,
+            engine: BotEngineKind::Native(native),
+            difficulty
+        }
+    }
+}
@@ -0,0 +1,137 @@
+/*
+ * Copyright (c) Grigory Alfyorov. Licensed under the MIT License.
+ * See the LICENSE file in the repository root for full licence text.
+ */
+
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use quader_engine::board::Board;
+use quader_engine::piece::PieceType;
+use quader_engine::replays::MoveAction;
+use quader_engine::wall_kick_data::WallKickData;
+use crate::{BotSettings, BotStatus};
+#[cfg(feature = "cold-clear")]
+use crate::cold_clear_engine::ColdClearEngine;
+use crate::native_bot::{NativeBot, NativeBotSettings, SearchLimits};
+use crate::native_bot_engine::NativeBotEngine;
+
+/// Move suggested by a bot.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BotMove {
+    /// The piece must be held before playing the `actions`.
+    pub hold: bool,
+    /// Actions which put the piece where the bot wants it. Ends with `MoveAction::HardDrop`.
+    pub actions: Vec<MoveAction>
+}
+
+/// Backend which decides the moves of a `BotBoard`.
+///
+/// The engine is told about every change of the board it can't predict itself,
+/// while the `BotBoard` plays the moves on the engine board.
+pub trait BotEngine {
+    /// Starts over from the state of `board`: the field, the current piece, the queue,
+    /// the hold piece, the B2B and the combo.
+    fn reset(&mut self, board: &Board);
+
+    /// Tells the engine about a new piece added to the end of the queue.
+    fn add_next_piece(&mut self, piece_type: PieceType);
+
+    /// Asks the engine to start looking for the move of the current piece of `board`.
+    fn request_move(&mut self, board: &Board, incoming_garbage: u32);
+
+    /// Returns the requested move, or `BotStatus::Waiting` if it is not found yet.
+    /// `BotStatus::Dead` means the engine can't find any move.
+    fn poll_move(&mut self) -> Result<BotMove, BotStatus>;
+
+    /// Tells the engine that garbage was added to the field of `board`.
+    fn garbage_received(&mut self, board: &Board);
+}
+
+/// Bot backends available for `BotSettings`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BotEngineKind {
+    /// Requires the `cold-clear` feature and plays only on the 10x40 boards.
+    ColdClear,
+    Native(NativeBotSettings),
+    /// Bot running in a separate process, see `ExternalBotEngine`.
+    External {
+        program: String,
+        args: Vec<String>
+    },
+    /// Third-party bot speaking the Tetris Bot Protocol, see `TbpBotEngine`.
+    Tbp {
+        program: String,
+        args: Vec<String>
+    }
+}
+
+impl Default for BotEngineKind {
+    fn default() -> Self {
+        BotEngineKind::Native(NativeBotSettings::default())
+    }
+}
+
+#[derive(Debug)]
+pub enum BotEngineError {
+    /// The process of the external bot failed to start.
+    Spawn(std::io::Error),
+    /// The engine is not supported on the current platform or with the board size,
+    /// or is not enabled by the crate features.
+    Unsupported
+}
+
+impl Display for BotEngineError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BotEngineError::Spawn(err) => write!(f, "Failed to start the bot process: {}", err),
+            BotEngineError::Unsupported => write!(f, "The bot engine is not supported on this platform, build or board")
+        }
+    }
+}
+
+impl std::error::Error for BotEngineError {}
+
+/// Creates the engine chosen by `bot_settings` starting from the state of `board`.
+/// The search limits of `bot_settings.difficulty` are applied to the engines supporting them.
+pub fn create_bot_engine(
+    bot_settings: &BotSettings,
+    board: &Board,
+    wkd: Arc<WallKickData>
+) -> Result<Box<dyn BotEngine>, BotEngineError> {
+    let difficulty = &bot_settings.difficulty;
+
+    let engine: Box<dyn BotEngine> = match &bot_settings.engine {
+        #[cfg(feature = "cold-clear")]
+        BotEngineKind::ColdClear => {
+            if !ColdClearEngine::supports(&board.game_settings.board) {
+                return Err(BotEngineError::Unsupported);
+            }
+
+            Box::new(ColdClearEngine::new(board, difficulty.node_budget))
+        }
+        #[cfg(not(feature = "cold-clear"))]
+        BotEngineKind::ColdClear => return Err(BotEngineError::Unsupported),
+        BotEngineKind::Native(settings) => {
+            let mut bot = NativeBot::new(*settings, board.game_settings.clone(), wkd);
+            bot.limits = SearchLimits {
+                node_budget: difficulty.node_budget,
+                think_time_ms: difficulty.think_time_ms
+            };
+
+            Box::new(NativeBotEngine::new(bot))
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        BotEngineKind::External { program, args } => {
+            Box::new(crate::external_engine::ExternalBotEngine::spawn(program, args, board)?)
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        BotEngineKind::Tbp { program, args } => {
+            Box::new(crate::tbp_engine::TbpBotEngine::spawn(program, args, board, wkd)?)
+        }
+        #[cfg(target_arch = "wasm32")]
+        BotEngineKind::External { .. } | BotEngineKind::Tbp { .. } => return Err(BotEngineError::Unsupported)
+    };
+
+    Ok(engine)
+}
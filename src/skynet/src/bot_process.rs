@@ -0,0 +1,89 @@
+/*
+ * Copyright (c) Grigory Alfyorov. Licensed under the MIT License.
+ * See the LICENSE file in the repository root for full licence text.
+ */
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use serde::Serialize;
+use crate::bot_engine::BotEngineError;
+
+/// Bot running in a separate process and exchanging JSON messages, one per line,
+/// over its stdin and stdout. The process is killed when dropped.
+pub struct BotProcess {
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+    /// Writing to the process failed or it closed its stdout.
+    pub is_dead: bool
+}
+
+impl BotProcess {
+    pub fn spawn(program: &str, args: &[String]) -> Result<Self, BotEngineError> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(BotEngineError::Spawn)?;
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+
+        let (tx, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            child,
+            stdin,
+            lines,
+            is_dead: false
+        })
+    }
+
+    pub fn send<T: Serialize>(&mut self, message: &T) {
+        let line = serde_json::to_string(message).unwrap();
+
+        if writeln!(self.stdin, "{}", line).and_then(|_| self.stdin.flush()).is_err() {
+            self.is_dead = true;
+        }
+    }
+
+    /// Returns the next line written by the process, or `None` if there is none yet
+    /// or the process is dead.
+    pub fn try_recv(&mut self) -> Option<String> {
+        if self.is_dead {
+            return None;
+        }
+
+        match self.lines.try_recv() {
+            Ok(line) => Some(line),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                self.is_dead = true;
+                None
+            }
+        }
+    }
+
+    /// Drops the lines written by the process so far.
+    pub fn clear(&mut self) {
+        while self.lines.try_recv().is_ok() {}
+    }
+}
+
+impl Drop for BotProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
@@ -0,0 +1,130 @@
+/*
+ * Copyright (c) Grigory Alfyorov. Licensed under the MIT License.
+ * See the LICENSE file in the repository root for full licence text.
+ */
+
+use cold_clear::{BotPollState, Interface};
+use quader_engine::board::Board;
+use quader_engine::cell_holder::BoolArray;
+use quader_engine::game_settings::{BoardSettings, HoldMode};
+use quader_engine::piece::PieceType;
+use quader_engine::replays::MoveAction;
+use crate::{BotStatus, piece_type_to_piece};
+use crate::bot_engine::{BotEngine, BotMove};
+
+/// Size of the field of cold-clear, which can't be changed.
+const FIELD_WIDTH: usize = 10;
+const FIELD_HEIGHT: usize = 40;
+
+/// `BotEngine` of cold-clear.
+pub struct ColdClearEngine {
+    interface: Box<Interface>,
+    /// Maximum number of the nodes cold-clear looks at per move, 0 means no limit.
+    node_budget: u32,
+    /// Number of the soft drops which drop the piece all the way down.
+    sonic_drop: usize
+}
+
+fn create_bot_interface(board: &Board, node_budget: u32) -> Box<Interface> {
+    let mut bot_board = libtetris::Board::new();
+    bot_board.add_next_piece(piece_type_to_piece(board.piece_mgr.cur_piece.get_type()));
+    for pt in board.piece_mgr.piece_queue.get_preview() {
+        bot_board.add_next_piece(piece_type_to_piece(*pt));
+    }
+
+    let mut options = cold_clear::Options {
+        use_hold: board.game_settings.hold_mode != HoldMode::Disabled,
+        ..Default::default()
+    };
+    if node_budget > 0 {
+        options.max_nodes = node_budget;
+        options.min_nodes = options.min_nodes.min(node_budget);
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    return Box::new(futures::executor::block_on(Interface::launch(
+        "cold_clear",
+        bot_board,
+        options,
+        cold_clear::evaluation::Standard::default()
+    )));
+    #[cfg(not(target_arch = "wasm32"))]
+    Box::new(Interface::launch(
+        bot_board,
+        options,
+        cold_clear::evaluation::Standard::default(),
+        None
+    ))
+}
+
+impl ColdClearEngine {
+    pub fn new(board: &Board, node_budget: u32) -> Self {
+        Self {
+            interface: create_bot_interface(board, node_budget),
+            node_budget,
+            sonic_drop: board.game_settings.board.full_height()
+        }
+    }
+
+    /// Returns `false` if cold-clear can't play on the boards of `board_settings`.
+    pub fn supports(board_settings: &BoardSettings) -> bool {
+        board_settings.width == FIELD_WIDTH && board_settings.full_height() == FIELD_HEIGHT
+    }
+
+    fn to_action(&self, input: &libtetris::PieceMovement) -> Vec<MoveAction> {
+        match input {
+            libtetris::PieceMovement::Left => vec![MoveAction::MoveLeft],
+            libtetris::PieceMovement::Right => vec![MoveAction::MoveRight],
+            libtetris::PieceMovement::Cw => vec![MoveAction::RotateCW],
+            libtetris::PieceMovement::Ccw => vec![MoveAction::RotateCCW],
+            libtetris::PieceMovement::SonicDrop => vec![MoveAction::SoftDrop; self.sonic_drop]
+        }
+    }
+}
+
+impl BotEngine for ColdClearEngine {
+    fn reset(&mut self, board: &Board) {
+        self.interface = create_bot_interface(board, self.node_budget);
+    }
+
+    fn add_next_piece(&mut self, piece_type: PieceType) {
+        self.interface.add_next_piece(piece_type_to_piece(piece_type));
+    }
+
+    fn request_move(&mut self, _board: &Board, incoming_garbage: u32) {
+        self.interface.suggest_next_move(incoming_garbage);
+    }
+
+    fn poll_move(&mut self) -> Result<BotMove, BotStatus> {
+        match self.interface.poll_next_move() {
+            Ok((m, _info)) => {
+                self.interface.play_next_move(m.expected_location);
+
+                let mut actions: Vec<MoveAction> = m.inputs
+                    .iter()
+                    .flat_map(|input| self.to_action(input))
+                    .collect();
+                actions.push(MoveAction::HardDrop);
+
+                Ok(BotMove { hold: m.hold, actions })
+            }
+            Err(BotPollState::Waiting) => Err(BotStatus::Waiting),
+            Err(BotPollState::Dead) => Err(BotStatus::Dead)
+        }
+    }
+
+    fn garbage_received(&mut self, board: &Board) {
+        // update bot's board
+        let new_board = board.piece_mgr.cell_holder.to_bool_array();
+        let mut field = [[false; FIELD_WIDTH]; FIELD_HEIGHT];
+
+        for (y, row) in new_board.iter().enumerate().take(FIELD_HEIGHT) {
+            for (x, val) in row.iter().enumerate().take(FIELD_WIDTH) {
+                field[FIELD_HEIGHT - 1 - y][x] = *val;
+            }
+        }
+
+        let scoring_mgr = board.get_scoring_mgr();
+        self.interface.reset(field, scoring_mgr.b2b > 0, scoring_mgr.combo);
+    }
+}
@@ -0,0 +1,201 @@
+/*
+ * Copyright (c) Grigory Alfyorov. Licensed under the MIT License.
+ * See the LICENSE file in the repository root for full licence text.
+ */
+
+use quader_engine::cell_holder::{CellHolder, CellType};
+
+/// Weights of the features of a board used by the native bot.
+/// Positive weights are rewards, negative ones are penalties.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Weights {
+    /// Per empty cell with an occupied cell somewhere above it.
+    pub holes: f32,
+    /// Per cell of the height difference between the neighbouring columns.
+    pub bumpiness: f32,
+    /// Per row of the highest column.
+    pub height: f32,
+    /// Per row of the highest column above the half of the visible board.
+    pub danger: f32,
+    /// Per row of the deepest well, up to 4 rows.
+    pub well: f32,
+    /// Per T-Spin Double slot ready to be filled.
+    pub t_slots: f32,
+    /// Per line sent to the opponent.
+    pub attack: f32,
+    /// Per line cleared without sending any attack.
+    pub clear_waste: f32,
+    /// For keeping the B2B chain going.
+    pub b2b: f32
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Self {
+            holes: -4.0,
+            bumpiness: -0.6,
+            height: -0.3,
+            danger: -2.0,
+            well: 0.5,
+            t_slots: 2.5,
+            attack: 3.0,
+            clear_waste: -1.5,
+            b2b: 1.0
+        }
+    }
+}
+
+/// Shape of the stack of a board.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BoardFeatures {
+    /// Heights of the columns from the left to the right.
+    pub heights: Vec<u32>,
+    pub holes: u32,
+    pub bumpiness: u32,
+    pub max_height: u32,
+    pub well_depth: u32,
+    pub t_slots: u32
+}
+
+impl BoardFeatures {
+    pub fn new(cell_holder: &CellHolder) -> Self {
+        let width = cell_holder.get_width();
+        let height = cell_holder.get_height();
+        let is_occupied = |x: usize, y: usize| cell_holder.get_cell_at(x, y) != CellType::None;
+
+        let heights: Vec<u32> = (0..width)
+            .map(|x| {
+                (0..height)
+                    .position(|y| is_occupied(x, y))
+                    .map_or(0, |y| (height - y) as u32)
+            })
+            .collect();
+
+        let holes = (0..width)
+            .map(|x| {
+                let top = height - heights[x] as usize;
+                (top..height).filter(|y| !is_occupied(x, *y)).count() as u32
+            })
+            .sum();
+
+        let bumpiness = heights
+            .windows(2)
+            .map(|w| w[0].abs_diff(w[1]))
+            .sum();
+
+        // the walls are as high as the board
+        let wall = height as u32;
+        let well_depth = (0..width)
+            .map(|x| {
+                let left = if x == 0 { wall } else { heights[x - 1] };
+                let right = if x + 1 == width { wall } else { heights[x + 1] };
+                left.min(right).saturating_sub(heights[x])
+            })
+            .max()
+            .unwrap_or(0);
+
+        Self {
+            max_height: heights.iter().copied().max().unwrap_or(0),
+            t_slots: count_t_slots(cell_holder, &heights),
+            heights,
+            holes,
+            bumpiness,
+            well_depth
+        }
+    }
+
+    /// Returns the value of the board according to `weights`.
+    pub fn evaluate(&self, weights: &Weights, visible_height: u32) -> f32 {
+        let danger = self.max_height.saturating_sub(visible_height / 2);
+
+        self.holes as f32 * weights.holes
+            + self.bumpiness as f32 * weights.bumpiness
+            + self.max_height as f32 * weights.height
+            + danger as f32 * weights.danger
+            + self.well_depth.min(4) as f32 * weights.well
+            + self.t_slots as f32 * weights.t_slots
+    }
+}
+
+/// Counts the slots a T piece pointing down can be spun into to clear two lines:
+///
+/// ```text
+/// #..    ..#
+/// ...    ...
+/// #.#    #.#
+/// ```
+fn count_t_slots(cell_holder: &CellHolder, heights: &[u32]) -> u32 {
+    let width = cell_holder.get_width();
+    let height = cell_holder.get_height();
+    let is_occupied = |x: usize, y: usize| cell_holder.get_cell_at(x, y) != CellType::None;
+
+    let mut count = 0;
+    for x in 0..width.saturating_sub(2) {
+        // the slot is right on top of the middle column
+        let y = height - heights[x + 1] as usize;
+        if y < 3 {
+            continue;
+        }
+
+        let (top, middle, bottom) = (y - 3, y - 2, y - 1);
+        let is_slot = is_occupied(x, bottom)
+            && !is_occupied(x + 1, bottom)
+            && is_occupied(x + 2, bottom)
+            && (x..x + 3).all(|x| !is_occupied(x, middle))
+            && !is_occupied(x + 1, top)
+            && (is_occupied(x, top) != is_occupied(x + 2, top));
+
+        if is_slot {
+            count += 1;
+        }
+    }
+
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quader_engine::game_settings::BoardSettings;
+
+    /// Creates a board with the bottom rows filled with `layout`, where `#` is an occupied cell.
+    fn create_cell_holder(layout: &[&str]) -> CellHolder {
+        let mut cell_holder = CellHolder::new(&BoardSettings::default());
+        cell_holder.fill_bottom(layout);
+
+        cell_holder
+    }
+
+    #[test]
+    fn features_of_stack() {
+        let features = BoardFeatures::new(&create_cell_holder(&[
+            "#.........",
+            "##.....#..",
+            "##.#..##.#",
+            "###.#####."
+        ]));
+
+        assert_eq!(features.heights, vec![4, 3, 1, 2, 1, 1, 2, 3, 1, 2]);
+        assert_eq!(features.holes, 2);
+        // 1 + 2 + 1 + 1 + 0 + 1 + 1 + 2 + 1
+        assert_eq!(features.bumpiness, 10);
+        assert_eq!(features.max_height, 4);
+        // the columns 2 and 8 are 1 row deep wells
+        assert_eq!(features.well_depth, 1);
+    }
+
+    #[test]
+    fn t_slot_is_found() {
+        let mut layout = [
+            "##........",
+            "#...######",
+            "##.#######",
+            "#########."
+        ];
+        assert_eq!(BoardFeatures::new(&create_cell_holder(&layout)).t_slots, 1);
+
+        // without the overhang the T piece is simply dropped into it
+        layout[0] = "..........";
+        assert_eq!(BoardFeatures::new(&create_cell_holder(&layout)).t_slots, 0);
+    }
+}
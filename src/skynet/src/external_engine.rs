@@ -0,0 +1,226 @@
+/*
+ * Copyright (c) Grigory Alfyorov. Licensed under the MIT License.
+ * See the LICENSE file in the repository root for full licence text.
+ */
+
+use serde::{Deserialize, Serialize};
+use quader_engine::board::Board;
+use quader_engine::cell_holder::BoolArray;
+use quader_engine::piece::PieceType;
+use crate::BotStatus;
+use crate::bot_engine::{BotEngine, BotEngineError, BotMove};
+use crate::bot_process::BotProcess;
+
+/// State of the board sent to the external bot.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExternalBoardState {
+    /// Rows of the field from the top to the bottom, `true` is an occupied cell.
+    pub field: Vec<Vec<bool>>,
+    pub current: PieceType,
+    pub queue: Vec<PieceType>,
+    pub hold: Option<PieceType>,
+    pub b2b: u32,
+    pub combo: u32
+}
+
+impl ExternalBoardState {
+    pub fn new(board: &Board) -> Self {
+        let piece_mgr = board.get_piece_mgr();
+        let scoring_mgr = board.get_scoring_mgr();
+
+        Self {
+            field: piece_mgr.cell_holder.to_bool_array(),
+            current: piece_mgr.get_piece().get_type(),
+            queue: piece_mgr.piece_queue.get_preview().copied().collect(),
+            hold: piece_mgr.get_hold_piece(),
+            b2b: scoring_mgr.b2b,
+            combo: scoring_mgr.combo
+        }
+    }
+}
+
+/// Messages sent to the external bot, one JSON object per line.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ExternalRequest {
+    Reset { state: ExternalBoardState },
+    AddPiece { piece: PieceType },
+    /// The bot answers with the same `id`, so the answers to the outdated requests can be told apart.
+    RequestMove { id: u64, incoming_garbage: u32 },
+    Garbage { state: ExternalBoardState }
+}
+
+/// Messages expected from the external bot, one JSON object per line.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ExternalResponse {
+    /// Answer to `ExternalRequest::RequestMove` of the same `id`.
+    Move {
+        id: u64,
+        #[serde(flatten)]
+        bot_move: BotMove
+    },
+    /// The bot can't find any move for the request `id`.
+    Dead { id: u64 }
+}
+
+/// `BotEngine` running in a separate process and talking over its stdin and stdout.
+///
+/// Every `BotEngine` call is sent as an `ExternalRequest`, and the bot answers every
+/// `ExternalRequest::RequestMove` with an `ExternalResponse`. The answers to the requests made
+/// before the last reset or garbage are dropped, as they are for another board.
+pub struct ExternalBotEngine {
+    process: BotProcess,
+    last_id: u64,
+    /// Id of the request the answer is waited for.
+    requested: Option<u64>
+}
+
+impl ExternalBotEngine {
+    /// Starts `program` with `args` and sends it the state of `board`.
+    pub fn spawn(program: &str, args: &[String], board: &Board) -> Result<Self, BotEngineError> {
+        let mut engine = Self {
+            process: BotProcess::spawn(program, args)?,
+            last_id: 0,
+            requested: None
+        };
+        engine.reset(board);
+
+        Ok(engine)
+    }
+}
+
+impl BotEngine for ExternalBotEngine {
+    fn reset(&mut self, board: &Board) {
+        // the answer to a move requested before the reset is of no use anymore
+        self.process.clear();
+        self.requested = None;
+
+        self.process.send(&ExternalRequest::Reset { state: ExternalBoardState::new(board) });
+    }
+
+    fn add_next_piece(&mut self, piece_type: PieceType) {
+        self.process.send(&ExternalRequest::AddPiece { piece: piece_type });
+    }
+
+    fn request_move(&mut self, _board: &Board, incoming_garbage: u32) {
+        self.last_id += 1;
+        self.requested = Some(self.last_id);
+
+        self.process.send(&ExternalRequest::RequestMove { id: self.last_id, incoming_garbage });
+    }
+
+    fn poll_move(&mut self) -> Result<BotMove, BotStatus> {
+        while let Some(line) = self.process.try_recv() {
+            match serde_json::from_str(&line) {
+                Ok(ExternalResponse::Move { id, bot_move }) if Some(id) == self.requested => {
+                    self.requested = None;
+                    return Ok(bot_move);
+                }
+                Ok(ExternalResponse::Dead { id }) if Some(id) == self.requested => {
+                    self.process.is_dead = true;
+                }
+                // the answer to an outdated request
+                Ok(_) => {}
+                Err(_) => self.process.is_dead = true
+            }
+        }
+
+        Err(if self.process.is_dead { BotStatus::Dead } else { BotStatus::Waiting })
+    }
+
+    fn garbage_received(&mut self, board: &Board) {
+        self.requested = None;
+        self.process.send(&ExternalRequest::Garbage { state: ExternalBoardState::new(board) });
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use quader_engine::game_settings::GameSettings;
+    use quader_engine::replays::MoveAction;
+    use quader_engine::wall_kick_data::WallKickData;
+
+    /// Bot which hard drops every piece.
+    const HARD_DROP_BOT: &str = r#"while read line; do
+        case "$line" in
+            *request_move*)
+                id=$(echo "$line" | sed 's/.*"id":\([0-9]*\).*/\1/')
+                echo '{"type":"move","id":'$id',"hold":false,"actions":["MoveLeft","HardDrop"]}' ;;
+        esac
+    done"#;
+
+    /// Bot which takes its time and moves the first piece to the left and the rest to the right.
+    const SLOW_BOT: &str = r#"while read line; do
+        case "$line" in
+            *request_move*)
+                id=$(echo "$line" | sed 's/.*"id":\([0-9]*\).*/\1/')
+                sleep 0.2
+                if [ "$id" = 1 ]; then action=MoveLeft; else action=MoveRight; fi
+                echo '{"type":"move","id":'$id',"hold":false,"actions":["'$action'","HardDrop"]}' ;;
+        esac
+    done"#;
+
+    fn wait_move(engine: &mut ExternalBotEngine) -> Result<BotMove, BotStatus> {
+        let start = Instant::now();
+
+        loop {
+            match engine.poll_move() {
+                Err(BotStatus::Waiting) if start.elapsed() < Duration::from_secs(5) => {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                res => return res
+            }
+        }
+    }
+
+    #[test]
+    fn external_bot_suggests_moves() {
+        let game_settings = GameSettings::default();
+        let wkd = Arc::new(WallKickData::new(&game_settings.wall_kick_data_mode));
+        let board = Board::new(game_settings, wkd, 1337);
+
+        let args = vec!["-c".to_string(), HARD_DROP_BOT.to_string()];
+        let mut engine = ExternalBotEngine::spawn("sh", &args, &board).unwrap();
+
+        assert!(matches!(engine.poll_move(), Err(BotStatus::Waiting)));
+
+        engine.request_move(&board, 0);
+        let bot_move = wait_move(&mut engine).unwrap();
+        assert_eq!(bot_move, BotMove { hold: false, actions: vec![MoveAction::MoveLeft, MoveAction::HardDrop] });
+    }
+
+    #[test]
+    fn exited_bot_is_dead() {
+        let game_settings = GameSettings::default();
+        let wkd = Arc::new(WallKickData::new(&game_settings.wall_kick_data_mode));
+        let board = Board::new(game_settings, wkd, 1337);
+
+        let mut engine = ExternalBotEngine::spawn("true", &[], &board).unwrap();
+
+        engine.request_move(&board, 0);
+        assert!(matches!(wait_move(&mut engine), Err(BotStatus::Dead)));
+    }
+
+    #[test]
+    fn outdated_answers_are_dropped() {
+        let game_settings = GameSettings::default();
+        let wkd = Arc::new(WallKickData::new(&game_settings.wall_kick_data_mode));
+        let board = Board::new(game_settings, wkd, 1337);
+
+        let args = vec!["-c".to_string(), SLOW_BOT.to_string()];
+        let mut engine = ExternalBotEngine::spawn("sh", &args, &board).unwrap();
+
+        // the garbage arrives while the bot is thinking about the first move
+        engine.request_move(&board, 0);
+        engine.garbage_received(&board);
+        engine.request_move(&board, 0);
+
+        let bot_move = wait_move(&mut engine).unwrap();
+        assert_eq!(bot_move.actions, vec![MoveAction::MoveRight, MoveAction::HardDrop]);
+    }
+}
@@ -4,21 +4,56 @@
  */
 
 mod bot_board;
+mod bot_difficulty;
+pub mod bot_engine;
+#[cfg(not(target_arch = "wasm32"))]
+mod bot_process;
+#[cfg(feature = "cold-clear")]
+mod cold_clear_engine;
+pub mod evaluation;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod external_engine;
+pub mod native_bot;
+mod native_bot_engine;
+pub mod tbp;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod tbp_engine;
+pub mod tbp_frontend;
 
 pub use bot_board::BotBoard;
+pub use bot_difficulty::{BotDifficulty, BotPreset};
+#[cfg(feature = "cold-clear")]
+pub use cold_clear_engine::ColdClearEngine;
+pub use native_bot_engine::NativeBotEngine;
+#[cfg(feature = "cold-clear")]
 use quader_engine::piece::PieceType;
+use crate::bot_engine::BotEngineKind;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub struct BotSettings {
     /// Target Pieces Per Second.
-    pub target_pps: f32
+    pub target_pps: f32,
+    pub engine: BotEngineKind,
+    /// Search limits and mistakes of the bot, see `BotPreset` for the ready-made ones.
+    pub difficulty: BotDifficulty
 }
 
-#[derive(Debug)]
+impl Default for BotSettings {
+    fn default() -> Self {
+        Self {
+            target_pps: 1.0,
+            engine: BotEngineKind::default(),
+            difficulty: BotDifficulty::default()
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum BotStatus {
     Waiting, Dead
 }
 
+#[cfg(feature = "cold-clear")]
 pub fn piece_type_to_piece(piece_type: PieceType) -> libtetris::Piece {
     match piece_type {
         PieceType::I => libtetris::Piece::I,
@@ -0,0 +1,371 @@
+/*
+ * Copyright (c) Grigory Alfyorov. Licensed under the MIT License.
+ * See the LICENSE file in the repository root for full licence text.
+ */
+
+use std::sync::Arc;
+use std::time::Instant;
+use quader_engine::board::Board;
+use quader_engine::game_settings::GameSettings;
+use quader_engine::piece::RotationDirection;
+use quader_engine::piece_mgr::{BoardErrorReason, PieceMgr};
+use quader_engine::replays::{HardDropInfo, MoveAction, MoveResult};
+use quader_engine::scoring::ScoringMgr;
+use quader_engine::wall_kick_data::WallKickData;
+use crate::bot_engine::BotMove;
+use crate::evaluation::{BoardFeatures, Weights};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct NativeBotSettings {
+    /// Number of the best boards kept after every piece.
+    pub beam_width: usize,
+    /// Number of the pieces to look ahead, limited by the preview.
+    pub depth: usize,
+    pub weights: Weights
+}
+
+impl Default for NativeBotSettings {
+    fn default() -> Self {
+        Self {
+            beam_width: 16,
+            depth: 3,
+            weights: Weights::default()
+        }
+    }
+}
+
+/// Limits of the search of a single move. The first piece is always searched in full,
+/// so the bot has a move even if the limits are used up.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct SearchLimits {
+    /// Maximum number of the boards evaluated, 0 means no limit.
+    pub node_budget: u32,
+    /// Maximum time spent in milliseconds, 0 means no limit. Not limited on the web.
+    pub think_time_ms: u32
+}
+
+/// Board state reached during the search.
+#[derive(Clone)]
+struct SearchNode {
+    piece_mgr: PieceMgr,
+    scoring_mgr: ScoringMgr,
+    /// Sum of the rewards of the moves on the way to the node.
+    reward: f32,
+    /// Reward plus the evaluation of the board.
+    value: f32,
+    /// Number of the pieces known to the bot, starting with the current piece of the node.
+    pieces_left: usize,
+    /// The first move on the way to the node, which is the one that is played.
+    first_move: Option<BotMove>
+}
+
+/// Bot written on top of the engine, so it follows every rule of the `GameSettings`.
+///
+/// Every piece is placed in each of the positions found by the engine's move generator,
+/// with and without the hold. The boards are evaluated by their shape and the attack sent
+/// on the way to them, and the best `beam_width` of them are searched further using the next pieces.
+#[derive(Debug, Clone)]
+pub struct NativeBot {
+    pub settings: NativeBotSettings,
+    pub limits: SearchLimits,
+    game_settings: GameSettings,
+    wkd: Arc<WallKickData>
+}
+
+impl NativeBot {
+    pub fn new(settings: NativeBotSettings, game_settings: GameSettings, wkd: Arc<WallKickData>) -> Self {
+        Self {
+            settings,
+            limits: SearchLimits::default(),
+            game_settings,
+            wkd
+        }
+    }
+
+    /// Returns the best move for the current piece of `board`, or `None` if the piece can't be placed
+    /// anywhere without topping out.
+    pub fn find_move(&self, board: &Board) -> Option<BotMove> {
+        // the pieces after the preview are not known to the player
+        let known_pieces = board.piece_mgr.piece_queue.get_preview().count() + 1;
+
+        self.find_move_for(board.get_piece_mgr(), *board.get_scoring_mgr(), known_pieces)
+    }
+
+    /// Same as `find_move()`, but only the first `known_pieces` pieces of `piece_mgr`,
+    /// starting with the current one, are used.
+    pub fn find_move_for(&self, piece_mgr: &PieceMgr, scoring_mgr: ScoringMgr, known_pieces: usize) -> Option<BotMove> {
+        self.search(piece_mgr, scoring_mgr, known_pieces).0
+    }
+
+    /// Returns the best move along with the number of the boards evaluated to find it.
+    fn search(&self, piece_mgr: &PieceMgr, scoring_mgr: ScoringMgr, known_pieces: usize) -> (Option<BotMove>, usize) {
+        let mut beam = vec![SearchNode {
+            piece_mgr: piece_mgr.clone(),
+            scoring_mgr,
+            reward: 0.0,
+            value: 0.0,
+            pieces_left: known_pieces,
+            first_move: None
+        }];
+
+        let started = now();
+        let mut nodes = 0;
+        let mut is_exhausted = false;
+
+        for depth in 0..self.settings.depth.max(1) {
+            let mut children = vec![];
+
+            // the beam is sorted, so the best nodes are expanded before the limits are reached
+            for node in &beam {
+                if depth > 0 && self.is_exhausted(nodes, started) {
+                    is_exhausted = true;
+                    break;
+                }
+
+                let expanded = self.expand(node);
+                nodes += expanded.len();
+                children.extend(expanded);
+            }
+
+            if children.is_empty() {
+                break;
+            }
+
+            children.sort_by(|a, b| b.value.total_cmp(&a.value));
+            children.truncate(self.settings.beam_width.max(1));
+            beam = children;
+
+            if is_exhausted {
+                break;
+            }
+        }
+
+        let best_move = beam.into_iter()
+            .max_by(|a, b| a.value.total_cmp(&b.value))
+            .and_then(|node| node.first_move);
+
+        (best_move, nodes)
+    }
+
+    fn is_exhausted(&self, nodes: usize, started: Option<Instant>) -> bool {
+        let limits = &self.limits;
+
+        (limits.node_budget > 0 && nodes >= limits.node_budget as usize)
+            || (limits.think_time_ms > 0
+                && started.is_some_and(|s| s.elapsed().as_millis() >= limits.think_time_ms as u128))
+    }
+
+    /// Returns the nodes of every placement of the current piece of `node` and of the hold piece.
+    fn expand(&self, node: &SearchNode) -> Vec<SearchNode> {
+        if node.pieces_left == 0 {
+            return vec![];
+        }
+
+        let mut starts = vec![(false, node.piece_mgr.clone(), node.pieces_left - 1)];
+
+        // with the empty hold the piece is taken from the queue, so it must be known too
+        let hold_from_queue = node.piece_mgr.get_hold_piece().is_none();
+        if node.piece_mgr.is_hold_available() && (!hold_from_queue || node.pieces_left > 1) {
+            let mut held = node.piece_mgr.clone();
+            let cur_type = held.get_piece().get_type();
+
+            if let Some(Ok(piece)) = held.try_hold_piece() {
+                // holding the same piece changes nothing
+                if piece.get_type() != cur_type {
+                    let pieces_left = node.pieces_left - if hold_from_queue { 2 } else { 1 };
+                    starts.push((true, held, pieces_left));
+                }
+            }
+        }
+
+        let mut children = vec![];
+
+        for (hold, piece_mgr, pieces_left) in starts {
+            for placement in piece_mgr.find_placements_fast(&self.wkd) {
+                let mut child_mgr = piece_mgr.clone();
+                let Ok(hard_drop_info) = play_actions(&mut child_mgr, &self.wkd, &placement.actions) else {
+                    continue;
+                };
+
+                let mut scoring_mgr = node.scoring_mgr;
+                let reward = self.reward(&mut scoring_mgr, hard_drop_info, &child_mgr);
+
+                let features = BoardFeatures::new(&child_mgr.cell_holder);
+                let reward = node.reward + reward;
+                let value = reward + features.evaluate(&self.settings.weights, self.game_settings.board.height as u32);
+
+                let first_move = node.first_move.clone().or(Some(BotMove { hold, actions: placement.actions }));
+
+                children.push(SearchNode {
+                    piece_mgr: child_mgr,
+                    scoring_mgr,
+                    reward,
+                    value,
+                    pieces_left,
+                    first_move
+                });
+            }
+        }
+
+        children
+    }
+
+    /// Returns the reward of the move which resulted in `hard_drop_info` and updates `scoring_mgr`.
+    fn reward(&self, scoring_mgr: &mut ScoringMgr, hard_drop_info: HardDropInfo, piece_mgr: &PieceMgr) -> f32 {
+        let weights = &self.settings.weights;
+
+        scoring_mgr.hard_drop(&hard_drop_info);
+        let result = MoveResult::without_garbage(
+            scoring_mgr,
+            hard_drop_info,
+            &self.game_settings.attack,
+            &piece_mgr.cell_holder
+        );
+
+        let attack = result.attack.out_damage.max(0) as f32;
+        let mut reward = attack * weights.attack;
+
+        if hard_drop_info.lines_cleared > 0 && attack == 0.0 {
+            reward += hard_drop_info.lines_cleared as f32 * weights.clear_waste;
+        }
+        if scoring_mgr.b2b > 0 {
+            reward += weights.b2b;
+        }
+
+        reward
+    }
+}
+
+/// `Instant::now()` panics on the web, so the think time is not limited there.
+#[cfg(not(target_arch = "wasm32"))]
+fn now() -> Option<Instant> {
+    Some(Instant::now())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn now() -> Option<Instant> {
+    None
+}
+
+/// Plays `actions` on `piece_mgr` up to and including the hard drop.
+pub(crate) fn play_actions(piece_mgr: &mut PieceMgr, wkd: &WallKickData, actions: &[MoveAction]) -> Result<HardDropInfo, BoardErrorReason> {
+    for action in actions {
+        match action {
+            MoveAction::MoveLeft => { piece_mgr.move_left(); }
+            MoveAction::MoveRight => { piece_mgr.move_right(); }
+            MoveAction::RotateCW => { piece_mgr.rotate(wkd, RotationDirection::Clockwise); }
+            MoveAction::RotateCCW => { piece_mgr.rotate(wkd, RotationDirection::CounterClockwise); }
+            MoveAction::RotateDeg180 => { piece_mgr.rotate(wkd, RotationDirection::Deg180); }
+            MoveAction::SoftDrop | MoveAction::GravityDrop => { piece_mgr.soft_drop(); }
+            MoveAction::HardDrop => return piece_mgr.hard_drop(),
+            MoveAction::HoldPiece => {}
+        }
+    }
+
+    Err(BoardErrorReason::CannotApplyPiece)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quader_engine::game_settings::BoardSettings;
+    use quader_engine::piece::PieceType;
+
+    fn create_bot(game_settings: &GameSettings) -> NativeBot {
+        let wkd = Arc::new(WallKickData::new(&game_settings.wall_kick_data_mode));
+
+        NativeBot::new(NativeBotSettings::default(), game_settings.clone(), wkd)
+    }
+
+    /// Plays `pieces` moves of the bot on `board`.
+    fn play(bot: &NativeBot, board: &mut Board, pieces: usize) {
+        for _ in 0..pieces {
+            let bot_move = bot.find_move(board).unwrap();
+            if bot_move.hold {
+                board.try_hold_piece();
+            }
+
+            for action in &bot_move.actions {
+                if let Some(res) = board.exec_action(*action) {
+                    res.unwrap();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bot_keeps_board_clean() {
+        let game_settings = GameSettings::default();
+        let bot = create_bot(&game_settings);
+        let mut board = Board::new(game_settings.clone(), Arc::clone(&bot.wkd), 1337);
+
+        play(&bot, &mut board, 25);
+
+        let features = BoardFeatures::new(&board.piece_mgr.cell_holder);
+        assert!(features.holes <= 2, "{:?}", features);
+        assert!(features.max_height <= 8, "{:?}", features);
+        assert!(board.board_stats.lines > 0);
+    }
+
+    #[test]
+    fn bot_plays_on_any_board_size() {
+        let game_settings = GameSettings {
+            board: BoardSettings { width: 6, height: 12 },
+            ..Default::default()
+        };
+        let bot = create_bot(&game_settings);
+        let mut board = Board::new(game_settings, Arc::clone(&bot.wkd), 42);
+
+        play(&bot, &mut board, 12);
+        assert!(!board.is_dead);
+    }
+
+    #[test]
+    fn node_budget_limits_search() {
+        let game_settings = GameSettings::default();
+        let mut bot = create_bot(&game_settings);
+        let board = Board::new(game_settings, Arc::clone(&bot.wkd), 1337);
+        let search = |bot: &NativeBot| bot.search(board.get_piece_mgr(), *board.get_scoring_mgr(), 6);
+
+        let (best_move, first_piece_nodes) = {
+            let mut bot = bot.clone();
+            bot.settings.depth = 1;
+            search(&bot)
+        };
+        assert!(best_move.is_some());
+
+        let (best_move, unlimited_nodes) = search(&bot);
+        assert!(best_move.is_some());
+        assert!(unlimited_nodes > first_piece_nodes);
+
+        // with the tiny budget only the first piece is searched, but there is a move anyway
+        bot.limits.node_budget = 1;
+        let (best_move, nodes) = search(&bot);
+        assert!(best_move.is_some());
+        assert_eq!(nodes, first_piece_nodes);
+
+        bot.limits = SearchLimits { node_budget: 0, think_time_ms: 1 };
+        assert!(bot.find_move(&board).is_some());
+    }
+
+    #[test]
+    fn bot_takes_t_spin_double() {
+        let game_settings = GameSettings::default();
+        let bot = create_bot(&game_settings);
+        // the first seed which starts with a T piece
+        let mut board = (0..)
+            .map(|seed| Board::new(game_settings.clone(), Arc::clone(&bot.wkd), seed))
+            .find(|board| board.piece_mgr.get_piece().get_type() == PieceType::T)
+            .unwrap();
+
+        board.piece_mgr.cell_holder.fill_bottom(&[
+            "##........",
+            "#...######",
+            "##.#######"
+        ]);
+        board.piece_mgr.update_nearest_y();
+
+        play(&bot, &mut board, 1);
+        assert_eq!(board.board_stats.tspin_doubles, 1);
+    }
+}
@@ -0,0 +1,44 @@
+/*
+ * Copyright (c) Grigory Alfyorov. Licensed under the MIT License.
+ * See the LICENSE file in the repository root for full licence text.
+ */
+
+use quader_engine::board::Board;
+use quader_engine::piece::PieceType;
+use crate::BotStatus;
+use crate::bot_engine::{BotEngine, BotMove};
+use crate::native_bot::NativeBot;
+
+/// `BotEngine` of the `NativeBot`. The bot looks at the board every time a move is requested,
+/// so it doesn't keep any state of its own.
+pub struct NativeBotEngine {
+    pub bot: NativeBot,
+    next_move: Option<Result<BotMove, BotStatus>>
+}
+
+impl NativeBotEngine {
+    pub fn new(bot: NativeBot) -> Self {
+        Self {
+            bot,
+            next_move: None
+        }
+    }
+}
+
+impl BotEngine for NativeBotEngine {
+    fn reset(&mut self, _board: &Board) {
+        self.next_move = None;
+    }
+
+    fn add_next_piece(&mut self, _piece_type: PieceType) {}
+
+    fn request_move(&mut self, board: &Board, _incoming_garbage: u32) {
+        self.next_move = Some(self.bot.find_move(board).ok_or(BotStatus::Dead));
+    }
+
+    fn poll_move(&mut self) -> Result<BotMove, BotStatus> {
+        self.next_move.take().unwrap_or(Err(BotStatus::Waiting))
+    }
+
+    fn garbage_received(&mut self, _board: &Board) {}
+}
@@ -0,0 +1,351 @@
+/*
+ * Copyright (c) Grigory Alfyorov. Licensed under the MIT License.
+ * See the LICENSE file in the repository root for full licence text.
+ */
+
+//! Messages of the [Tetris Bot Protocol](https://github.com/tetris-bot-protocol/tbp-spec)
+//! and conversions between its board and piece locations and the engine ones.
+//!
+//! TBP counts the rows from the bottom of the board and places the pieces
+//! by their SRS rotation center, while the engine counts the rows from the top.
+
+use serde::{Deserialize, Serialize};
+use quader_engine::cell_holder::{CellHolder, CellType};
+use quader_engine::move_gen::Placement;
+use quader_engine::piece::{Piece, PieceType, RotationState};
+use quader_engine::piece_mgr::PieceMgr;
+use quader_engine::primitives::Point;
+use quader_engine::replays::MoveAction;
+use quader_engine::scoring::TSpinStatus;
+use quader_engine::utils::adjust_positions_clone;
+use quader_engine::wall_kick_data::WallKickData;
+
+/// Messages sent by the frontend (the game) to the bot.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FrontendMessage {
+    Rules,
+    Start(Start),
+    Stop,
+    Suggest,
+    Play {
+        #[serde(rename = "move")]
+        mv: TbpMove
+    },
+    NewPiece {
+        piece: PieceType
+    },
+    Quit
+}
+
+/// Messages sent by the bot to the frontend.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BotMessage {
+    Error {
+        reason: String
+    },
+    Ready,
+    Info {
+        name: String,
+        version: String,
+        author: String,
+        #[serde(default)]
+        features: Vec<String>
+    },
+    /// Moves the bot wants to play, the best one first.
+    Suggestion {
+        moves: Vec<TbpMove>
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Start {
+    pub hold: Option<PieceType>,
+    /// The first piece is the one to be placed.
+    pub queue: Vec<PieceType>,
+    pub combo: u32,
+    pub back_to_back: bool,
+    /// Rows from the bottom to the top, `None` is an empty cell.
+    pub board: Vec<Vec<Option<char>>>
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Orientation {
+    North, East, South, West
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Spin {
+    None, Mini, Full
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PieceLocation {
+    #[serde(rename = "type")]
+    pub piece_type: PieceType,
+    pub orientation: Orientation,
+    pub x: i32,
+    pub y: i32
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TbpMove {
+    pub location: PieceLocation,
+    pub spin: Spin
+}
+
+impl From<TSpinStatus> for Spin {
+    fn from(value: TSpinStatus) -> Self {
+        match value {
+            TSpinStatus::None => Spin::None,
+            TSpinStatus::Mini => Spin::Mini,
+            TSpinStatus::Full => Spin::Full
+        }
+    }
+}
+
+impl From<RotationState> for Orientation {
+    fn from(value: RotationState) -> Self {
+        match value {
+            RotationState::Initial => Orientation::North,
+            RotationState::Clockwise => Orientation::East,
+            RotationState::Deg180 => Orientation::South,
+            RotationState::CounterClockwise => Orientation::West
+        }
+    }
+}
+
+/// Cells of the north orientation of the pieces relative to their rotation center, Y up.
+fn north_cells(piece_type: PieceType) -> [(i32, i32); 4] {
+    match piece_type {
+        PieceType::I => [(-1, 0), (0, 0), (1, 0), (2, 0)],
+        PieceType::O => [(0, 0), (1, 0), (0, 1), (1, 1)],
+        PieceType::T => [(-1, 0), (0, 0), (1, 0), (0, 1)],
+        PieceType::L => [(-1, 0), (0, 0), (1, 0), (1, 1)],
+        PieceType::J => [(-1, 0), (0, 0), (1, 0), (-1, 1)],
+        PieceType::S => [(-1, 0), (0, 0), (0, 1), (1, 1)],
+        PieceType::Z => [(-1, 1), (0, 1), (0, 0), (1, 0)],
+        PieceType::Pixel => [(0, 0); 4]
+    }
+}
+
+/// Returns the cells of `location` in the engine coordinates, sorted.
+pub fn location_cells(location: &PieceLocation, full_height: usize) -> Vec<(i32, i32)> {
+    let mut cells: Vec<(i32, i32)> = north_cells(location.piece_type)
+        .iter()
+        .map(|&(x, y)| match location.orientation {
+            Orientation::North => (x, y),
+            Orientation::East => (y, -x),
+            Orientation::South => (-x, -y),
+            Orientation::West => (-y, x)
+        })
+        .map(|(x, y)| (location.x + x, full_height as i32 - 1 - (location.y + y)))
+        .collect();
+    cells.sort_unstable();
+
+    cells
+}
+
+/// Returns the cells `piece` occupies, sorted.
+pub fn piece_cells(piece: &Piece) -> Vec<(i32, i32)> {
+    let mut cells: Vec<(i32, i32)> = adjust_positions_clone(
+        piece.get_current_pos(),
+        Point::new(piece.get_x() as i32, piece.get_y() as i32)
+    )
+        .iter()
+        .map(|p| (p.x, p.y))
+        .collect();
+    cells.sort_unstable();
+
+    cells
+}
+
+/// Returns the location of `piece` occupying the same cells.
+pub fn to_location(piece: &Piece, full_height: usize) -> PieceLocation {
+    let cells = piece_cells(piece);
+    let preferred = Orientation::from(piece.current_rotation);
+
+    // the symmetric pieces occupy the same cells in several orientations
+    let orientations = [preferred, Orientation::North, Orientation::East, Orientation::South, Orientation::West];
+
+    for orientation in orientations {
+        let mut location = PieceLocation { piece_type: piece.get_type(), orientation, x: 0, y: 0 };
+        let offset = location_cells(&location, full_height);
+
+        location.x = cells[0].0 - offset[0].0;
+        location.y = offset[0].1 - cells[0].1;
+
+        if location_cells(&location, full_height) == cells {
+            return location;
+        }
+    }
+
+    unreachable!("Piece {:?} has no matching location", piece.get_type())
+}
+
+/// Converts the TBP board into `cell_holder`. The rows above the board are left empty.
+pub fn apply_board(cell_holder: &mut CellHolder, board: &[Vec<Option<char>>]) {
+    let height = cell_holder.get_height();
+
+    for y in 0..height {
+        let row = board.get(height - 1 - y);
+
+        for x in 0..cell_holder.get_width() {
+            let cell = row.and_then(|row| row.get(x)).copied().flatten();
+            cell_holder.set_cell_at(x, y, to_cell_type(cell));
+        }
+    }
+}
+
+/// Returns the board of `cell_holder` in the TBP format.
+pub fn to_board(cell_holder: &CellHolder) -> Vec<Vec<Option<char>>> {
+    let height = cell_holder.get_height();
+
+    (0..height)
+        .rev()
+        .map(|y| {
+            (0..cell_holder.get_width())
+                .map(|x| to_cell_char(cell_holder.get_cell_at(x, y)))
+                .collect()
+        })
+        .collect()
+}
+
+fn to_cell_type(cell: Option<char>) -> CellType {
+    match cell {
+        None => CellType::None,
+        Some('I') => CellType::I,
+        Some('O') => CellType::O,
+        Some('T') => CellType::T,
+        Some('L') => CellType::L,
+        Some('J') => CellType::J,
+        Some('S') => CellType::S,
+        Some('Z') => CellType::Z,
+        Some(_) => CellType::Garbage
+    }
+}
+
+fn to_cell_char(cell: CellType) -> Option<char> {
+    match cell {
+        CellType::None | CellType::Ghost => None,
+        CellType::I => Some('I'),
+        CellType::O => Some('O'),
+        CellType::T => Some('T'),
+        CellType::L => Some('L'),
+        CellType::J => Some('J'),
+        CellType::S => Some('S'),
+        CellType::Z => Some('Z'),
+        CellType::Garbage | CellType::Solid => Some('G')
+    }
+}
+
+/// Finds the placement of the current piece of `piece_mgr` matching `mv`.
+/// Placements with the same cells but another spin are used if there is no exact match.
+pub fn find_placement(piece_mgr: &PieceMgr, wkd: &WallKickData, mv: &TbpMove) -> Option<Placement> {
+    let full_height = piece_mgr.cell_holder.get_height();
+    let cells = location_cells(&mv.location, full_height);
+
+    let mut matching: Vec<Placement> = piece_mgr.find_placements(wkd)
+        .into_iter()
+        .filter(|p| piece_cells(&p.piece) == cells)
+        .collect();
+
+    let exact = matching.iter().position(|p| Spin::from(p.tspin_status) == mv.spin);
+    match exact {
+        Some(index) => Some(matching.swap_remove(index)),
+        None => matching.into_iter().next()
+    }
+}
+
+/// Returns the actions which play `mv` on `piece_mgr`, holding the current piece first
+/// if `mv` places another piece. `None` if the piece can't be put there.
+pub fn to_actions(piece_mgr: &PieceMgr, wkd: &WallKickData, mv: &TbpMove) -> Option<(bool, Vec<MoveAction>)> {
+    if piece_mgr.get_piece().get_type() == mv.location.piece_type {
+        return find_placement(piece_mgr, wkd, mv).map(|p| (false, p.actions));
+    }
+
+    let mut held = piece_mgr.clone();
+    match held.try_hold_piece() {
+        Some(Ok(piece)) if piece.get_type() == mv.location.piece_type => {
+            find_placement(&held, wkd, mv).map(|p| (true, p.actions))
+        }
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quader_engine::game_settings::GameSettings;
+
+    #[test]
+    fn locations_match_engine_pieces() {
+        let game_settings = GameSettings::default();
+        let wkd = WallKickData::new(&game_settings.wall_kick_data_mode);
+        let full_height = game_settings.board.full_height();
+
+        for piece_type in [PieceType::I, PieceType::O, PieceType::T, PieceType::L, PieceType::J, PieceType::S, PieceType::Z] {
+            let mut piece_mgr = PieceMgr::new(&game_settings, 1337);
+            piece_mgr.try_create_piece(piece_type).unwrap();
+
+            for placement in piece_mgr.find_placements(&wkd) {
+                let location = to_location(&placement.piece, full_height);
+                assert_eq!(location_cells(&location, full_height), piece_cells(&placement.piece));
+
+                let mv = TbpMove { location, spin: placement.tspin_status.into() };
+                let (hold, actions) = to_actions(&piece_mgr, &wkd, &mv).unwrap();
+                assert!(!hold);
+                assert_eq!(actions, placement.actions);
+            }
+        }
+    }
+
+    #[test]
+    fn flat_t_piece_location() {
+        // T piece pointing up and lying on the floor with its center at the column 4
+        let location = PieceLocation { piece_type: PieceType::T, orientation: Orientation::North, x: 4, y: 0 };
+
+        assert_eq!(location_cells(&location, 40), vec![(3, 39), (4, 38), (4, 39), (5, 39)]);
+    }
+
+    #[test]
+    fn board_round_trip() {
+        let game_settings = GameSettings::default();
+        let mut cell_holder = CellHolder::new(&game_settings.board);
+
+        let mut board = vec![vec![None; 10]; 40];
+        board[0] = vec![Some('G'); 10];
+        board[0][3] = None;
+        board[1][0] = Some('T');
+
+        apply_board(&mut cell_holder, &board);
+        assert_eq!(cell_holder.get_cell_at(0, 39), CellType::Garbage);
+        assert_eq!(cell_holder.get_cell_at(3, 39), CellType::None);
+        assert_eq!(cell_holder.get_cell_at(0, 38), CellType::T);
+
+        assert_eq!(to_board(&cell_holder), board);
+    }
+
+    #[test]
+    fn messages_format() {
+        let message: FrontendMessage = serde_json::from_str(
+            r#"{"type":"play","move":{"location":{"type":"T","orientation":"east","x":1,"y":2},"spin":"full"}}"#
+        ).unwrap();
+        assert_eq!(message, FrontendMessage::Play {
+            mv: TbpMove {
+                location: PieceLocation { piece_type: PieceType::T, orientation: Orientation::East, x: 1, y: 2 },
+                spin: Spin::Full
+            }
+        });
+
+        let message: FrontendMessage = serde_json::from_str(
+            r#"{"type":"start","hold":null,"queue":["S","Z"],"combo":0,"back_to_back":true,"board":[]}"#
+        ).unwrap();
+        assert!(matches!(message, FrontendMessage::Start(Start { back_to_back: true, .. })));
+
+        assert_eq!(serde_json::to_string(&BotMessage::Ready).unwrap(), r#"{"type":"ready"}"#);
+    }
+}
@@ -0,0 +1,272 @@
+/*
+ * Copyright (c) Grigory Alfyorov. Licensed under the MIT License.
+ * See the LICENSE file in the repository root for full licence text.
+ */
+
+use std::sync::Arc;
+use quader_engine::board::Board;
+use quader_engine::piece::PieceType;
+use quader_engine::piece_mgr::PieceMgr;
+use quader_engine::wall_kick_data::WallKickData;
+use crate::BotStatus;
+use crate::bot_engine::{BotEngine, BotEngineError, BotMove};
+use crate::bot_process::BotProcess;
+use crate::tbp::{self, BotMessage, FrontendMessage, Start};
+
+/// `BotEngine` of a third-party bot speaking the Tetris Bot Protocol over its stdin and stdout.
+///
+/// TBP doesn't tell the bot about the garbage, so the game is restarted from the current
+/// state of the board every time garbage is received.
+pub struct TbpBotEngine {
+    process: BotProcess,
+    wkd: Arc<WallKickData>,
+    /// The board the move was requested for, used to turn the suggested location into actions.
+    requested: Option<Box<PieceMgr>>,
+    /// The game is restarted with the next move request.
+    restart_pending: bool,
+    /// The rules are sent once the bot has introduced itself with `info`.
+    rules_pending: bool,
+    /// The bot has replied to the rules with `ready`, so the game can be started.
+    is_ready: bool,
+    /// Start of the game, sent once the bot is ready.
+    pending_start: Option<Start>,
+    name: Option<String>
+}
+
+impl TbpBotEngine {
+    /// Starts `program` with `args`. The rules are sent once the bot has sent its info,
+    /// and the game is started from the state of `board` once the bot replies that it is ready.
+    pub fn spawn(program: &str, args: &[String], board: &Board, wkd: Arc<WallKickData>) -> Result<Self, BotEngineError> {
+        let mut engine = Self {
+            process: BotProcess::spawn(program, args)?,
+            wkd,
+            requested: None,
+            restart_pending: false,
+            rules_pending: true,
+            is_ready: false,
+            pending_start: None,
+            name: None
+        };
+        engine.start(board);
+
+        Ok(engine)
+    }
+
+    /// Returns the name of the bot once it has sent its info.
+    pub fn get_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    fn start(&mut self, board: &Board) {
+        let piece_mgr = board.get_piece_mgr();
+        let scoring_mgr = board.get_scoring_mgr();
+
+        let queue = std::iter::once(piece_mgr.get_piece().get_type())
+            .chain(piece_mgr.piece_queue.get_preview().copied())
+            .collect();
+
+        let start = Start {
+            hold: piece_mgr.get_hold_piece(),
+            queue,
+            combo: scoring_mgr.combo,
+            back_to_back: scoring_mgr.b2b > 0,
+            board: tbp::to_board(&piece_mgr.cell_holder)
+        };
+
+        if self.is_ready {
+            self.process.send(&FrontendMessage::Start(start));
+        } else {
+            self.pending_start = Some(start);
+        }
+    }
+
+    /// Starts the game and asks for the requested move, if any, once the bot is ready.
+    fn on_ready(&mut self) {
+        if self.is_ready {
+            return;
+        }
+        self.is_ready = true;
+
+        if let Some(start) = self.pending_start.take() {
+            self.process.send(&FrontendMessage::Start(start));
+        }
+        if self.requested.is_some() {
+            self.process.send(&FrontendMessage::Suggest);
+        }
+    }
+
+    /// Turns the first playable suggestion into a move and tells the bot which one was played.
+    fn accept_suggestion(&mut self, moves: &[tbp::TbpMove]) -> Result<BotMove, BotStatus> {
+        let piece_mgr = self.requested.take().ok_or(BotStatus::Waiting)?;
+
+        for mv in moves {
+            if let Some((hold, actions)) = tbp::to_actions(&piece_mgr, &self.wkd, mv) {
+                self.process.send(&FrontendMessage::Play { mv: *mv });
+                return Ok(BotMove { hold, actions });
+            }
+        }
+
+        Err(BotStatus::Dead)
+    }
+}
+
+impl BotEngine for TbpBotEngine {
+    fn reset(&mut self, board: &Board) {
+        // before the bot is ready there is no game to stop, and its reply to the rules must be kept
+        if self.is_ready {
+            self.process.send(&FrontendMessage::Stop);
+            self.process.clear();
+        }
+        self.requested = None;
+        self.restart_pending = false;
+
+        self.start(board);
+    }
+
+    fn add_next_piece(&mut self, piece_type: PieceType) {
+        // the piece is sent along with the rest of the queue on the restart
+        if self.restart_pending {
+            return;
+        }
+
+        match &mut self.pending_start {
+            Some(start) => start.queue.push(piece_type),
+            None => self.process.send(&FrontendMessage::NewPiece { piece: piece_type })
+        }
+    }
+
+    fn request_move(&mut self, board: &Board, _incoming_garbage: u32) {
+        if self.restart_pending {
+            self.reset(board);
+        }
+
+        self.requested = Some(Box::new(board.get_piece_mgr().clone()));
+        if self.is_ready {
+            self.process.send(&FrontendMessage::Suggest);
+        }
+    }
+
+    fn poll_move(&mut self) -> Result<BotMove, BotStatus> {
+        while let Some(line) = self.process.try_recv() {
+            match serde_json::from_str::<BotMessage>(&line) {
+                Ok(BotMessage::Suggestion { moves }) => return self.accept_suggestion(&moves),
+                Ok(BotMessage::Info { name, .. }) => {
+                    self.name = Some(name);
+                    if self.rules_pending {
+                        self.rules_pending = false;
+                        self.process.send(&FrontendMessage::Rules);
+                    }
+                }
+                Ok(BotMessage::Ready) => self.on_ready(),
+                // the bot has rejected the rules or failed
+                Ok(BotMessage::Error { .. }) => self.process.is_dead = true,
+                // unknown messages are ignored as the protocol requires
+                Err(_) => {}
+            }
+        }
+
+        Err(if self.process.is_dead { BotStatus::Dead } else { BotStatus::Waiting })
+    }
+
+    fn garbage_received(&mut self, _board: &Board) {
+        self.restart_pending = true;
+    }
+}
+
+impl Drop for TbpBotEngine {
+    fn drop(&mut self) {
+        self.process.send(&FrontendMessage::Quit);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use quader_engine::game_settings::GameSettings;
+    use quader_engine::replays::MoveAction;
+    use crate::tbp::{Orientation, PieceLocation, Spin, TbpMove};
+
+    /// Bot which suggests to drop `piece` pointing up at the column 4.
+    /// It fails if anything is sent to it before it has sent its info or replied to the rules.
+    fn create_script(piece: PieceType) -> String {
+        let suggestion = serde_json::to_string(&BotMessage::Suggestion {
+            moves: vec![TbpMove {
+                location: PieceLocation { piece_type: piece, orientation: Orientation::North, x: 4, y: 0 },
+                spin: Spin::None
+            }]
+        }).unwrap();
+
+        format!(r#"if read -t 0.2 early; then
+                echo '{{"type":"error","reason":"unexpected message"}}'
+            fi
+            echo '{{"type":"info","name":"Script","version":"1","author":"","features":[]}}'
+            while read line; do
+                case "$line" in
+                    *rules*)
+                        if read -t 0.2 early; then
+                            echo '{{"type":"error","reason":"unexpected message"}}'
+                        else
+                            echo '{{"type":"ready"}}'
+                        fi ;;
+                    *suggest*) echo '{suggestion}' ;;
+                esac
+            done"#)
+    }
+
+    fn wait_for_move(engine: &mut TbpBotEngine) -> Result<BotMove, BotStatus> {
+        let start = Instant::now();
+
+        loop {
+            match engine.poll_move() {
+                Err(BotStatus::Waiting) if start.elapsed() < Duration::from_secs(5) => {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                res => return res
+            }
+        }
+    }
+
+    #[test]
+    fn tbp_bot_moves_are_played() {
+        let game_settings = GameSettings::default();
+        let wkd = Arc::new(WallKickData::new(&game_settings.wall_kick_data_mode));
+        let mut board = Board::new(game_settings, Arc::clone(&wkd), 1337);
+        let piece_type = board.get_piece_mgr().get_piece().get_type();
+
+        let args = vec!["-c".to_string(), create_script(piece_type)];
+        let mut engine = TbpBotEngine::spawn("bash", &args, &board, wkd).unwrap();
+        engine.request_move(&board, 0);
+
+        let bot_move = wait_for_move(&mut engine).unwrap();
+
+        assert_eq!(engine.get_name(), Some("Script"));
+        assert!(!bot_move.hold);
+        assert_eq!(bot_move.actions.last(), Some(&MoveAction::HardDrop));
+
+        for action in bot_move.actions {
+            board.exec_action(action);
+        }
+        // every piece pointing up has a cell at the column 4 of the bottom row
+        let bottom = board.get_cell_holder().get_height() - 1;
+        assert_ne!(board.get_cell_holder().get_cell_at(4, bottom), quader_engine::cell_holder::CellType::None);
+    }
+
+    #[test]
+    fn tbp_bot_rejecting_rules_is_dead() {
+        let game_settings = GameSettings::default();
+        let wkd = Arc::new(WallKickData::new(&game_settings.wall_kick_data_mode));
+        let board = Board::new(game_settings, Arc::clone(&wkd), 1337);
+
+        let script = r#"echo '{"type":"info","name":"Script","version":"1","author":"","features":[]}'
+            read line
+            echo '{"type":"error","reason":"unsupported_rules"}'
+            while read line; do :; done"#;
+        let args = vec!["-c".to_string(), script.to_string()];
+        let mut engine = TbpBotEngine::spawn("sh", &args, &board, wkd).unwrap();
+        engine.request_move(&board, 0);
+
+        assert_eq!(wait_for_move(&mut engine), Err(BotStatus::Dead));
+    }
+}
@@ -0,0 +1,253 @@
+/*
+ * Copyright (c) Grigory Alfyorov. Licensed under the MIT License.
+ * See the LICENSE file in the repository root for full licence text.
+ */
+
+use std::io::{BufRead, Write};
+use std::sync::Arc;
+use quader_engine::game_settings::GameSettings;
+use quader_engine::piece_mgr::PieceMgr;
+use quader_engine::replays::MoveAction;
+use quader_engine::scoring::ScoringMgr;
+use quader_engine::wall_kick_data::WallKickData;
+use crate::native_bot::{play_actions, NativeBot, NativeBotSettings};
+use crate::tbp::{self, BotMessage, FrontendMessage, Start, TbpMove};
+
+/// Game started by the frontend.
+struct TbpGame {
+    piece_mgr: Box<PieceMgr>,
+    scoring_mgr: ScoringMgr,
+    /// Number of the pieces told by the frontend, starting with the current piece.
+    /// The engine generates the pieces after them, but the bot never looks at those.
+    known_pieces: usize
+}
+
+/// Exposes the `NativeBot` to the frontends speaking the Tetris Bot Protocol.
+pub struct TbpFrontend {
+    bot: NativeBot,
+    game_settings: GameSettings,
+    wkd: Arc<WallKickData>,
+    game: Option<TbpGame>
+}
+
+impl TbpFrontend {
+    pub fn new(bot_settings: NativeBotSettings) -> Self {
+        let game_settings = GameSettings::default();
+        let wkd = Arc::new(WallKickData::new(&game_settings.wall_kick_data_mode));
+
+        Self {
+            bot: NativeBot::new(bot_settings, game_settings.clone(), Arc::clone(&wkd)),
+            game_settings,
+            wkd,
+            game: None
+        }
+    }
+
+    pub fn info() -> BotMessage {
+        BotMessage::Info {
+            name: "Quader".to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            author: "Grigory Alfyorov".to_string(),
+            features: vec![]
+        }
+    }
+
+    /// Handles `message` and returns the answer to it, if there is any.
+    pub fn handle(&mut self, message: FrontendMessage) -> Option<BotMessage> {
+        match message {
+            FrontendMessage::Rules => Some(BotMessage::Ready),
+            FrontendMessage::Start(start) => {
+                self.game = Some(self.start(start));
+                None
+            }
+            FrontendMessage::Stop | FrontendMessage::Quit => {
+                self.game = None;
+                None
+            }
+            FrontendMessage::Suggest => Some(BotMessage::Suggestion { moves: self.suggest() }),
+            FrontendMessage::Play { mv } => {
+                self.play(&mv);
+                None
+            }
+            FrontendMessage::NewPiece { piece } => {
+                if let Some(game) = &mut self.game {
+                    if game.known_pieces == 0 {
+                        let _ = game.piece_mgr.try_create_piece(piece);
+                    } else {
+                        game.piece_mgr.piece_queue.queue.insert(game.known_pieces - 1, piece);
+                    }
+                    game.known_pieces += 1;
+                }
+                None
+            }
+        }
+    }
+
+    fn start(&self, start: Start) -> TbpGame {
+        let mut piece_mgr = Box::new(PieceMgr::new(&self.game_settings, 0));
+        tbp::apply_board(&mut piece_mgr.cell_holder, &start.board);
+        piece_mgr.set_hold_piece(start.hold);
+
+        if let Some(current) = start.queue.first() {
+            let _ = piece_mgr.try_create_piece(*current);
+        }
+        for (i, piece_type) in start.queue.iter().skip(1).enumerate() {
+            piece_mgr.piece_queue.queue.insert(i, *piece_type);
+        }
+
+        let scoring_mgr = ScoringMgr {
+            combo: start.combo,
+            b2b: start.back_to_back as u32,
+            ..Default::default()
+        };
+
+        TbpGame {
+            piece_mgr,
+            scoring_mgr,
+            known_pieces: start.queue.len()
+        }
+    }
+
+    fn suggest(&self) -> Vec<TbpMove> {
+        let Some(game) = &self.game else { return vec![] };
+        if game.known_pieces == 0 {
+            return vec![];
+        }
+
+        let Some(bot_move) = self.bot.find_move_for(&game.piece_mgr, game.scoring_mgr, game.known_pieces) else {
+            return vec![];
+        };
+
+        // play the move up to the hard drop to find out where the piece lands
+        let mut piece_mgr = game.piece_mgr.clone();
+        if bot_move.hold {
+            piece_mgr.try_hold_piece();
+        }
+        let actions = bot_move.actions.strip_suffix(&[MoveAction::HardDrop]).unwrap_or(&bot_move.actions);
+        let _ = play_actions(&mut piece_mgr, &self.wkd, actions);
+
+        let mut piece = *piece_mgr.get_piece();
+        piece.set_y(piece_mgr.find_nearest_y());
+        let Ok(hard_drop_info) = piece_mgr.hard_drop() else { return vec![] };
+
+        vec![TbpMove {
+            location: tbp::to_location(&piece, self.game_settings.board.full_height()),
+            spin: hard_drop_info.tspin_status.into()
+        }]
+    }
+
+    fn play(&mut self, mv: &TbpMove) {
+        let Some(game) = &mut self.game else { return };
+        let Some((hold, actions)) = tbp::to_actions(&game.piece_mgr, &self.wkd, mv) else { return };
+
+        if hold {
+            // with the empty hold the next piece is taken from the queue
+            if game.piece_mgr.get_hold_piece().is_none() {
+                game.known_pieces = game.known_pieces.saturating_sub(1);
+            }
+            game.piece_mgr.try_hold_piece();
+        }
+
+        if let Ok(hard_drop_info) = play_actions(&mut game.piece_mgr, &self.wkd, &actions) {
+            game.scoring_mgr.hard_drop(&hard_drop_info);
+            game.known_pieces = game.known_pieces.saturating_sub(1);
+        }
+    }
+}
+
+/// Runs the bot reading the frontend messages from `input` and writing the answers to `output`
+/// until the frontend quits or closes `input`.
+pub fn run_tbp_frontend(bot_settings: NativeBotSettings, input: impl BufRead, mut output: impl Write) -> std::io::Result<()> {
+    let mut frontend = TbpFrontend::new(bot_settings);
+
+    let mut send = |message: &BotMessage| -> std::io::Result<()> {
+        writeln!(output, "{}", serde_json::to_string(message).unwrap())?;
+        output.flush()
+    };
+
+    send(&TbpFrontend::info())?;
+
+    for line in input.lines() {
+        // unknown messages are ignored as the protocol requires
+        let Ok(message) = serde_json::from_str::<FrontendMessage>(&line?) else { continue };
+        let is_quit = message == FrontendMessage::Quit;
+
+        if let Some(answer) = frontend.handle(message) {
+            send(&answer)?;
+        }
+        if is_quit {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quader_engine::piece::PieceType;
+
+    fn start_message(queue: Vec<PieceType>) -> FrontendMessage {
+        FrontendMessage::Start(Start {
+            hold: None,
+            queue,
+            combo: 0,
+            back_to_back: false,
+            board: vec![vec![None; 10]; 40]
+        })
+    }
+
+    #[test]
+    fn frontend_suggests_and_plays() {
+        let mut frontend = TbpFrontend::new(NativeBotSettings::default());
+        assert_eq!(frontend.handle(FrontendMessage::Rules), Some(BotMessage::Ready));
+
+        frontend.handle(start_message(vec![PieceType::O, PieceType::I, PieceType::T]));
+
+        // the bot plays until it runs out of the known pieces, one of them may be left in the hold
+        let mut placed = 0;
+        while let Some(BotMessage::Suggestion { moves }) = frontend.handle(FrontendMessage::Suggest) {
+            let Some(mv) = moves.first() else { break };
+
+            frontend.handle(FrontendMessage::Play { mv: *mv });
+            placed += 1;
+        }
+        assert!(placed >= 2);
+
+        let game = frontend.game.as_ref().unwrap();
+        assert_eq!(game.known_pieces, 0);
+        assert_eq!(game.piece_mgr.cell_holder.get_occupied_cell_count(), placed * 4);
+
+        frontend.handle(FrontendMessage::NewPiece { piece: PieceType::L });
+        let Some(BotMessage::Suggestion { moves }) = frontend.handle(FrontendMessage::Suggest) else {
+            panic!("No suggestion");
+        };
+        assert_eq!(moves.len(), 1);
+    }
+
+    #[test]
+    fn frontend_session() {
+        let input = [
+            r#"{"type":"rules"}"#,
+            r#"{"type":"unknown_message"}"#,
+            r#"{"type":"start","hold":null,"queue":["T","S"],"combo":0,"back_to_back":false,"board":[]}"#,
+            r#"{"type":"suggest"}"#,
+            r#"{"type":"quit"}"#,
+            r#"{"type":"suggest"}"#
+        ].join("\n");
+        let mut output = vec![];
+
+        run_tbp_frontend(NativeBotSettings::default(), input.as_bytes(), &mut output).unwrap();
+
+        let answers: Vec<BotMessage> = String::from_utf8(output).unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(answers.len(), 3);
+        assert!(matches!(answers[0], BotMessage::Info { .. }));
+        assert_eq!(answers[1], BotMessage::Ready);
+        assert!(matches!(&answers[2], BotMessage::Suggestion { moves } if moves[0].location.piece_type == PieceType::T));
+    }
+}